@@ -0,0 +1,166 @@
+//! Load balance of the worker pool on a corpus whose cost per byte is uneven.
+//!
+//! Run with `cargo bench --bench balance`. The first eighth of the generated corpus is made of
+//! one-letter words, which take several times longer to count per byte than the sentences of
+//! the rest; both parts share a small vocabulary, so no schedule pays for a bigger map. The run
+//! with `--chunk-size` set to a `--threads`-th of the corpus splits it like the old static
+//! scheduler, one chunk per worker; the default run takes shrinking chunks from the queue. The
+//! corpus size defaults to 512 MiB and can be changed with `WORD_COUNT_BENCH_MB`;
+//! `WORD_COUNT_BENCH_THREADS` sets `--threads` (default 4).
+//!
+//! Busy time per worker runs from its first `counting` to its `done` event of `--progress json`,
+//! timed as they arrive on stderr; imbalance is the busiest worker's time over the mean, 1.00
+//! being perfectly balanced. Workers only run side by side with at least as many cores as
+//! threads; with fewer, they share cores and look balanced.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+const SENTENCES: &[&str] = &[
+    "The quick brown fox jumps over the lazy dog.",
+    "All work and no play makes Jack a dull boy.",
+    "To be or not to be, that is the question.",
+    "Houston, we have a problem.",
+];
+
+fn generate_corpus(path: &Path, target_bytes: u64) -> io::Result<()> {
+    let mut writer = BufWriter::with_capacity(1 << 20, File::create(path)?);
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut written = 0;
+    while written < target_bytes {
+        let line = if written < target_bytes / 8 {
+            let words: Vec<String> = (0..40)
+                .map(|_| char::from(b'a' + (next() % 26) as u8).to_string())
+                .collect();
+            words.join(" ") + "\n"
+        } else {
+            format!("{}\n", SENTENCES[next() as usize % SENTENCES.len()])
+        };
+        writer.write_all(line.as_bytes())?;
+        written += line.len() as u64;
+    }
+    writer.flush()
+}
+
+struct Run {
+    elapsed: Duration,
+    busy_ms: Vec<f64>,
+}
+
+fn run(input: &Path, output: &Path, threads: usize, chunk_size: Option<u64>) -> Run {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_word_count_rust"));
+    let num_threads = threads.to_string();
+    command
+        .args(["--progress", "json", "--threads", &num_threads, "--output"])
+        .arg(output)
+        .stderr(Stdio::piped());
+    if let Some(chunk_size) = chunk_size {
+        command.args(["--chunk-size", &chunk_size.to_string()]);
+    }
+    let start = Instant::now();
+    let mut child = command
+        .arg(input)
+        .spawn()
+        .expect("failed to run word_count_rust");
+
+    // {"event": "worker", "id": 3, "state": "counting", "path": "..."}
+    let (mut started, mut busy) = (HashMap::new(), HashMap::new());
+    let stderr = BufReader::new(child.stderr.take().expect("stderr is piped"));
+    for line in stderr.lines() {
+        let line = line.expect("failed to read stderr");
+        let event = match serde_json::from_str::<Value>(&line) {
+            Ok(event) if event["event"] == "worker" => event,
+            _ => continue,
+        };
+        let id = event["id"].as_u64().expect("worker event without id");
+        match event["state"].as_str() {
+            Some("counting") => {
+                started.entry(id).or_insert_with(|| start.elapsed());
+            }
+            Some("done") => {
+                let started = started.get(&id).copied().unwrap_or_else(|| start.elapsed());
+                busy.insert(id, (start.elapsed() - started).as_secs_f64() * 1000.0);
+            }
+            _ => {}
+        }
+    }
+    let status = child.wait().expect("failed to wait for word_count_rust");
+    let elapsed = start.elapsed();
+    assert!(status.success(), "word_count_rust failed");
+    assert_eq!(busy.len(), threads, "not every worker reported finishing");
+
+    Run {
+        elapsed,
+        busy_ms: busy.into_values().collect(),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let megabytes: u64 = std::env::var("WORD_COUNT_BENCH_MB")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(512);
+    let num_threads: usize = std::env::var("WORD_COUNT_BENCH_THREADS")
+        .map_or(Ok(4), |value| value.parse())
+        .expect("WORD_COUNT_BENCH_THREADS is not a number");
+
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if cores < num_threads {
+        println!(
+            "Only {} cores for {} threads: busy times are shared and not comparable.",
+            cores, num_threads
+        );
+    }
+
+    let temp_dir = tempfile::TempDir::new()?;
+    let input = temp_dir.path().join("corpus.txt");
+    println!("Generating {} MiB skewed corpus...", megabytes);
+    generate_corpus(&input, megabytes * 1024 * 1024)?;
+    let size = fs::metadata(&input)?.len();
+
+    println!(
+        "{:<10} {:>10} {:>12} {:>14} {:>14} {:>10}",
+        "schedule", "time (s)", "MiB/s", "busiest (ms)", "idlest (ms)", "imbalance"
+    );
+    let mut outputs = Vec::new();
+    for (name, chunk_size) in [
+        ("static", Some(size.div_ceil(num_threads as u64))),
+        ("queue", None),
+    ] {
+        let output = temp_dir.path().join(format!("{}.txt", name));
+        let run = run(&input, &output, num_threads, chunk_size);
+        let busiest = run.busy_ms.iter().cloned().fold(0.0, f64::max);
+        let idlest = run.busy_ms.iter().cloned().fold(f64::INFINITY, f64::min);
+        let mean = run.busy_ms.iter().sum::<f64>() / run.busy_ms.len() as f64;
+        println!(
+            "{:<10} {:>10.2} {:>12.1} {:>14.0} {:>14.0} {:>10.2}",
+            name,
+            run.elapsed.as_secs_f64(),
+            size as f64 / (1024.0 * 1024.0) / run.elapsed.as_secs_f64(),
+            busiest,
+            idlest,
+            busiest / mean,
+        );
+        outputs.push(fs::read(&output)?);
+    }
+    assert_eq!(
+        outputs[0], outputs[1],
+        "schedules produced different counts"
+    );
+
+    Ok(())
+}
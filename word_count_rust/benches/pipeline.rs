@@ -0,0 +1,164 @@
+//! Throughput and peak memory of the `lines` and `mmap` pipelines on a generated corpus.
+//!
+//! Run with `cargo bench --bench pipeline`. The corpus size defaults to 2 GiB and can be
+//! changed with `WORD_COUNT_BENCH_MB`; `WORD_COUNT_BENCH_THREADS` sets `--threads` (default 4).
+//! Each pipeline runs in its own child process so that peak RSS is measured per pipeline;
+//! memory figures are sampled from `/proc` and are only available on Linux.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SENTENCES: &[&str] = &[
+    "The quick brown fox jumps over the lazy dog.",
+    "All work and no play makes Jack a dull boy.",
+    "To be or not to be, that is the question.",
+    "I think, therefore I am.",
+    "Life is like a box of chocolates.",
+    "May the Force be with you.",
+    "Elementary, my dear Watson.",
+    "Houston, we have a problem.",
+    "E.T. phone home.",
+    "There's no place like home.",
+];
+
+const EXTRA_WORDS: &[&str] = &[
+    "computer",
+    "algorithm",
+    "programming",
+    "python",
+    "concurrency",
+    "multithreading",
+    "performance",
+    "optimization",
+    "analysis",
+    "design",
+];
+
+/// Same shape of text as `generate_large_input_file.py`, but deterministic.
+fn generate_corpus(path: &Path, target_bytes: u64) -> io::Result<()> {
+    let mut writer = BufWriter::with_capacity(1 << 20, File::create(path)?);
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut written = 0;
+    while written < target_bytes {
+        let sentence = SENTENCES[next() as usize % SENTENCES.len()];
+        let line = if next() % 10 < 3 {
+            let extra = EXTRA_WORDS[next() as usize % EXTRA_WORDS.len()];
+            format!("{} {}\n", extra, sentence)
+        } else {
+            format!("{}\n", sentence)
+        };
+        writer.write_all(line.as_bytes())?;
+        written += line.len() as u64;
+    }
+    writer.flush()
+}
+
+/// Peak resident and peak anonymous (heap) memory of a process, in KiB.
+#[derive(Default)]
+struct Memory {
+    peak_rss_kib: u64,
+    peak_anon_kib: u64,
+}
+
+fn sample_memory(pid: u32, memory: &mut Memory) {
+    let Ok(status) = fs::read_to_string(format!("/proc/{}/status", pid)) else {
+        return;
+    };
+    for line in status.lines() {
+        let field = |prefix: &str| {
+            line.strip_prefix(prefix).and_then(|rest| {
+                rest.trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+        };
+        if let Some(kib) = field("VmHWM:") {
+            memory.peak_rss_kib = memory.peak_rss_kib.max(kib);
+        } else if let Some(kib) = field("RssAnon:") {
+            memory.peak_anon_kib = memory.peak_anon_kib.max(kib);
+        }
+    }
+}
+
+fn run_pipeline(pipeline: &str, input: &Path, output: &Path, threads: &str) -> (Duration, Memory) {
+    let start = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_word_count_rust"))
+        .args(["--pipeline", pipeline, "--threads", threads, "--output"])
+        .arg(output)
+        .arg(input)
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to start word_count_rust");
+
+    let mut memory = Memory::default();
+    loop {
+        sample_memory(child.id(), &mut memory);
+        match child
+            .try_wait()
+            .expect("failed to wait for word_count_rust")
+        {
+            Some(status) => {
+                assert!(status.success(), "{} pipeline failed: {}", pipeline, status);
+                break;
+            }
+            None => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    (start.elapsed(), memory)
+}
+
+fn main() -> io::Result<()> {
+    let megabytes: u64 = std::env::var("WORD_COUNT_BENCH_MB")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(2048);
+    let threads = std::env::var("WORD_COUNT_BENCH_THREADS").unwrap_or_else(|_| "4".to_string());
+
+    let temp_dir = tempfile::TempDir::new()?;
+    let input = temp_dir.path().join("corpus.txt");
+    println!("Generating {} MiB corpus...", megabytes);
+    generate_corpus(&input, megabytes * 1024 * 1024)?;
+    let size = fs::metadata(&input)?.len();
+
+    println!(
+        "{:<8} {:>10} {:>12} {:>14} {:>14}",
+        "pipeline", "time (s)", "MiB/s", "peak RSS MiB", "peak anon MiB"
+    );
+    let mut outputs = Vec::new();
+    for pipeline in ["lines", "mmap"] {
+        let output = temp_dir.path().join(format!("{}.txt", pipeline));
+        let (elapsed, memory) = run_pipeline(pipeline, &input, &output, &threads);
+        println!(
+            "{:<8} {:>10.2} {:>12.1} {:>14.1} {:>14.1}",
+            pipeline,
+            elapsed.as_secs_f64(),
+            size as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64(),
+            memory.peak_rss_kib as f64 / 1024.0,
+            memory.peak_anon_kib as f64 / 1024.0,
+        );
+        outputs.push(fs::read(&output)?);
+    }
+    assert_eq!(
+        outputs[0], outputs[1],
+        "pipelines produced different counts"
+    );
+    println!(
+        "Peak RSS of the mmap pipeline includes mapped page-cache pages; peak anon is the heap."
+    );
+
+    Ok(())
+}
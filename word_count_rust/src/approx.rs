@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::decompress::decompressing_reader;
+use crate::error::at_path;
+use crate::mapped::{normalize_counts, tally_raw_words};
+use crate::progress::info;
+use crate::sketch::{hash_word, CountMinSketch, HyperLogLog, SpaceSaving};
+use crate::{stream, Config, Output, STDIN_PATH};
+
+/// Version of the saved sketch file; files of another version are refused.
+const SKETCH_VERSION: u32 = 1;
+
+/// Error bounds of the sketches and where they are saved to and merged from, as selected with
+/// `--approx` and its options.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SketchConfig {
+    /// Standard error of the distinct word estimate.
+    pub(crate) hll_error: f64,
+    /// A frequency is overestimated by at most `cms_epsilon` times the total word count...
+    pub(crate) cms_epsilon: f64,
+    /// ...except with probability `cms_delta`.
+    pub(crate) cms_delta: f64,
+    /// Number of words tracked as heavy hitters.
+    pub(crate) heavy_hitters: usize,
+    /// Write the final sketches to this file.
+    pub(crate) save: Option<PathBuf>,
+    /// Sketch files saved by earlier runs, merged into this run's result.
+    pub(crate) merge: Vec<PathBuf>,
+}
+
+impl Default for SketchConfig {
+    fn default() -> Self {
+        SketchConfig {
+            hll_error: 0.01,
+            cms_epsilon: 0.0001,
+            cms_delta: 0.01,
+            heavy_hitters: 1000,
+            save: None,
+            merge: Vec::new(),
+        }
+    }
+}
+
+/// Everything approximate mode knows about the words it has seen. Two `Sketches` built with the
+/// same `SketchConfig` merge into the sketches of both inputs, whether they come from two
+/// workers or two runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Sketches {
+    version: u32,
+    /// Number of words counted, not distinct.
+    total: u64,
+    /// Number of stop word occurrences left out of the sketches.
+    #[serde(default)]
+    filtered: u64,
+    distinct: HyperLogLog,
+    frequencies: CountMinSketch,
+    heavy_hitters: SpaceSaving,
+}
+
+impl Sketches {
+    /// Fails with a configuration error when the error bounds call for oversized sketches.
+    pub(crate) fn new(config: &SketchConfig) -> io::Result<Self> {
+        Ok(Sketches {
+            version: SKETCH_VERSION,
+            total: 0,
+            filtered: 0,
+            distinct: HyperLogLog::with_error(config.hll_error),
+            frequencies: CountMinSketch::with_error(config.cms_epsilon, config.cms_delta)?,
+            heavy_hitters: SpaceSaving::new(config.heavy_hitters),
+        })
+    }
+
+    pub(crate) fn add(&mut self, word: &str, count: usize) {
+        let hash = hash_word(word);
+        let count = count as u64;
+        self.total += count;
+        self.distinct.add(hash);
+        self.frequencies.add(hash, count);
+        self.heavy_hitters.add(word, count);
+    }
+
+    /// Fails with `InvalidInput` when the sketches were built with different error bounds.
+    pub(crate) fn merge(&mut self, other: &Sketches) -> io::Result<()> {
+        self.distinct.merge(&other.distinct)?;
+        self.frequencies.merge(&other.frequencies)?;
+        self.heavy_hitters.merge(&other.heavy_hitters)?;
+        self.total += other.total;
+        self.filtered += other.filtered;
+        Ok(())
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Estimated number of distinct words and the standard error of that estimate.
+    pub(crate) fn distinct(&self) -> (f64, f64) {
+        (self.distinct.estimate(), self.distinct.relative_error())
+    }
+
+    /// Estimated frequency of any word; never below the true count.
+    pub(crate) fn frequency(&self, word: &str) -> u64 {
+        self.frequencies.estimate(hash_word(word))
+    }
+
+    /// The heavy hitters with their estimated counts: both sketches overestimate, so the
+    /// smaller of the two is kept.
+    pub(crate) fn heavy_hitters(&self) -> HashMap<String, usize> {
+        self.heavy_hitters
+            .counters()
+            .iter()
+            .map(|counter| {
+                let count = counter.count.min(self.frequency(&counter.word));
+                (counter.word.clone(), count as usize)
+            })
+            .collect()
+    }
+
+    /// Fails with `InvalidData` for another version or sketches whose sizes do not add up, as
+    /// merging those would silently drop counts.
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        let sketches: Sketches = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if sketches.version != SKETCH_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported sketch version {}", sketches.version),
+            ));
+        }
+        sketches.distinct.check()?;
+        sketches.frequencies.check()?;
+        sketches.heavy_hitters.check()?;
+        Ok(sketches)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+}
+
+/// Builds the sketches of one input. Every input is streamed, so compressed files, pipes and
+/// stdin are all read the same way; each worker fills its own sketches and they are merged.
+pub(crate) fn sketch_file(
+    input_path: &Path,
+    config: &Config,
+    sketch_config: &SketchConfig,
+) -> io::Result<Sketches> {
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let fold = |sketches: &mut Sketches, text: &str, _offset: u64| {
+        let mut raw_count = HashMap::new();
+        let words = tally_raw_words(text, tokenizer, &mut raw_count);
+        for (word, count) in normalize_counts(raw_count, tokenizer, normalizer) {
+            if config.stop_words.contains(&word) {
+                sketches.filtered += count as u64;
+            } else if config.stemmer.is_enabled() {
+                sketches.add(&config.stemmer.stem(&word), count);
+            } else {
+                sketches.add(&word, count);
+            }
+        }
+        words
+    };
+    let empty = Sketches::new(sketch_config)?;
+    let init = || empty.clone();
+
+    let thread_sketches = if input_path == Path::new(STDIN_PATH) {
+        stream::fold_stream(decompressing_reader(io::stdin())?, config, init, fold)?
+    } else {
+        let reader = decompressing_reader(File::open(input_path)?)?;
+        stream::fold_stream(reader, config, init, fold)?
+    };
+
+    let mut result = empty.clone();
+    for sketches in &thread_sketches {
+        result.merge(sketches)?;
+    }
+    Ok(result)
+}
+
+/// Approximate counterpart of `process_file`: writes the heavy hitters through the usual report
+/// and summarizes the totals on stderr.
+pub(crate) fn process_files(
+    input_files: &[PathBuf],
+    output: &Output,
+    config: &Config,
+    sketch_config: &SketchConfig,
+) -> io::Result<()> {
+    let mut result = Sketches::new(sketch_config)?;
+    for input_file in input_files {
+        let sketches =
+            sketch_file(input_file, config, sketch_config).map_err(at_path(input_file))?;
+        result.merge(&sketches)?;
+    }
+    for path in &sketch_config.merge {
+        let saved = Sketches::load(path).map_err(at_path(path))?;
+        result.merge(&saved).map_err(at_path(path))?;
+    }
+
+    if let Some(path) = &sketch_config.save {
+        result.save(path).map_err(at_path(path))?;
+        info!("Sketches saved to {}", path.display());
+    }
+
+    let heavy_hitters = result.heavy_hitters();
+    let footer = crate::stop_word_footer(config, result.filtered as usize);
+    match output {
+        Output::Stdout => {
+            crate::write_results_to(io::stdout().lock(), &heavy_hitters, &config.report, &footer)?
+        }
+        Output::File(output_path) => {
+            crate::write_results(output_path, &heavy_hitters, &config.report, &footer)
+                .map_err(at_path(output_path))?
+        }
+    }
+
+    let (distinct, error) = result.distinct();
+    info!(
+        "Approximate totals: {} words, about {:.0} distinct (standard error {:.2}%)",
+        result.total(),
+        distinct,
+        error * 100.0
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn small_config() -> SketchConfig {
+        SketchConfig {
+            hll_error: 0.05,
+            cms_epsilon: 0.001,
+            cms_delta: 0.01,
+            heavy_hitters: 20,
+            ..SketchConfig::default()
+        }
+    }
+
+    /// Word `i` occurs `200 / (i + 1)` times, one word per line.
+    fn skewed_text(distinct: usize) -> String {
+        (0..distinct)
+            .flat_map(|i| std::iter::repeat_n(format!("w{}\n", i), 200 / (i + 1)))
+            .collect()
+    }
+
+    fn exact_counts(text: &str) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for word in text.split_whitespace() {
+            *counts.entry(word.to_string()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    mod test_sketch_file {
+        use super::*;
+
+        #[test]
+        fn test_sketch_file_estimates_within_bounds() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("input.txt");
+            let text = skewed_text(300);
+            fs::write(&path, &text).unwrap();
+            let sketch_config = small_config();
+            let config = Config {
+                num_threads: 3,
+                block_size: 64,
+                ..Config::default()
+            };
+
+            // When
+            let sketches = sketch_file(&path, &config, &sketch_config).unwrap();
+
+            // Then
+            let exact = exact_counts(&text);
+            let total: usize = exact.values().sum();
+            assert_eq!(sketches.total(), total as u64);
+            let (distinct, error) = sketches.distinct();
+            let expected = exact.len() as f64;
+            assert!(
+                (distinct - expected).abs() <= 3.0 * error * expected,
+                "{} for {}",
+                distinct,
+                expected
+            );
+            let bound = (sketch_config.cms_epsilon * total as f64).ceil() as u64;
+            for (word, &count) in &exact {
+                let estimate = sketches.frequency(word);
+                assert!(estimate >= count as u64 && estimate - count as u64 <= bound);
+            }
+            assert_eq!(sketches.heavy_hitters().get("w0"), Some(&200));
+            assert_eq!(sketches.heavy_hitters().get("w1"), Some(&100));
+        }
+
+        #[test]
+        fn test_sketch_file_matches_single_worker() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("input.txt");
+            fs::write(&path, skewed_text(100)).unwrap();
+            let single = Config {
+                num_threads: 1,
+                ..Config::default()
+            };
+            let parallel = Config {
+                num_threads: 4,
+                block_size: 32,
+                ..Config::default()
+            };
+
+            // When
+            let one = sketch_file(&path, &single, &small_config()).unwrap();
+            let many = sketch_file(&path, &parallel, &small_config()).unwrap();
+
+            // Then: distinct and frequency sketches merge exactly.
+            assert_eq!(one.total(), many.total());
+            assert_eq!(one.distinct, many.distinct);
+            assert_eq!(one.frequencies, many.frequencies);
+        }
+    }
+
+    mod test_saved_sketches {
+        use super::*;
+
+        #[test]
+        fn test_saved_sketches_merge_across_runs() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let (first, second) = (temp_dir.path().join("a.txt"), temp_dir.path().join("b.txt"));
+            fs::write(&first, "apple banana\napple\n").unwrap();
+            fs::write(&second, "apple cherry\n").unwrap();
+            let config = Config::default();
+            let saved = temp_dir.path().join("sketches/a.json");
+
+            // When
+            sketch_file(&first, &config, &small_config())
+                .unwrap()
+                .save(&saved)
+                .unwrap();
+            let mut merged = Sketches::load(&saved).unwrap();
+            merged
+                .merge(&sketch_file(&second, &config, &small_config()).unwrap())
+                .unwrap();
+
+            // Then
+            assert_eq!(merged.total(), 5);
+            assert_eq!(
+                merged.heavy_hitters(),
+                HashMap::from([
+                    ("apple".to_string(), 3),
+                    ("banana".to_string(), 1),
+                    ("cherry".to_string(), 1)
+                ])
+            );
+        }
+
+        #[test]
+        fn test_merge_rejects_other_error_bounds() {
+            // Given
+            let mut sketches = Sketches::new(&small_config()).unwrap();
+            let other = Sketches::new(&SketchConfig {
+                cms_epsilon: 0.01,
+                ..small_config()
+            })
+            .unwrap();
+
+            // When
+            let err = sketches.merge(&other).unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        #[test]
+        fn test_load_rejects_other_version() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("sketch.json");
+            let mut value = serde_json::to_value(Sketches::new(&small_config()).unwrap()).unwrap();
+            value["version"] = serde_json::Value::from(SKETCH_VERSION + 1);
+            fs::write(&path, value.to_string()).unwrap();
+
+            // When
+            let err = Sketches::load(&path).unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        #[test]
+        fn test_load_rejects_corrupt_sketches() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("sketch.json");
+            let mut sketches = Sketches::new(&small_config()).unwrap();
+            for i in 0..30 {
+                sketches.add(&format!("word{}", i), i + 1);
+            }
+            let saved = serde_json::to_value(&sketches).unwrap();
+            let first_word = saved["heavy_hitters"]["counters"][0]["word"].clone();
+            let corruptions = [
+                ("/frequencies/counters", serde_json::json!(vec![0; 10])),
+                ("/frequencies/width", serde_json::json!(0)),
+                ("/distinct/registers", serde_json::json!(vec![0; 10])),
+                ("/distinct/precision", serde_json::json!(40)),
+                ("/heavy_hitters/capacity", serde_json::json!(5)),
+                ("/heavy_hitters/counters/1/word", first_word),
+            ];
+
+            for (pointer, corrupt) in corruptions {
+                let mut value = saved.clone();
+                *value.pointer_mut(pointer).unwrap() = corrupt;
+                fs::write(&path, value.to_string()).unwrap();
+
+                // When
+                let err = Sketches::load(&path).unwrap_err();
+
+                // Then
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", pointer);
+            }
+            fs::write(&path, saved.to_string()).unwrap();
+            assert!(Sketches::load(&path).is_ok());
+        }
+    }
+}
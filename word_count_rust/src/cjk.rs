@@ -0,0 +1,425 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::sketch::hash_bytes;
+use crate::tokenizer::Tokenizer;
+
+/// The lexicon compiled into the binary; see the header of the file for its format.
+const BUNDLED_LEXICON: &str = include_str!("../data/cjk_lexicon.txt");
+
+/// The words the segmenter knows about.
+#[derive(Default)]
+pub(crate) struct Lexicon {
+    words: HashSet<Box<str>>,
+    /// Length in characters of the longest word, which bounds the lattice edges.
+    max_chars: usize,
+}
+
+impl Lexicon {
+    /// The lexicon bundled with the binary.
+    pub(crate) fn bundled() -> Self {
+        let mut lexicon = Lexicon::default();
+        lexicon.add_entries(BUNDLED_LEXICON);
+        lexicon
+    }
+
+    /// Adds the words of a dictionary file, one per line. Only the first whitespace-separated
+    /// field of a line is used, so jieba-style `word frequency tag` files work as they are;
+    /// blank lines and lines starting with `#` are skipped.
+    pub(crate) fn add_entries(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(word) = line.split_whitespace().next() {
+                self.max_chars = self.max_chars.max(word.chars().count());
+                self.words.insert(word.into());
+            }
+        }
+    }
+
+    /// Adds the words of the dictionary file at `path`.
+    pub(crate) fn load(&mut self, path: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.add_entries(&text);
+        Ok(())
+    }
+
+    fn contains(&self, word: &str) -> bool {
+        self.words.contains(word)
+    }
+
+    /// A hash of the words, the same whatever order they were added in, so saved state can
+    /// tell a run with another dictionary apart.
+    fn fingerprint(&self) -> u64 {
+        let mut words: Vec<&str> = self.words.iter().map(|word| &**word).collect();
+        words.sort_unstable();
+        hash_bytes(words.join("\n").as_bytes())
+    }
+}
+
+impl fmt::Debug for Lexicon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lexicon")
+            .field("words", &self.words.len())
+            .field("max_chars", &self.max_chars)
+            .finish()
+    }
+}
+
+/// The scripts the segmenter handles; everything else goes to the inner tokenizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+}
+
+fn script_of(c: char) -> Option<Script> {
+    match c {
+        '\u{3005}' | '\u{3007}' // 々 and 〇
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}' => Some(Script::Han),
+        '\u{3041}'..='\u{309F}' => Some(Script::Hiragana),
+        '\u{30FB}' => None, // katakana middle dot is punctuation
+        '\u{30A0}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' | '\u{FF66}'..='\u{FF9F}' => {
+            Some(Script::Katakana)
+        }
+        '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' | '\u{AC00}'..='\u{D7AF}' => {
+            Some(Script::Hangul)
+        }
+        _ => None,
+    }
+}
+
+/// CJK and fullwidth punctuation such as `，` `。` `「` `・`, which separates runs and is dropped.
+fn is_cjk_punctuation(c: char) -> bool {
+    match c {
+        '\u{3005}' | '\u{3007}' => false,
+        '\u{3000}'..='\u{303F}' | '\u{30FB}' => true,
+        '\u{FF00}'..='\u{FF65}' => !c.is_alphanumeric(),
+        _ => false,
+    }
+}
+
+/// Cost of a path through the lattice, compared field by field: fewest characters left to
+/// unknown words first, then fewest words, then fewest single-character words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+struct Cost {
+    unknown: usize,
+    words: usize,
+    singles: usize,
+}
+
+/// Segments runs of Han, Kana and Hangul with a lexicon and hands everything else, Latin text
+/// included, to an inner tokenizer.
+///
+/// Each run is a lattice whose edges are the lexicon words found in it; the cheapest path (see
+/// `Cost`) wins, and on ties the one whose last word is longest. A Han character that starts no
+/// known word becomes a word of its own, while an unknown stretch of Kana or Hangul is kept
+/// together up to the end of its script run, as it is usually a loanword or a name.
+#[derive(Debug)]
+pub(crate) struct CjkSegmenter {
+    inner: Arc<dyn Tokenizer>,
+    lexicon: Lexicon,
+}
+
+impl CjkSegmenter {
+    pub(crate) fn new(inner: Arc<dyn Tokenizer>, lexicon: Lexicon) -> Self {
+        CjkSegmenter { inner, lexicon }
+    }
+
+    fn segment<'a>(&self, run: &'a str, emit: &mut dyn FnMut(&'a str)) {
+        let mut offsets: Vec<usize> = run.char_indices().map(|(i, _)| i).collect();
+        let scripts: Vec<Option<Script>> = run.chars().map(script_of).collect();
+        let n = offsets.len();
+        offsets.push(run.len());
+
+        // best[j] is the cheapest way to segment the first j characters and where its last word starts.
+        let mut best: Vec<Option<(Cost, usize)>> = vec![None; n + 1];
+        best[0] = Some((Cost::default(), 0));
+        for i in 0..n {
+            let Some((cost, _)) = best[i] else {
+                continue;
+            };
+            let mut relax = |j: usize, unknown: bool| {
+                let len = j - i;
+                let next = Cost {
+                    unknown: cost.unknown + if unknown { len } else { 0 },
+                    words: cost.words + 1,
+                    singles: cost.singles + usize::from(len == 1),
+                };
+                if best[j].is_none_or(|(current, _)| next < current) {
+                    best[j] = Some((next, i));
+                }
+            };
+
+            for j in i + 1..=n.min(i + self.lexicon.max_chars) {
+                if self.lexicon.contains(&run[offsets[i]..offsets[j]]) {
+                    relax(j, false);
+                }
+            }
+            let unknown_end = match scripts[i] {
+                Some(Script::Han) | None => i + 1,
+                script => {
+                    i + scripts[i..]
+                        .iter()
+                        .take_while(|&&other| other == script)
+                        .count()
+                }
+            };
+            relax(unknown_end, true);
+        }
+
+        let mut cuts = vec![n];
+        let mut j = n;
+        while j > 0 {
+            j = best[j].expect("every position is reachable").1;
+            cuts.push(j);
+        }
+        for pair in cuts.windows(2).rev() {
+            emit(&run[offsets[pair[1]]..offsets[pair[0]]]);
+        }
+    }
+}
+
+impl Tokenizer for CjkSegmenter {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+        // The text is cut into alternating CJK and other runs; `run` holds the current one.
+        let mut run: Option<(usize, bool)> = None;
+        let flush = |start: usize, end: usize, cjk: bool, emit: &mut dyn FnMut(&'a str)| {
+            if cjk {
+                self.segment(&text[start..end], emit);
+            } else {
+                self.inner.tokenize(&text[start..end], emit);
+            }
+        };
+        for (i, c) in text.char_indices() {
+            if is_cjk_punctuation(c) {
+                if let Some((start, cjk)) = run.take() {
+                    flush(start, i, cjk, emit);
+                }
+                continue;
+            }
+            let cjk = script_of(c).is_some();
+            match run {
+                Some((_, current)) if current == cjk => {}
+                Some((start, current)) => {
+                    flush(start, i, current, emit);
+                    run = Some((i, cjk));
+                }
+                None => run = Some((i, cjk)),
+            }
+        }
+        if let Some((start, cjk)) = run {
+            flush(start, text.len(), cjk, emit);
+        }
+    }
+
+    fn clean<'a>(&self, token: &'a str) -> Cow<'a, str> {
+        self.inner.clean(token)
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        let inner = self.inner.fingerprint()?;
+        Some(format!("cjk {:016x} {}", self.lexicon.fingerprint(), inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::{normalize_token, Normalizer};
+    use crate::tokenizer::{UnicodeWordTokenizer, WhitespaceTokenizer};
+
+    fn segment(text: &str) -> Vec<&str> {
+        let segmenter = CjkSegmenter::new(Arc::new(WhitespaceTokenizer), Lexicon::bundled());
+        let mut tokens = Vec::new();
+        segmenter.tokenize(text, &mut |token| tokens.push(token));
+        tokens
+    }
+
+    mod test_lexicon {
+        use super::*;
+
+        #[test]
+        fn test_lexicon_reads_first_field_and_skips_comments() {
+            // Given
+            let mut lexicon = Lexicon::default();
+
+            // When
+            lexicon.add_entries("# comment\n北京大学 2053 nt\n\n  清华  \n");
+
+            // Then
+            assert!(lexicon.contains("北京大学"));
+            assert!(lexicon.contains("清华"));
+            assert!(!lexicon.contains("# comment"));
+            assert_eq!(lexicon.words.len(), 2);
+            assert_eq!(lexicon.max_chars, 4);
+        }
+
+        #[test]
+        fn test_lexicon_fingerprint_follows_the_words_not_their_order() {
+            // Given
+            let (mut first, mut second, mut third) =
+                (Lexicon::default(), Lexicon::default(), Lexicon::default());
+
+            // When
+            first.add_entries("北京\n清华\n");
+            second.add_entries("清华\n北京\n");
+            third.add_entries("北京\n清华大学\n");
+
+            // Then
+            assert_eq!(first.fingerprint(), second.fingerprint());
+            assert_ne!(first.fingerprint(), third.fingerprint());
+        }
+
+        #[test]
+        fn test_lexicon_load_reports_missing_file() {
+            // When
+            let err = Lexicon::default()
+                .load(Path::new("/nonexistent/dict.txt"))
+                .unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        }
+    }
+
+    mod test_cjk_segmenter {
+        use super::*;
+
+        #[test]
+        fn test_segments_chinese_sentence() {
+            // When
+            let result = segment("我们在北京学习中文");
+
+            // Then
+            assert_eq!(result, ["我们", "在", "北京", "学习", "中文"]);
+        }
+
+        #[test]
+        fn test_prefers_fewer_words() {
+            // When
+            let result = segment("我爱天安门");
+
+            // Then
+            assert_eq!(result, ["我", "爱", "天安门"]);
+        }
+
+        #[test]
+        fn test_bundled_lexicon_knows_everyday_words() {
+            // When
+            let result = segment("中华人民共和国成立了，人民很高兴。我喜欢吃苹果");
+
+            // Then
+            assert_eq!(
+                result,
+                [
+                    "中华人民共和国",
+                    "成立",
+                    "了",
+                    "人民",
+                    "很",
+                    "高兴",
+                    "我",
+                    "喜欢",
+                    "吃",
+                    "苹果"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_unknown_han_characters_stand_alone() {
+            // When
+            let result = segment("我喜欢龘靐");
+
+            // Then
+            assert_eq!(result, ["我", "喜欢", "龘", "靐"]);
+        }
+
+        #[test]
+        fn test_leaves_latin_text_to_inner_tokenizer() {
+            // When
+            let result = segment("用Rust写code, 很快!");
+
+            // Then
+            assert_eq!(result, ["用", "Rust", "写", "code,", "很", "快", "!"]);
+        }
+
+        #[test]
+        fn test_drops_cjk_punctuation() {
+            // When
+            let result = segment("「你好」，世界。");
+
+            // Then
+            assert_eq!(result, ["你好", "世界"]);
+        }
+
+        #[test]
+        fn test_segments_japanese_and_keeps_unknown_katakana_together() {
+            // When
+            let result = segment("私はプログラムとラズベリーパイを見る");
+
+            // Then
+            assert_eq!(
+                result,
+                [
+                    "私",
+                    "は",
+                    "プログラム",
+                    "と",
+                    "ラズベリーパイ",
+                    "を",
+                    "見る"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_segments_korean() {
+            // When
+            let result = segment("우리는 학교에 가다");
+
+            // Then
+            assert_eq!(result, ["우리", "는", "학교", "에", "가다"]);
+        }
+
+        #[test]
+        fn test_user_dictionary_extends_bundled_lexicon() {
+            // Given
+            let mut lexicon = Lexicon::bundled();
+            lexicon.add_entries("学习中文\n");
+            let segmenter = CjkSegmenter::new(Arc::new(UnicodeWordTokenizer), lexicon);
+            let mut tokens = Vec::new();
+
+            // When
+            segmenter.tokenize("我们学习中文 don't", &mut |token| tokens.push(token));
+
+            // Then
+            assert_eq!(tokens, ["我们", "学习中文", "don't"]);
+        }
+
+        #[test]
+        fn test_clean_delegates_to_inner_tokenizer() {
+            // Given
+            let segmenter = CjkSegmenter::new(Arc::new(WhitespaceTokenizer), Lexicon::bundled());
+
+            let normalizer = Normalizer::default();
+
+            // Then
+            assert_eq!(normalize_token(&segmenter, &normalizer, "Code,"), "code");
+            assert_eq!(normalize_token(&segmenter, &normalizer, "北京"), "北京");
+        }
+    }
+}
@@ -0,0 +1,1364 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::builder::RangedU64ValueParser;
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, Parser};
+
+use crate::approx::SketchConfig;
+use crate::cjk::{CjkSegmenter, Lexicon};
+use crate::encoding::{InvalidLines, Utf8Policy};
+use crate::error::{describe, WordCountError};
+use crate::fields::{Column, FieldSelector};
+use crate::follow::{FollowConfig, Updates};
+use crate::format::Format;
+use crate::ngram::{Boundary, NgramConfig};
+use crate::normalize::{CaseMode, Form, Normalizer};
+use crate::progress::{self, debug, error, Level, ProgressConfig, ProgressMode};
+use crate::stem::{Lemmatizer, StemLanguage, Stemmer};
+use crate::stopwords::{StopList, StopWords};
+use crate::timeline::{BucketSize, TimelineConfig, TimestampFormat};
+use crate::tokenizer::{Tokenizer, TokenizerKind};
+use crate::topk::Order;
+use crate::walk::WalkConfig;
+use crate::{
+    default_threads, process_file, Config, Output, Pipeline, Report, BLOCK_SIZE, BUFFER_SIZE,
+};
+
+/// Count word frequencies in one or more text files.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    after_help = "Exit status:\n  0  success\n  1  reading an input or writing the results failed\n  2  invalid command line\n  3  an input is not valid UTF-8\n  4  a worker thread panicked"
+)]
+pub struct Args {
+    /// Input files or directories to count, "-" for stdin; counts from all inputs are merged
+    /// into one result unless --per-file is given
+    #[arg(required_unless_present = "merge_sketch", value_name = "INPUT")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Where to write the results, "-" for stdout
+    #[arg(short, long, value_name = "PATH", default_value = "-")]
+    pub output: String,
+
+    /// Number of worker threads [default: number of cores]
+    #[arg(short = 't', long, value_name = "N", default_value_t = default_threads(), hide_default_value = true,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub threads: usize,
+
+    /// Size in bytes of the read buffer used by each worker
+    #[arg(short = 'b', long, value_name = "BYTES", default_value_t = BUFFER_SIZE,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub buffer_size: usize,
+
+    /// Size in bytes of the chunks workers take from the queue [default: shrinks from 16 MiB to
+    /// 64 KiB as the input runs out]
+    #[arg(long, value_name = "BYTES",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub chunk_size: Option<usize>,
+
+    /// Copy each chunk into DIR and count from the copies; they are removed afterwards
+    #[arg(long, value_name = "DIR")]
+    pub export_chunks: Option<PathBuf>,
+
+    /// How workers read their chunk (--export-chunks always uses "lines")
+    #[arg(long, value_enum, default_value_t = Pipeline::default())]
+    pub pipeline: Pipeline,
+
+    /// Size in bytes of the line-aligned blocks read from stdin, pipes and FIFOs
+    #[arg(long, value_name = "BYTES", default_value_t = BLOCK_SIZE,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub block_size: usize,
+
+    /// What to do with lines that are not valid UTF-8; UTF-16 input with a byte order mark is
+    /// always transcoded
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = Utf8Policy::default())]
+    pub invalid_utf8: Utf8Policy,
+
+    /// Only count one column of CSV input, given by its name in the header record or its number
+    /// from 1; quoted fields may span lines
+    #[arg(long, value_name = "COLUMN", value_parser = Column::parse, conflicts_with = "json_field")]
+    pub csv_column: Option<Column>,
+
+    /// Field delimiter of the --csv-column input, such as ";" or "\t"
+    #[arg(long, value_name = "CHAR", default_value = ",", requires = "csv_column",
+          value_parser = parse_delimiter)]
+    pub csv_delimiter: u8,
+
+    /// The first CSV record is data, not column names; --csv-column must then be a number
+    #[arg(long, requires = "csv_column")]
+    pub csv_no_header: bool,
+
+    /// Only count the strings at a JSON pointer, such as /message, of NDJSON input (one JSON
+    /// record per line)
+    #[arg(long, value_name = "POINTER", value_parser = parse_pointer)]
+    pub json_field: Option<String>,
+
+    /// How lines are split into the tokens that are counted
+    #[arg(long, value_enum, default_value_t = TokenizerKind::default())]
+    pub tokenizer: TokenizerKind,
+
+    /// Regular expression whose matches are the tokens (with --tokenizer regex)
+    #[arg(long, value_name = "REGEX")]
+    pub token_pattern: Option<String>,
+
+    /// Segment Chinese, Japanese and Korean runs with the bundled lexicon; other text still goes
+    /// to --tokenizer
+    #[arg(long)]
+    pub cjk: bool,
+
+    /// Add the words of FILE (one per line) to the CJK lexicon; implies --cjk
+    #[arg(long, value_name = "FILE")]
+    pub cjk_dict: Vec<PathBuf>,
+
+    /// Unicode normalization form applied to every word
+    #[arg(long, value_enum, value_name = "FORM", default_value_t = Form::default())]
+    pub normalize: Form,
+
+    /// How letter case is treated
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = CaseMode::default())]
+    pub case: CaseMode,
+
+    /// Remove accents and other combining marks, so "café" counts as "cafe"
+    #[arg(long)]
+    pub strip_diacritics: bool,
+
+    /// Only count the files below a directory input that match GLOB; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip the files and directories below a directory input that match GLOB; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude_glob: Vec<String>,
+
+    /// Skip what .gitignore files ignore below a directory input
+    #[arg(long)]
+    pub gitignore: bool,
+
+    /// Write the counts of every input file separately instead of one total
+    #[arg(long)]
+    pub per_file: bool,
+
+    /// Format of the results
+    #[arg(short = 'f', long, value_enum, default_value_t = Format::default())]
+    pub format: Format,
+
+    /// Only write the K most frequent words (ties broken by word), ordered by count unless
+    /// --sort is given
+    #[arg(short = 'k', long, value_name = "K",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub top: Option<usize>,
+
+    /// Order of the results [default: word, or count with --top]
+    #[arg(long, value_enum)]
+    pub sort: Option<Order>,
+
+    /// Estimate with fixed-size sketches instead of exact counts: only the heavy hitters are
+    /// written, the total and distinct counts are reported on stderr
+    #[arg(long)]
+    pub approx: bool,
+
+    /// Standard error of the distinct word estimate (HyperLogLog); implies --approx
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    pub hll_error: Option<f64>,
+
+    /// Frequencies are overestimated by at most this fraction of all words (Count-Min
+    /// Sketch), at least 0.000001; every worker keeps 8 x e/FRACTION x ln(1/--cms-delta) bytes,
+    /// about 1 MB at the default 0.0001 and 109 MB at 0.000001; implies --approx
+    #[arg(long, value_name = "FRACTION", value_parser = parse_cms_epsilon)]
+    pub cms_epsilon: Option<f64>,
+
+    /// Probability that a frequency exceeds the --cms-epsilon bound; implies --approx
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    pub cms_delta: Option<f64>,
+
+    /// Number of most frequent words tracked (Space-Saving); implies --approx
+    #[arg(long, value_name = "N",
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub heavy_hitters: Option<usize>,
+
+    /// Save the sketches to PATH, to be merged into a later run; implies --approx
+    #[arg(long, value_name = "PATH")]
+    pub save_sketch: Option<PathBuf>,
+
+    /// Merge sketches saved by an earlier run; may be repeated, implies --approx
+    #[arg(long, value_name = "PATH")]
+    pub merge_sketch: Vec<PathBuf>,
+
+    /// Remember the counts of regular input files in FILE; later runs with the same FILE
+    /// count only the lines appended since, and count truncated or rewritten files again
+    #[arg(long, value_name = "FILE")]
+    pub state: Option<PathBuf>,
+
+    /// Keep reading the input files as they grow, as `tail -F` does, and report the counts
+    /// as they change; rotated and truncated files are followed from their start
+    #[arg(long)]
+    pub follow: bool,
+
+    /// Seconds between looks at the followed files
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0, requires = "follow",
+          value_parser = parse_interval)]
+    pub follow_interval: f64,
+
+    /// How --follow reports the counts as they change
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = Updates::default(),
+          requires = "follow")]
+    pub follow_updates: Updates,
+
+    /// Count words per minute, hour or day of the timestamp each line starts with, and write a
+    /// series of counts per word
+    #[arg(long, value_enum, value_name = "SIZE")]
+    pub buckets: Option<BucketSize>,
+
+    /// Timestamp at the start of each line: %Y, %m, %d, %H, %M, %S, %f (fraction of a second),
+    /// %z (Z or +hh:mm) and %s (Unix time); other characters match themselves
+    #[arg(long, value_name = "FORMAT", default_value = "%Y-%m-%dT%H:%M:%S", requires = "buckets",
+          value_parser = TimestampFormat::parse)]
+    pub timestamp_format: TimestampFormat,
+
+    /// Number of words reported as rising the most into the last bucket
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "buckets")]
+    pub trending: usize,
+
+    /// How progress is shown on stderr
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = ProgressMode::default())]
+    pub progress: ProgressMode,
+
+    /// Only report errors: no progress and no summary
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log more: -v for workers and phases, -vv for every chunk
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Count runs of N consecutive words instead of single words
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 1,
+          value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub ngrams: usize,
+
+    /// Where an n-gram may not continue
+    #[arg(long, value_enum, default_value_t = Boundary::default())]
+    pub ngram_boundary: Boundary,
+
+    /// Leave the words of a bundled stop-word list out of the results; may be repeated
+    #[arg(long, value_enum, value_name = "LIST")]
+    pub stop_words: Vec<StopList>,
+
+    /// Leave the words of FILE (one per line) out of the results; may be repeated
+    #[arg(long, value_name = "FILE")]
+    pub stop_words_file: Vec<PathBuf>,
+
+    /// Leave words matching REGEX out of the results; may be repeated
+    #[arg(long, value_name = "REGEX")]
+    pub exclude: Vec<String>,
+
+    /// Count every word under its Snowball stem
+    #[arg(long, value_enum, value_name = "LANG")]
+    pub stem: Option<StemLanguage>,
+
+    /// Count irregular forms under their lemma ("went" as "go") with the bundled table
+    #[arg(long)]
+    pub lemmatize: bool,
+
+    /// Add the lemmas of FILE ("lemma form..." per line); may be repeated, implies --lemmatize
+    #[arg(long, value_name = "FILE")]
+    pub lemma_table: Vec<PathBuf>,
+
+    /// Write each stem or lemma as its most common word instead, so the results stay readable
+    #[arg(long)]
+    pub surface_forms: bool,
+}
+
+/// Smallest `--cms-epsilon`.
+const MIN_CMS_EPSILON: f64 = 0.000001;
+
+/// Parses a polling interval in seconds, which must be positive.
+fn parse_interval(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value
+        .parse()
+        .map_err(|_| format!("{} is not a number of seconds", value))?;
+    if seconds > 0.0 && seconds.is_finite() {
+        Ok(seconds)
+    } else {
+        Err(format!("{} is not a positive number of seconds", value))
+    }
+}
+
+/// Parses a CSV delimiter: one ASCII character other than a quote or a newline, or "\t" for a tab.
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "\\t" | "\t" => Ok(b'\t'),
+        _ => match value.as_bytes() {
+            [byte] if byte.is_ascii() && !matches!(byte, b'"' | b'\n' | b'\r') => Ok(*byte),
+            _ => Err(format!("{} is not a single ASCII character", value)),
+        },
+    }
+}
+
+/// Parses a JSON pointer: empty for the whole record, or a path starting with "/".
+fn parse_pointer(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.starts_with('/') {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "{} is not a JSON pointer, which starts with /",
+            value
+        ))
+    }
+}
+
+/// Parses an error bound, which must lie strictly between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if fraction > 0.0 && fraction < 1.0 {
+        Ok(fraction)
+    } else {
+        Err(format!("{} is not between 0 and 1", value))
+    }
+}
+
+/// Parses the Count-Min Sketch error bound, which must lie between `MIN_CMS_EPSILON` and 1: the
+/// sketch of every worker grows as its inverse.
+fn parse_cms_epsilon(value: &str) -> Result<f64, String> {
+    let epsilon = parse_fraction(value)?;
+    if epsilon >= MIN_CMS_EPSILON {
+        Ok(epsilon)
+    } else {
+        Err(format!(
+            "{} is below {}, which already takes 109 MB per worker",
+            value, MIN_CMS_EPSILON
+        ))
+    }
+}
+
+/// Runs the command line of the process and returns its exit status; usage errors are
+/// printed by clap, which exits with status 2 itself.
+pub fn run() -> i32 {
+    let args = Args::parse();
+    progress::install(args.progress());
+    let result = match args.config() {
+        Ok(config) => {
+            debug!("Starting word count process");
+            process_file(&args.inputs, &args.output(), &config)
+        }
+        Err(ArgsError::Usage(err)) => err.exit(),
+        Err(ArgsError::Input(err)) => Err(err),
+    };
+    progress::finish();
+    match result {
+        Ok(()) => {
+            debug!("Processing completed successfully.");
+            0
+        }
+        Err(err) => {
+            error!("Error: {}", describe(&err));
+            err.exit_code()
+        }
+    }
+}
+
+/// Why the command line cannot be turned into a run configuration.
+#[derive(Debug)]
+pub enum ArgsError {
+    /// Options that are invalid or do not fit together; clap prints it with the usage.
+    Usage(clap::Error),
+    /// A dictionary, stop-word list or lemma table could not be read.
+    Input(WordCountError),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::Usage(err) => write!(f, "{}", err),
+            ArgsError::Input(err) => write!(f, "{}", describe(err)),
+        }
+    }
+}
+
+impl From<clap::Error> for ArgsError {
+    fn from(err: clap::Error) -> Self {
+        ArgsError::Usage(err)
+    }
+}
+
+impl From<WordCountError> for ArgsError {
+    fn from(err: WordCountError) -> Self {
+        ArgsError::Input(err)
+    }
+}
+
+/// A usage error of `kind`, printed with `message`.
+fn usage_error(kind: ErrorKind, message: impl fmt::Display) -> ArgsError {
+    ArgsError::Usage(Args::command().error(kind, message))
+}
+
+impl Args {
+    pub fn output(&self) -> Output {
+        if self.output == "-" {
+            Output::Stdout
+        } else {
+            Output::File(PathBuf::from(&self.output))
+        }
+    }
+
+    /// How progress and log messages are reported.
+    pub fn progress(&self) -> ProgressConfig {
+        ProgressConfig {
+            mode: self.progress,
+            level: Level::from_flags(self.quiet, self.verbose),
+        }
+    }
+
+    /// Builds the run configuration; fails, with a usage error, on options that only make
+    /// sense together, and with an input error when a file it names cannot be read.
+    pub fn config(&self) -> Result<Config, ArgsError> {
+        let tokenizer = self
+            .tokenizer
+            .build(self.token_pattern.as_deref())
+            .map_err(|message| Args::command().error(ErrorKind::ArgumentConflict, message))?;
+        let tokenizer = if self.cjk || !self.cjk_dict.is_empty() {
+            let mut lexicon = Lexicon::bundled();
+            for path in &self.cjk_dict {
+                lexicon
+                    .load(path)
+                    .map_err(|e| WordCountError::from(e).with_path(path))?;
+            }
+            Arc::new(CjkSegmenter::new(tokenizer, lexicon))
+        } else {
+            tokenizer
+        };
+        let normalizer = Normalizer {
+            form: self.normalize,
+            case: self.case,
+            strip_diacritics: self.strip_diacritics,
+        };
+        let stop_words = self.stop_words(tokenizer.as_ref(), &normalizer)?;
+        let stemmer = self.stemmer(tokenizer.as_ref(), &normalizer)?;
+        let approx = self.sketch_config();
+        let ngrams = (self.ngrams > 1).then_some(NgramConfig {
+            n: self.ngrams,
+            boundary: self.ngram_boundary,
+        });
+        if stemmer.surface_forms && !stemmer.is_enabled() {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--surface-forms needs --stem, --lemmatize or --lemma-table",
+            ));
+        }
+        if approx.is_some() && stemmer.surface_forms {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--surface-forms cannot be combined with approximate mode",
+            ));
+        }
+        if approx.is_some() && self.per_file {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--per-file cannot be combined with approximate mode",
+            ));
+        }
+        let walk = WalkConfig {
+            include: self.include.clone(),
+            exclude: self.exclude_glob.clone(),
+            gitignore: self.gitignore,
+        };
+        walk.validate()
+            .map_err(|message| Args::command().error(ErrorKind::ValueValidation, message))?;
+        if approx.is_some() && ngrams.is_some() {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--ngrams cannot be combined with approximate mode",
+            ));
+        }
+        if self.state.is_some() && (approx.is_some() || ngrams.is_some()) {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--state cannot be combined with approximate mode or --ngrams",
+            ));
+        }
+        if self.follow
+            && (approx.is_some() || ngrams.is_some() || self.state.is_some() || self.per_file)
+        {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--follow cannot be combined with approximate mode, --ngrams, --state or \
+                 --per-file",
+            ));
+        }
+        if self.buckets.is_some()
+            && (approx.is_some()
+                || ngrams.is_some()
+                || self.state.is_some()
+                || self.follow
+                || self.per_file)
+        {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--buckets cannot be combined with approximate mode, --ngrams, --state, --follow \
+                 or --per-file",
+            ));
+        }
+        let fields = match (&self.csv_column, &self.json_field) {
+            (Some(column), _) => Some(FieldSelector::Csv {
+                column: column.clone(),
+                delimiter: self.csv_delimiter,
+                header: !self.csv_no_header,
+            }),
+            (None, Some(pointer)) => Some(FieldSelector::Json {
+                pointer: pointer.clone(),
+            }),
+            (None, None) => None,
+        };
+        if self.csv_no_header && matches!(self.csv_column, Some(Column::Name(_))) {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--csv-no-header needs --csv-column to be a column number",
+            ));
+        }
+        if fields.is_some()
+            && (approx.is_some()
+                || ngrams.is_some()
+                || self.state.is_some()
+                || self.follow
+                || self.buckets.is_some()
+                || self.export_chunks.is_some())
+        {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--csv-column and --json-field cannot be combined with approximate mode, \
+                 --ngrams, --state, --follow, --buckets or --export-chunks",
+            ));
+        }
+        if (self.state.is_some() || self.follow) && self.export_chunks.is_some() {
+            return Err(usage_error(
+                ErrorKind::ArgumentConflict,
+                "--state and --follow cannot be combined with --export-chunks",
+            ));
+        }
+        Ok(Config {
+            num_threads: self.threads,
+            buffer_size: self.buffer_size,
+            chunk_size: self.chunk_size,
+            chunk_dir: self.export_chunks.clone(),
+            pipeline: self.pipeline,
+            block_size: self.block_size,
+            invalid_utf8: self.invalid_utf8,
+            invalid_lines: InvalidLines::default(),
+            tokenizer,
+            normalizer,
+            report: Report {
+                format: self.format,
+                order: self.sort.unwrap_or(match self.top {
+                    Some(_) => Order::Count,
+                    None => Order::Word,
+                }),
+                top: self.top,
+                per_file: self.per_file,
+                threads: self.threads,
+            },
+            approx,
+            ngrams,
+            stop_words: Arc::new(stop_words),
+            stemmer: Arc::new(stemmer),
+            walk,
+            state: self.state.clone(),
+            follow: self.follow.then_some(FollowConfig {
+                interval: Duration::from_secs_f64(self.follow_interval),
+                updates: self.follow_updates,
+            }),
+            timeline: self.buckets.map(|bucket| TimelineConfig {
+                format: self.timestamp_format.clone(),
+                bucket,
+                trending: self.trending,
+            }),
+            fields,
+        })
+    }
+
+    /// The stemming stage, with the lemma tables normalized like the words they are compared
+    /// with.
+    fn stemmer(
+        &self,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) -> Result<Stemmer, ArgsError> {
+        let lemmatizer = if self.lemmatize || !self.lemma_table.is_empty() {
+            let mut lemmatizer = Lemmatizer::bundled(tokenizer, normalizer);
+            for path in &self.lemma_table {
+                lemmatizer
+                    .load(path, tokenizer, normalizer)
+                    .map_err(|e| WordCountError::from(e).with_path(path))?;
+            }
+            Some(lemmatizer)
+        } else {
+            None
+        };
+        Ok(Stemmer {
+            language: self.stem,
+            lemmatizer,
+            surface_forms: self.surface_forms,
+        })
+    }
+
+    /// The bundled lists, list files and patterns, with list entries normalized like the words
+    /// they are compared with.
+    fn stop_words(
+        &self,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) -> Result<StopWords, ArgsError> {
+        let mut stop_words = StopWords::default();
+        for &list in &self.stop_words {
+            stop_words.add_list(list, tokenizer, normalizer);
+        }
+        for path in &self.stop_words_file {
+            stop_words
+                .load(path, tokenizer, normalizer)
+                .map_err(|e| WordCountError::from(e).with_path(path))?;
+        }
+        for pattern in &self.exclude {
+            stop_words
+                .add_pattern(pattern)
+                .map_err(|message| Args::command().error(ErrorKind::ValueValidation, message))?;
+        }
+        Ok(stop_words)
+    }
+
+    /// The sketch settings, when any approximate mode option is given.
+    fn sketch_config(&self) -> Option<SketchConfig> {
+        let enabled = self.approx
+            || self.hll_error.is_some()
+            || self.cms_epsilon.is_some()
+            || self.cms_delta.is_some()
+            || self.heavy_hitters.is_some()
+            || self.save_sketch.is_some()
+            || !self.merge_sketch.is_empty();
+        if !enabled {
+            return None;
+        }
+        let defaults = SketchConfig::default();
+        Some(SketchConfig {
+            hll_error: self.hll_error.unwrap_or(defaults.hll_error),
+            cms_epsilon: self.cms_epsilon.unwrap_or(defaults.cms_epsilon),
+            cms_delta: self.cms_delta.unwrap_or(defaults.cms_delta),
+            heavy_hitters: self.heavy_hitters.unwrap_or(defaults.heavy_hitters),
+            save: self.save_sketch.clone(),
+            merge: self.merge_sketch.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The usage error `err` carries; an input error fails the test.
+    fn usage(err: ArgsError) -> clap::Error {
+        match err {
+            ArgsError::Usage(err) => err,
+            ArgsError::Input(err) => panic!("expected a usage error, got {}", describe(&err)),
+        }
+    }
+
+    mod test_args {
+        use super::*;
+
+        fn tokens(config: &Config, text: &str) -> Vec<String> {
+            let mut tokens = Vec::new();
+            config
+                .tokenizer
+                .tokenize(text, &mut |token| tokens.push(token.to_string()));
+            tokens
+        }
+
+        #[test]
+        fn test_args_defaults() {
+            // Given
+            let argv = ["word_count", "input.txt"];
+
+            // When
+            let args = Args::try_parse_from(argv).unwrap();
+
+            // Then
+            assert_eq!(args.inputs, vec![PathBuf::from("input.txt")]);
+            assert_eq!(args.output(), Output::Stdout);
+            assert_eq!(args.config().unwrap().num_threads, default_threads());
+            assert_eq!(args.config().unwrap().buffer_size, BUFFER_SIZE);
+            assert_eq!(args.config().unwrap().chunk_dir, None);
+            assert_eq!(args.config().unwrap().pipeline, Pipeline::Mmap);
+            assert_eq!(args.config().unwrap().block_size, BLOCK_SIZE);
+            assert_eq!(args.config().unwrap().normalizer, Normalizer::default());
+            assert_eq!(args.config().unwrap().report, Report::default());
+        }
+
+        #[test]
+        fn test_args_with_all_options() {
+            // Given
+            let argv = [
+                "word_count",
+                "-o",
+                "out.txt",
+                "-t",
+                "8",
+                "-b",
+                "65536",
+                "--export-chunks",
+                "chunks",
+                "--pipeline",
+                "lines",
+                "--block-size",
+                "4096",
+                "-f",
+                "ndjson",
+                "a.txt",
+                "b.txt",
+            ];
+
+            // When
+            let args = Args::try_parse_from(argv).unwrap();
+
+            // Then
+            assert_eq!(
+                args.inputs,
+                vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]
+            );
+            assert_eq!(args.output(), Output::File(PathBuf::from("out.txt")));
+            assert_eq!(args.config().unwrap().num_threads, 8);
+            assert_eq!(args.config().unwrap().buffer_size, 65536);
+            assert_eq!(
+                args.config().unwrap().chunk_dir,
+                Some(PathBuf::from("chunks"))
+            );
+            assert_eq!(args.config().unwrap().pipeline, Pipeline::Lines);
+            assert_eq!(args.config().unwrap().block_size, 4096);
+            assert_eq!(args.config().unwrap().report.format, Format::Ndjson);
+        }
+
+        #[test]
+        fn test_args_accepts_stdin() {
+            // When
+            let args = Args::try_parse_from(["word_count", "-"]).unwrap();
+
+            // Then
+            assert_eq!(args.inputs, vec![PathBuf::from(crate::STDIN_PATH)]);
+        }
+
+        #[test]
+        fn test_args_requires_an_input() {
+            // When
+            let result = Args::try_parse_from(["word_count"]);
+
+            // Then
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn test_args_rejects_zero_threads() {
+            // When
+            let result = Args::try_parse_from(["word_count", "-t", "0", "input.txt"]);
+
+            // Then
+            let err = result.unwrap_err();
+            assert_eq!(err.exit_code(), 2);
+        }
+
+        #[test]
+        fn test_args_rejects_zero_buffer_size() {
+            // When
+            let result = Args::try_parse_from(["word_count", "--buffer-size", "0", "input.txt"]);
+
+            // Then
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn test_args_selects_tokenizer() {
+            // Given
+            let argv = [
+                "word_count",
+                "--tokenizer",
+                "regex",
+                "--token-pattern",
+                r"\d+",
+                "in.txt",
+            ];
+
+            // When
+            let config = Args::try_parse_from(argv).unwrap().config().unwrap();
+
+            // Then
+            assert_eq!(tokens(&config, "a1 b22"), ["1", "22"]);
+        }
+
+        #[test]
+        fn test_args_enables_cjk_segmentation() {
+            // Given
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            let dict_path = temp_dir.path().join("dict.txt");
+            std::fs::write(&dict_path, "学习中文 10 v\n").unwrap();
+            let plain = Args::try_parse_from(["word_count", "in.txt"]).unwrap();
+            let cjk = Args::try_parse_from(["word_count", "--cjk", "in.txt"]).unwrap();
+            let dict_arg = dict_path.to_str().unwrap();
+            let with_dict =
+                Args::try_parse_from(["word_count", "--cjk-dict", dict_arg, "in.txt"]).unwrap();
+
+            // When
+            let text = "我们学习中文 ok";
+
+            // Then
+            assert_eq!(
+                tokens(&plain.config().unwrap(), text),
+                ["我们学习中文", "ok"]
+            );
+            assert_eq!(
+                tokens(&cjk.config().unwrap(), text),
+                ["我们", "学习", "中文", "ok"]
+            );
+            assert_eq!(
+                tokens(&with_dict.config().unwrap(), text),
+                ["我们", "学习中文", "ok"]
+            );
+        }
+
+        #[test]
+        fn test_args_selects_normalization() {
+            // Given
+            let argv = [
+                "word_count",
+                "--normalize",
+                "nfkc",
+                "--case",
+                "fold-turkic",
+                "--strip-diacritics",
+                "in.txt",
+            ];
+
+            // When
+            let config = Args::try_parse_from(argv).unwrap().config().unwrap();
+
+            // Then
+            assert_eq!(
+                config.normalizer,
+                Normalizer {
+                    form: Form::Nfkc,
+                    case: CaseMode::FoldTurkic,
+                    strip_diacritics: true,
+                }
+            );
+        }
+
+        #[test]
+        fn test_args_top_defaults_to_count_order() {
+            // Given
+            let top = Args::try_parse_from(["word_count", "-k", "100", "in.txt"]).unwrap();
+            let sorted = Args::try_parse_from(["word_count", "--sort", "count", "in.txt"]).unwrap();
+            let top_by_word =
+                Args::try_parse_from(["word_count", "--top", "5", "--sort", "word", "in.txt"])
+                    .unwrap();
+
+            // Then
+            let report = |args: &Args| args.config().unwrap().report;
+            assert_eq!(
+                (report(&top).top, report(&top).order),
+                (Some(100), Order::Count)
+            );
+            assert_eq!(
+                (report(&sorted).top, report(&sorted).order),
+                (None, Order::Count)
+            );
+            assert_eq!(
+                (report(&top_by_word).top, report(&top_by_word).order),
+                (Some(5), Order::Word)
+            );
+            assert!(Args::try_parse_from(["word_count", "--top", "0", "in.txt"]).is_err());
+        }
+
+        #[test]
+        fn test_args_reports_unreadable_files_as_input_errors() {
+            for (option, path) in [
+                ("--cjk-dict", "/nonexistent/dict.txt"),
+                ("--stop-words-file", "/nonexistent/stop.txt"),
+                ("--lemma-table", "/nonexistent/lemmas.tsv"),
+            ] {
+                // Given
+                let argv = ["word_count", option, path, "in.txt"];
+
+                // When
+                let err = Args::try_parse_from(argv).unwrap().config().unwrap_err();
+
+                // Then
+                let ArgsError::Input(err) = err else {
+                    panic!("{} gave a usage error: {}", option, err);
+                };
+                assert_eq!(err.exit_code(), 1, "{}", option);
+                assert!(describe(&err).contains(path), "{}", describe(&err));
+            }
+        }
+
+        #[test]
+        fn test_args_rejects_token_pattern_mismatch() {
+            for argv in [
+                vec!["word_count", "--tokenizer", "regex", "in.txt"],
+                vec!["word_count", "--token-pattern", "x+", "in.txt"],
+                vec![
+                    "word_count",
+                    "--tokenizer",
+                    "regex",
+                    "--token-pattern",
+                    "(",
+                    "in.txt",
+                ],
+            ] {
+                // When
+                let err = Args::try_parse_from(&argv).unwrap().config().unwrap_err();
+
+                // Then
+                assert_eq!(usage(err).exit_code(), 2, "{:?}", argv);
+            }
+        }
+
+        #[test]
+        fn test_args_enables_approximate_mode() {
+            // Given
+            let plain = Args::try_parse_from(["word_count", "in.txt"]).unwrap();
+            let approx = Args::try_parse_from(["word_count", "--approx", "in.txt"]).unwrap();
+            let tuned = Args::try_parse_from([
+                "word_count",
+                "--cms-epsilon",
+                "0.001",
+                "--heavy-hitters",
+                "50",
+                "--merge-sketch",
+                "a.json",
+                "--merge-sketch",
+                "b.json",
+            ])
+            .unwrap();
+
+            // Then
+            assert_eq!(plain.config().unwrap().approx, None);
+            assert_eq!(
+                approx.config().unwrap().approx,
+                Some(SketchConfig::default())
+            );
+            let sketch_config = tuned.config().unwrap().approx.unwrap();
+            assert_eq!(sketch_config.cms_epsilon, 0.001);
+            assert_eq!(sketch_config.heavy_hitters, 50);
+            assert_eq!(sketch_config.hll_error, SketchConfig::default().hll_error);
+            assert_eq!(
+                sketch_config.merge,
+                vec![PathBuf::from("a.json"), PathBuf::from("b.json")]
+            );
+            assert!(tuned.inputs.is_empty());
+        }
+
+        #[test]
+        fn test_args_rejects_error_bounds_outside_unit_interval() {
+            for value in ["0", "1", "1.5", "-0.1", "abc"] {
+                // When
+                let result = Args::try_parse_from(["word_count", "--hll-error", value, "in.txt"]);
+
+                // Then
+                assert!(result.is_err(), "{}", value);
+            }
+        }
+
+        #[test]
+        fn test_args_rejects_tiny_cms_epsilon() {
+            for value in ["1e-300", "0.0000009"] {
+                // When
+                let result = Args::try_parse_from(["word_count", "--cms-epsilon", value, "in"]);
+
+                // Then
+                assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+            }
+            assert!(Args::try_parse_from(["word_count", "--cms-epsilon", "1e-6", "in"]).is_ok());
+        }
+
+        #[test]
+        fn test_args_selects_ngrams() {
+            // Given
+            let unigrams = Args::try_parse_from(["word_count", "-n", "1", "in.txt"]).unwrap();
+            let trigrams = Args::try_parse_from([
+                "word_count",
+                "--ngrams",
+                "3",
+                "--ngram-boundary",
+                "line",
+                "in.txt",
+            ])
+            .unwrap();
+
+            // Then
+            assert_eq!(unigrams.config().unwrap().ngrams, None);
+            assert_eq!(
+                trigrams.config().unwrap().ngrams,
+                Some(NgramConfig {
+                    n: 3,
+                    boundary: Boundary::Line
+                })
+            );
+        }
+
+        #[test]
+        fn test_args_rejects_ngrams_with_approx() {
+            // Given
+            let argv = ["word_count", "-n", "2", "--approx", "in.txt"];
+
+            // When
+            let err = Args::try_parse_from(argv).unwrap().config().unwrap_err();
+
+            // Then
+            assert_eq!(usage(err).kind(), ErrorKind::ArgumentConflict);
+        }
+
+        #[test]
+        fn test_args_selects_state_file_and_rejects_conflicts() {
+            // Given
+            let argv = ["word_count", "--state", "state.json", "in.txt"];
+
+            // When
+            let config = Args::try_parse_from(argv).unwrap().config().unwrap();
+
+            // Then
+            assert_eq!(config.state, Some(PathBuf::from("state.json")));
+            for conflict in [&["--approx"][..], &["-n", "2"], &["--export-chunks", "dir"]] {
+                let argv = ["word_count", "--state", "state.json", "in.txt"]
+                    .iter()
+                    .chain(conflict);
+                let err = Args::try_parse_from(argv).unwrap().config().unwrap_err();
+                assert_eq!(
+                    usage(err).kind(),
+                    ErrorKind::ArgumentConflict,
+                    "{:?}",
+                    conflict
+                );
+            }
+        }
+
+        #[test]
+        fn test_args_selects_follow_mode() {
+            // Given
+            let argv = [
+                "word_count",
+                "--follow",
+                "--follow-interval",
+                "0.5",
+                "--follow-updates",
+                "delta",
+                "app.log",
+            ];
+
+            // When
+            let config = Args::try_parse_from(argv).unwrap().config().unwrap();
+
+            // Then
+            assert_eq!(
+                config.follow,
+                Some(FollowConfig {
+                    interval: Duration::from_millis(500),
+                    updates: Updates::Delta,
+                })
+            );
+            assert!(
+                Args::try_parse_from(["word_count", "--follow-interval", "2", "in.txt"]).is_err()
+            );
+            let err = Args::try_parse_from(["word_count", "--follow", "--per-file", "in.txt"])
+                .unwrap()
+                .config()
+                .unwrap_err();
+            assert_eq!(usage(err).kind(), ErrorKind::ArgumentConflict);
+        }
+
+        #[test]
+        fn test_args_selects_csv_column_or_json_field() {
+            // Given
+            let csv = [
+                "word_count",
+                "--csv-column",
+                "2",
+                "--csv-delimiter",
+                "\\t",
+                "--csv-no-header",
+                "data.tsv",
+            ];
+            let json = ["word_count", "--json-field", "/msg/text", "events.ndjson"];
+
+            // When
+            let csv_config = Args::try_parse_from(csv).unwrap().config().unwrap();
+            let json_config = Args::try_parse_from(json).unwrap().config().unwrap();
+
+            // Then
+            assert_eq!(
+                csv_config.fields,
+                Some(FieldSelector::Csv {
+                    column: Column::Index(1),
+                    delimiter: b'\t',
+                    header: false,
+                })
+            );
+            assert_eq!(
+                json_config.fields,
+                Some(FieldSelector::Json {
+                    pointer: "/msg/text".to_string()
+                })
+            );
+            for (argv, kind) in [
+                (
+                    &[
+                        "word_count",
+                        "--csv-column",
+                        "text",
+                        "--csv-no-header",
+                        "in",
+                    ][..],
+                    ErrorKind::ArgumentConflict,
+                ),
+                (
+                    &["word_count", "--csv-column", "text", "--ngrams", "2", "in"],
+                    ErrorKind::ArgumentConflict,
+                ),
+                (
+                    &["word_count", "--json-field", "msg", "in"],
+                    ErrorKind::ValueValidation,
+                ),
+                (
+                    &[
+                        "word_count",
+                        "--csv-column",
+                        "1",
+                        "--json-field",
+                        "/a",
+                        "in",
+                    ],
+                    ErrorKind::ArgumentConflict,
+                ),
+                (
+                    &[
+                        "word_count",
+                        "--csv-column",
+                        "1",
+                        "--csv-delimiter",
+                        ";;",
+                        "in",
+                    ],
+                    ErrorKind::ValueValidation,
+                ),
+            ] {
+                let err = Args::try_parse_from(argv)
+                    .map_err(ArgsError::from)
+                    .and_then(|args| args.config())
+                    .unwrap_err();
+                assert_eq!(usage(err).kind(), kind, "{:?}", argv);
+            }
+        }
+
+        #[test]
+        fn test_args_selects_time_buckets() {
+            // Given
+            let argv = [
+                "word_count",
+                "--buckets",
+                "hour",
+                "--timestamp-format",
+                "[%Y-%m-%d %H:%M:%S]",
+                "--trending",
+                "3",
+                "app.log",
+            ];
+
+            // When
+            let config = Args::try_parse_from(argv).unwrap().config().unwrap();
+
+            // Then
+            let timeline = config.timeline.unwrap();
+            assert_eq!(timeline.bucket, BucketSize::Hour);
+            assert_eq!(timeline.trending, 3);
+            assert_eq!(timeline.format.to_string(), "[%Y-%m-%d %H:%M:%S]");
+            let bad_format = [
+                "word_count",
+                "--buckets",
+                "day",
+                "--timestamp-format",
+                "%Q",
+                "in",
+            ];
+            assert_eq!(
+                Args::try_parse_from(bad_format).unwrap_err().kind(),
+                ErrorKind::ValueValidation
+            );
+            let err = Args::try_parse_from(["word_count", "--buckets", "day", "--approx", "in"])
+                .unwrap()
+                .config()
+                .unwrap_err();
+            assert_eq!(usage(err).kind(), ErrorKind::ArgumentConflict);
+        }
+
+        #[test]
+        fn test_args_builds_stop_words() {
+            // Given
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            let list = temp_dir.path().join("stop.txt");
+            std::fs::write(&list, "Foo\n").unwrap();
+            let argv = [
+                "word_count",
+                "--stop-words",
+                "english",
+                "--stop-words-file",
+                list.to_str().unwrap(),
+                "--exclude",
+                "^[0-9]+$",
+                "in.txt",
+            ];
+
+            // When
+            let config = Args::try_parse_from(argv).unwrap().config().unwrap();
+
+            // Then
+            assert!(config.stop_words.contains("the"));
+            assert!(config.stop_words.contains("foo"));
+            assert!(config.stop_words.contains("2024"));
+            assert!(!config.stop_words.contains("cat"));
+            assert!(Args::try_parse_from(["word_count", "in.txt"])
+                .unwrap()
+                .config()
+                .unwrap()
+                .stop_words
+                .is_empty());
+        }
+
+        #[test]
+        fn test_args_rejects_bad_exclude_pattern() {
+            // Given
+            let argv = ["word_count", "--exclude", "(", "in.txt"];
+
+            // When
+            let err = Args::try_parse_from(argv).unwrap().config().unwrap_err();
+
+            // Then
+            assert_eq!(usage(err).exit_code(), 2);
+        }
+
+        #[test]
+        fn test_args_builds_stemmer() {
+            // Given
+            let plain = Args::try_parse_from(["word_count", "in.txt"]).unwrap();
+            let stemmed = Args::try_parse_from([
+                "word_count",
+                "--stem",
+                "english",
+                "--lemmatize",
+                "--surface-forms",
+                "in.txt",
+            ])
+            .unwrap();
+
+            // When
+            let stemmer = stemmed.config().unwrap().stemmer;
+
+            // Then
+            assert!(!plain.config().unwrap().stemmer.is_enabled());
+            assert_eq!(stemmer.language, Some(StemLanguage::English));
+            assert!(stemmer.surface_forms);
+            assert_eq!(stemmer.stem("went"), "go");
+            assert_eq!(stemmer.stem("running"), "run");
+        }
+
+        #[test]
+        fn test_args_rejects_surface_forms_without_stemming() {
+            for argv in [
+                vec!["word_count", "--surface-forms", "in.txt"],
+                vec![
+                    "word_count",
+                    "--stem",
+                    "english",
+                    "--surface-forms",
+                    "--approx",
+                    "in.txt",
+                ],
+            ] {
+                // When
+                let err = Args::try_parse_from(&argv).unwrap().config().unwrap_err();
+
+                // Then
+                assert_eq!(usage(err).kind(), ErrorKind::ArgumentConflict, "{:?}", argv);
+            }
+        }
+
+        #[test]
+        fn test_args_selects_progress_and_verbosity() {
+            // Given
+            let default = Args::try_parse_from(["word_count", "in.txt"]).unwrap();
+            let verbose =
+                Args::try_parse_from(["word_count", "-vv", "--progress", "json", "in.txt"])
+                    .unwrap();
+            let quiet = Args::try_parse_from(["word_count", "-q", "in.txt"]).unwrap();
+
+            // Then
+            assert_eq!(default.progress(), ProgressConfig::default());
+            assert_eq!(
+                verbose.progress(),
+                ProgressConfig {
+                    mode: ProgressMode::Json,
+                    level: Level::Trace,
+                }
+            );
+            assert_eq!(quiet.progress().level, Level::Error);
+            assert!(Args::try_parse_from(["word_count", "-q", "-v", "in.txt"]).is_err());
+        }
+
+        #[test]
+        fn test_args_builds_walk_config() {
+            // Given
+            let args = Args::try_parse_from([
+                "word_count",
+                "--include",
+                "*.txt",
+                "--include",
+                "*.md",
+                "--exclude-glob",
+                "target",
+                "--gitignore",
+                "--per-file",
+                "docs",
+            ])
+            .unwrap();
+
+            // When
+            let config = args.config().unwrap();
+
+            // Then
+            assert_eq!(config.walk.include, ["*.txt", "*.md"]);
+            assert_eq!(config.walk.exclude, ["target"]);
+            assert!(config.walk.gitignore);
+            assert!(config.report.per_file);
+        }
+
+        #[test]
+        fn test_args_rejects_bad_globs_and_per_file_with_approx() {
+            for (argv, kind) in [
+                (
+                    vec!["word_count", "--include", "a[", "docs"],
+                    ErrorKind::ValueValidation,
+                ),
+                (
+                    vec!["word_count", "--exclude-glob", "{a", "docs"],
+                    ErrorKind::ValueValidation,
+                ),
+                (
+                    vec!["word_count", "--per-file", "--approx", "docs"],
+                    ErrorKind::ArgumentConflict,
+                ),
+            ] {
+                // When
+                let err = Args::try_parse_from(&argv).unwrap().config().unwrap_err();
+
+                // Then
+                assert_eq!(usage(err).kind(), kind, "{:?}", argv);
+            }
+        }
+    }
+}
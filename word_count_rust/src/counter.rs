@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::encoding::{InvalidLines, Utf8Policy};
+use crate::error::{at_path, WordCountError};
+use crate::format::{Footer, Format};
+use crate::normalize::{CaseMode, Form, Normalizer};
+use crate::progress::info;
+use crate::stem::{StemLanguage, Stemmer};
+use crate::stopwords::{StopList, StopWords};
+use crate::tokenizer::{Tokenizer, TokenizerKind};
+use crate::topk::{self, Order};
+use crate::{
+    count_inputs, create_output, default_threads, stream, walk, write_file_results_to,
+    write_results_to, Config, Output, Report,
+};
+
+/// Counts words with the same pipeline as the command line, configured in code.
+///
+/// Every setting has the command line's default; [`WordCounter::count`] returns the counts and
+/// [`WordCounter::run`] also writes them to the [`Output`] in the chosen format.
+///
+/// ```
+/// use word_count_rust::{StopList, WordCounter};
+///
+/// let result = WordCounter::new()
+///     .threads(2)
+///     .stop_words(StopList::English)
+///     .count_text("The cat and the hat. The end.")?;
+/// assert_eq!(result.get("cat"), 1);
+/// assert_eq!(result.get("the"), 0);
+/// assert_eq!(result.top(1)?, vec![("cat", 1)]);
+/// # Ok::<(), word_count_rust::WordCountError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct WordCounter {
+    threads: usize,
+    tokenizer: TokenizerKind,
+    token_pattern: Option<String>,
+    custom_tokenizer: Option<Arc<dyn Tokenizer>>,
+    normalizer: Normalizer,
+    stop_lists: Vec<StopList>,
+    stop_word_files: Vec<PathBuf>,
+    exclude: Vec<String>,
+    stem: Option<StemLanguage>,
+    invalid_utf8: Utf8Policy,
+    state: Option<PathBuf>,
+    report: Report,
+    output: Output,
+}
+
+impl Default for WordCounter {
+    fn default() -> Self {
+        WordCounter {
+            threads: default_threads(),
+            tokenizer: TokenizerKind::default(),
+            token_pattern: None,
+            custom_tokenizer: None,
+            normalizer: Normalizer::default(),
+            stop_lists: Vec::new(),
+            stop_word_files: Vec::new(),
+            exclude: Vec::new(),
+            stem: None,
+            invalid_utf8: Utf8Policy::default(),
+            state: None,
+            report: Report::default(),
+            output: Output::Stdout,
+        }
+    }
+}
+
+impl WordCounter {
+    pub fn new() -> Self {
+        WordCounter::default()
+    }
+
+    /// Number of worker threads; one per core by default.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// One of the built-in tokenizers.
+    pub fn tokenizer(mut self, tokenizer: TokenizerKind) -> Self {
+        self.tokenizer = tokenizer;
+        self.custom_tokenizer = None;
+        self
+    }
+
+    /// Counts every match of `pattern`, like `--tokenizer regex --token-pattern`.
+    pub fn token_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.tokenizer = TokenizerKind::Regex;
+        self.token_pattern = Some(pattern.into());
+        self.custom_tokenizer = None;
+        self
+    }
+
+    /// A tokenizer of your own instead of a built-in one.
+    pub fn custom_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.custom_tokenizer = Some(Arc::new(tokenizer));
+        self
+    }
+
+    /// How letter case is treated.
+    pub fn case(mut self, case: CaseMode) -> Self {
+        self.normalizer.case = case;
+        self
+    }
+
+    /// Unicode normalization form applied to every word.
+    pub fn normalize(mut self, form: Form) -> Self {
+        self.normalizer.form = form;
+        self
+    }
+
+    /// Leaves the words of a bundled list out of the results.
+    pub fn stop_words(mut self, list: StopList) -> Self {
+        self.stop_lists.push(list);
+        self
+    }
+
+    /// Leaves the words of a list file, one per line, out of the results.
+    pub fn stop_words_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stop_word_files.push(path.into());
+        self
+    }
+
+    /// Leaves the words matching a regular expression out of the results.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Folds inflected forms together under their Snowball stem.
+    pub fn stem(mut self, language: StemLanguage) -> Self {
+        self.stem = Some(language);
+        self
+    }
+
+    /// What happens to lines that are not valid UTF-8.
+    pub fn invalid_utf8(mut self, policy: Utf8Policy) -> Self {
+        self.invalid_utf8 = policy;
+        self
+    }
+
+    /// Remembers the counts of regular input files in the state file at `path`, so that later
+    /// counts with the same file only read what was appended; see `--state`.
+    pub fn state(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state = Some(path.into());
+        self
+    }
+
+    /// Only reports the `k` most frequent words, most frequent first unless `order` says
+    /// otherwise.
+    pub fn top(mut self, k: usize) -> Self {
+        self.report.top = Some(k);
+        self.report.order = Order::Count;
+        self
+    }
+
+    /// Order of the written results.
+    pub fn order(mut self, order: Order) -> Self {
+        self.report.order = order;
+        self
+    }
+
+    /// Format of the written results.
+    pub fn format(mut self, format: Format) -> Self {
+        self.report.format = format;
+        self
+    }
+
+    /// Keeps the counts of each input apart.
+    pub fn per_file(mut self, per_file: bool) -> Self {
+        self.report.per_file = per_file;
+        self
+    }
+
+    /// Where [`WordCounter::run`] writes the results.
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Counts the words of `inputs`; directories are walked, `-` is stdin.
+    pub fn count<P: AsRef<Path>>(&self, inputs: &[P]) -> Result<CountResult, WordCountError> {
+        let config = self.config()?;
+        let inputs: Vec<PathBuf> = inputs.iter().map(|path| path.as_ref().into()).collect();
+        let input_files = walk::expand_inputs(&inputs, &config.walk)?;
+        count_inputs(&input_files, &config)
+    }
+
+    /// Counts the words of `text`.
+    pub fn count_text(&self, text: &str) -> Result<CountResult, WordCountError> {
+        let config = self.config()?;
+        let mut counts = stream::count_stream(Cursor::new(text.as_bytes()), &config)?;
+        let filtered = config.stop_words.remove_from(&mut counts, false);
+        Ok(CountResult {
+            counts: config.stemmer.apply(counts, false),
+            files: None,
+            filtered: (!config.stop_words.is_empty()).then_some(filtered),
+            // A `&str` is valid UTF-8 throughout.
+            invalid_lines: 0,
+            report: config.report,
+        })
+    }
+
+    /// Counts the words of `inputs` and writes them to the output.
+    pub fn run<P: AsRef<Path>>(&self, inputs: &[P]) -> Result<CountResult, WordCountError> {
+        let result = self.count(inputs)?;
+        result.write(&self.output)?;
+        Ok(result)
+    }
+
+    fn config(&self) -> Result<Config, WordCountError> {
+        let tokenizer = match &self.custom_tokenizer {
+            Some(tokenizer) => Arc::clone(tokenizer),
+            None => self
+                .tokenizer
+                .build(self.token_pattern.as_deref())
+                .map_err(WordCountError::Config)?,
+        };
+        let mut stop_words = StopWords::default();
+        for &list in &self.stop_lists {
+            stop_words.add_list(list, tokenizer.as_ref(), &self.normalizer);
+        }
+        for path in &self.stop_word_files {
+            stop_words
+                .load(path, tokenizer.as_ref(), &self.normalizer)
+                .map_err(at_path(path))?;
+        }
+        for pattern in &self.exclude {
+            stop_words
+                .add_pattern(pattern)
+                .map_err(WordCountError::Config)?;
+        }
+        Ok(Config {
+            num_threads: self.threads,
+            invalid_utf8: self.invalid_utf8,
+            invalid_lines: InvalidLines::default(),
+            state: self.state.clone(),
+            tokenizer,
+            normalizer: self.normalizer,
+            report: Report {
+                threads: self.threads,
+                ..self.report
+            },
+            stop_words: Arc::new(stop_words),
+            stemmer: Arc::new(Stemmer {
+                language: self.stem,
+                ..Stemmer::default()
+            }),
+            ..Config::default()
+        })
+    }
+}
+
+/// The counts of a run, after stop words and stemming, with the report settings of the
+/// counter that made them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountResult {
+    pub(crate) counts: HashMap<String, usize>,
+    /// The counts of each input in order, when they are kept apart.
+    pub(crate) files: Option<Vec<(PathBuf, HashMap<String, usize>)>>,
+    /// Occurrences of stop words left out, when stop words are in use.
+    pub(crate) filtered: Option<usize>,
+    pub(crate) invalid_lines: u64,
+    pub(crate) report: Report,
+}
+
+impl CountResult {
+    /// How often `word` was counted, in its normalized form; 0 if never.
+    pub fn get(&self, word: &str) -> usize {
+        self.counts.get(word).copied().unwrap_or(0)
+    }
+
+    /// The number of words counted.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// The number of different words.
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// The `k` most frequent words, most frequent first, ties in word order. Fails only when a
+    /// thread selecting them panicked.
+    pub fn top(&self, k: usize) -> Result<Vec<(&str, usize)>, WordCountError> {
+        let threads = self.report.threads;
+        Ok(topk::top_entries(&self.counts, k, Order::Count, threads)?)
+    }
+
+    /// Every word with its count, in `order`.
+    pub fn sorted(&self, order: Order) -> Vec<(&str, usize)> {
+        topk::sorted_entries(&self.counts, order)
+    }
+
+    /// The counts of all inputs together.
+    pub fn counts(&self) -> &HashMap<String, usize> {
+        &self.counts
+    }
+
+    /// The counts of each input, in the order they were given, when they were kept apart with
+    /// [`WordCounter::per_file`].
+    pub fn files(&self) -> Option<&[(PathBuf, HashMap<String, usize>)]> {
+        self.files.as_deref()
+    }
+
+    /// The counts of one input, when they were kept apart.
+    pub fn file(&self, path: &Path) -> Option<&HashMap<String, usize>> {
+        self.files()?
+            .iter()
+            .find(|(file, _)| file == path)
+            .map(|(_, counts)| counts)
+    }
+
+    /// Occurrences of stop words that were left out, when stop words are in use.
+    pub fn filtered(&self) -> Option<usize> {
+        self.filtered
+    }
+
+    /// Lines that were not valid UTF-8 and were skipped or decoded some other way.
+    pub fn invalid_lines(&self) -> u64 {
+        self.invalid_lines
+    }
+
+    pub fn into_counts(self) -> HashMap<String, usize> {
+        self.counts
+    }
+
+    /// Writes the results as the counter's format, order and top-k say.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let footer = Footer {
+            filtered: self.filtered,
+        };
+        match &self.files {
+            Some(files) => write_file_results_to(writer, files, &self.report, &footer),
+            None => write_results_to(writer, &self.counts, &self.report, &footer),
+        }
+    }
+
+    /// Writes the results to `output`.
+    pub(crate) fn write(&self, output: &Output) -> Result<(), WordCountError> {
+        match output {
+            Output::Stdout => self.write_to(io::stdout().lock())?,
+            Output::File(output_path) => {
+                create_output(output_path)
+                    .and_then(|writer| self.write_to(writer))
+                    .map_err(at_path(output_path))?;
+                info!("Results written to {}", output_path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    mod test_word_counter {
+        use super::*;
+
+        #[test]
+        fn test_count_matches_command_line_defaults() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "One two, three.\nTWO three\n\nthree!\n").unwrap();
+
+            // When
+            let result = WordCounter::new().count(&[&input_path]).unwrap();
+
+            // Then
+            assert_eq!(result.get("three"), 3);
+            assert_eq!(result.get("two"), 2);
+            assert_eq!(result.get("missing"), 0);
+            assert_eq!(result.total(), 6);
+            assert_eq!(result.distinct(), 3);
+            assert_eq!(result.top(2).unwrap(), vec![("three", 3), ("two", 2)]);
+            assert_eq!(
+                result.sorted(Order::Word),
+                vec![("one", 1), ("three", 3), ("two", 2)]
+            );
+            assert_eq!(result.filtered(), None);
+        }
+
+        #[test]
+        fn test_count_text_applies_tokenizer_and_filters() {
+            // When
+            let result = WordCounter::new()
+                .tokenizer(TokenizerKind::Words)
+                .case(CaseMode::Preserve)
+                .stop_words(StopList::English)
+                .exclude("^[0-9]+$")
+                .count_text("the cat's 9 lives\nthe Cat")
+                .unwrap();
+
+            // Then
+            assert_eq!(result.counts().len(), 3);
+            assert_eq!(result.get("cat's"), 1);
+            assert_eq!(result.get("Cat"), 1);
+            assert_eq!(result.get("lives"), 1);
+            assert_eq!(result.filtered(), Some(3));
+        }
+
+        #[test]
+        fn test_top_selects_on_the_configured_threads() {
+            // When
+            let result = WordCounter::new()
+                .threads(3)
+                .count_text("b a b c b a")
+                .unwrap();
+
+            // Then
+            assert_eq!(result.report.threads, 3);
+            assert_eq!(result.top(2).unwrap(), vec![("b", 3), ("a", 2)]);
+        }
+
+        #[test]
+        fn test_count_keeps_files_apart_and_writes_them() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let first = temp_dir.path().join("first.txt");
+            let second = temp_dir.path().join("second.txt");
+            fs::write(&first, "apple banana apple").unwrap();
+            fs::write(&second, "banana cherry").unwrap();
+            let output_path = temp_dir.path().join("out.json");
+            let counter = WordCounter::new()
+                .per_file(true)
+                .format(Format::Json)
+                .output(Output::File(output_path.clone()));
+
+            // When
+            let result = counter.run(&[&first, &second]).unwrap();
+
+            // Then
+            assert_eq!(result.get("banana"), 2);
+            assert_eq!(result.file(&first).unwrap().get("apple"), Some(&2));
+            assert_eq!(result.file(&second).unwrap().get("apple"), None);
+            let written: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+            assert_eq!(written[second.display().to_string()]["cherry"], 1);
+        }
+
+        #[test]
+        fn test_concurrent_counts_keep_their_invalid_lines_apart() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let invalid = temp_dir.path().join("invalid.txt");
+            let valid = temp_dir.path().join("valid.txt");
+            fs::write(&invalid, b"ok\nbad \xff\nworse \xfe\n").unwrap();
+            fs::write(&valid, "fine words\n").unwrap();
+            let counter = WordCounter::new().invalid_utf8(Utf8Policy::Skip);
+
+            // When
+            let results: Vec<_> = std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..16)
+                    .map(|i| {
+                        let (counter, path) = (&counter, [&invalid, &valid][i % 2]);
+                        scope.spawn(move || counter.count(&[path]).unwrap().invalid_lines())
+                    })
+                    .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            });
+
+            // Then
+            for (i, invalid_lines) in results.into_iter().enumerate() {
+                assert_eq!(invalid_lines, [2, 0][i % 2], "run {}", i);
+            }
+        }
+
+        #[test]
+        fn test_count_with_stemming_and_custom_tokenizer() {
+            // Given
+            #[derive(Debug)]
+            struct Commas;
+            impl Tokenizer for Commas {
+                fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+                    text.split(',').map(str::trim).for_each(emit);
+                }
+            }
+
+            // When
+            let result = WordCounter::new()
+                .custom_tokenizer(Commas)
+                .stem(StemLanguage::English)
+                .count_text("running fast,runs,ran")
+                .unwrap();
+
+            // Then
+            assert_eq!(result.get("running fast"), 1);
+            assert_eq!(result.get("run"), 1);
+            assert_eq!(result.get("ran"), 1);
+        }
+
+        #[test]
+        fn test_count_reports_configuration_and_input_errors() {
+            // When
+            let bad_pattern = WordCounter::new().token_pattern("(").count_text("x");
+            let missing_list = WordCounter::new()
+                .stop_words_file("/nonexistent/stop.txt")
+                .count_text("x");
+
+            // Then
+            assert!(matches!(bad_pattern, Err(WordCountError::Config(_))));
+            assert!(matches!(
+                missing_list,
+                Err(WordCountError::Io { path: Some(_), .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use memmap2::Mmap;
+
+use crate::encoding::{self, InvalidLines, Utf8Policy};
+use crate::error::WordCountError;
+use crate::mapped::{normalize_counts, tally_raw_words};
+use crate::merge::{merge_counts, tree_reduce};
+use crate::normalize::Normalizer;
+use crate::progress::{self, debug};
+use crate::tokenizer::Tokenizer;
+use crate::{stream, Config};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+/// Longest magic number we look for.
+const MAGIC_LEN: usize = 4;
+/// Size of the buffer each worker decompresses into.
+const DECODE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Compression format of an input, recognised by its magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+pub(crate) fn detect_compression(magic: &[u8]) -> Compression {
+    if magic.starts_with(GZIP_MAGIC) {
+        Compression::Gzip
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Compression::Zstd
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Compression::Bzip2
+    } else {
+        Compression::None
+    }
+}
+
+/// Reads the first bytes of `path` and reports its compression format.
+pub(crate) fn detect_file_compression(path: &Path) -> io::Result<Compression> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    File::open(path)?
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    Ok(detect_compression(&magic))
+}
+
+/// Wraps `reader` in the decoder its magic bytes call for; plain text is passed through.
+pub(crate) fn decompressing_reader<R: Read + Send + 'static>(
+    mut reader: R,
+) -> io::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    (&mut reader)
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    let compression = detect_compression(&magic);
+    // The peeked bytes are put back in front of the rest of the stream.
+    let reader = Cursor::new(magic).chain(reader);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+    })
+}
+
+/// Counts the words of a compressed file.
+///
+/// Multi-member gzip files and zstd files made of several frames are split into their
+/// members/frames, and each one is decompressed and counted by a separate worker. Everything
+/// else, and UTF-16 text, is decompressed on one thread and fed to the `stream::count_stream`
+/// workers.
+pub(crate) fn count_compressed_file(
+    input_path: &Path,
+    compression: Compression,
+    config: &Config,
+) -> io::Result<HashMap<String, usize>> {
+    let file = File::open(input_path)?;
+    if encoding::starts_with_utf16_bom(decompressing_reader(File::open(input_path)?)?)? {
+        return stream::count_stream(decompressing_reader(file)?, config);
+    }
+    // SAFETY: read-only mapping that lives until the end of this function; the input must not
+    // be truncated while it is being counted.
+    let mmap = unsafe { Mmap::map(&file)? };
+
+    let layout = match compression {
+        Compression::Gzip => bgzf_blocks(&mmap)
+            .map(FrameLayout::Exact)
+            .unwrap_or_else(|| FrameLayout::Candidates(gzip_member_candidates(&mmap))),
+        Compression::Zstd => zstd_frames(&mmap)
+            .map(FrameLayout::Exact)
+            .unwrap_or(FrameLayout::Unsplittable),
+        Compression::Bzip2 | Compression::None => FrameLayout::Unsplittable,
+    };
+
+    let word_count = match layout {
+        FrameLayout::Exact(frames) if frames.len() > 1 => {
+            let starts = frames.iter().map(|frame| frame.start).collect();
+            count_frames(&mmap, compression, starts, true, config)?
+        }
+        FrameLayout::Candidates(starts) if starts.len() > 1 => {
+            count_frames(&mmap, compression, starts, false, config)?
+        }
+        _ => None,
+    };
+
+    match word_count {
+        Some(word_count) => Ok(word_count),
+        None => {
+            // A single frame, or members we could not delimit: decompress serially instead.
+            drop(mmap);
+            stream::count_stream(decompressing_reader(file)?, config)
+        }
+    }
+}
+
+/// Where the independently decodable frames of a compressed file start.
+enum FrameLayout {
+    /// Frame boundaries read from the container format itself.
+    Exact(Vec<Range<usize>>),
+    /// Offsets that look like gzip member headers; some may be false positives.
+    Candidates(Vec<usize>),
+    Unsplittable,
+}
+
+/// Splits a BGZF file (gzip members carrying their own size in a "BC" extra field) into members.
+fn bgzf_blocks(data: &[u8]) -> Option<Vec<Range<usize>>> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 18)?;
+        let has_extra = header[3] & 0x04 != 0;
+        let extra_len = u16::from_le_bytes([header[10], header[11]]);
+        let is_bc = &header[12..14] == b"BC" && u16::from_le_bytes([header[14], header[15]]) == 2;
+        if !header.starts_with(GZIP_MAGIC) || !has_extra || extra_len < 6 || !is_bc {
+            return None;
+        }
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+        blocks.push(pos..pos + block_size);
+        pos += block_size;
+    }
+    (pos == data.len()).then_some(blocks)
+}
+
+/// Offsets that start with a plausible gzip member header (magic, deflate, no reserved flags).
+fn gzip_member_candidates(data: &[u8]) -> Vec<usize> {
+    data.windows(4)
+        .enumerate()
+        .filter(|(_, w)| w[0] == 0x1f && w[1] == 0x8b && w[2] == 8 && w[3] & 0xe0 == 0)
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+/// Splits a zstd file into its frames by walking the frame and block headers.
+fn zstd_frames(data: &[u8]) -> Option<Vec<Range<usize>>> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        // Also covers skippable frames, such as the seek table of the seekable format.
+        let size = zstd::zstd_safe::find_frame_compressed_size(&data[pos..]).ok()?;
+        if size == 0 {
+            return None;
+        }
+        frames.push(pos..pos + size);
+        pos += size;
+    }
+    Some(frames)
+}
+
+/// What a worker learned from decoding the frame at one start offset.
+struct DecodedFrame {
+    /// Offset just past the frame in the compressed data.
+    end: usize,
+    /// Decompressed size of the frame.
+    len: u64,
+    /// Bytes before the first newline, or `None` if the frame contains no newline at all.
+    head: Option<Vec<u8>>,
+    /// Bytes after the last newline (the whole frame if it contains no newline).
+    tail: Vec<u8>,
+    /// Counts of the complete lines; empty if they were already merged into the worker's map.
+    counts: HashMap<String, usize>,
+    /// Offset inside the decompressed frame of the first invalid UTF-8 byte, if any.
+    invalid_utf8_at: Option<u64>,
+}
+
+/// Decodes and counts the frames starting at `starts` on `config.num_threads` workers, then
+/// joins the lines that straddle frame boundaries.
+///
+/// With `exact` starts every frame is known to be real and its counts go straight into the
+/// worker's map. Candidate starts are only trusted once the chain of frames, each starting where
+/// the previous one ended, reaches them; `None` is returned if that chain does not cover the file.
+fn count_frames(
+    data: &[u8],
+    compression: Compression,
+    starts: Vec<usize>,
+    exact: bool,
+    config: &Config,
+) -> io::Result<Option<HashMap<String, usize>>> {
+    let next_frame = AtomicUsize::new(0);
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let invalid_utf8 = config.invalid_utf8;
+    let num_workers = config.num_threads.min(starts.len());
+
+    let (mut decoded, worker_counts) = thread::scope(|scope| -> io::Result<_> {
+        let handles: Vec<_> = (0..num_workers)
+            .map(|i| {
+                let (starts, next_frame) = (&starts, &next_frame);
+                scope.spawn(move || {
+                    debug!("Thread {} started", i);
+                    let mut word_count = HashMap::new();
+                    let mut frames = Vec::new();
+                    loop {
+                        let index = next_frame.fetch_add(1, Ordering::Relaxed);
+                        let Some(&start) = starts.get(index) else {
+                            break;
+                        };
+                        let end = if exact {
+                            starts.get(index + 1).copied()
+                        } else {
+                            None
+                        };
+                        let mut frame = decode_frame(data, compression, start, end, config);
+                        if let (Ok(frame), true) = (&mut frame, exact) {
+                            merge_counts(&mut word_count, std::mem::take(&mut frame.counts));
+                        }
+                        frames.push((start, frame));
+                    }
+                    debug!("Thread {} finished {} frames", i, frames.len());
+                    (frames, word_count)
+                })
+            })
+            .collect();
+
+        let mut decoded = HashMap::new();
+        let mut worker_counts = Vec::with_capacity(num_workers);
+        for (i, handle) in handles.into_iter().enumerate() {
+            let (frames, word_count) = handle
+                .join()
+                .map_err(|e| WordCountError::worker_panic(format!("Thread {}", i), e))?;
+            decoded.extend(frames);
+            worker_counts.push(word_count);
+        }
+        Ok((decoded, worker_counts))
+    })?;
+
+    let mut word_count = tree_reduce(worker_counts)?;
+    let mut line = Vec::new();
+    let mut line_offset = 0;
+    let mut offset = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        let frame = match decoded.remove(&pos) {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) if exact => return Err(e),
+            // The chain hit a corrupt member or an offset no candidate covers.
+            _ => return Ok(None),
+        };
+        // The line stitched onto this frame's head comes before the rest of the frame, so it is
+        // checked first and the lowest invalid offset is the one reported.
+        match frame.head {
+            Some(head) => {
+                line.extend_from_slice(&head);
+                count_line(
+                    &line,
+                    line_offset,
+                    tokenizer,
+                    normalizer,
+                    invalid_utf8,
+                    &config.invalid_lines,
+                    &mut word_count,
+                )?;
+                line = frame.tail;
+                line_offset = offset + frame.len - line.len() as u64;
+            }
+            None => line.extend_from_slice(&frame.tail),
+        }
+        if let Some(at) = frame.invalid_utf8_at {
+            return Err(WordCountError::invalid_utf8(offset + at).into());
+        }
+        merge_counts(&mut word_count, frame.counts);
+        offset += frame.len;
+        pos = frame.end;
+    }
+    count_line(
+        &line,
+        line_offset,
+        tokenizer,
+        normalizer,
+        invalid_utf8,
+        &config.invalid_lines,
+        &mut word_count,
+    )?;
+
+    debug!("All threads finished, merging results");
+    Ok(Some(word_count))
+}
+
+/// Decodes the single frame that starts at `start` and counts its complete lines.
+fn decode_frame(
+    data: &[u8],
+    compression: Compression,
+    start: usize,
+    end: Option<usize>,
+    config: &Config,
+) -> io::Result<DecodedFrame> {
+    let input = &data[start..end.unwrap_or(data.len())];
+    match compression {
+        Compression::Gzip => {
+            let mut decoder = flate2::bufread::GzDecoder::new(input);
+            let mut frame = count_decoded(
+                &mut decoder,
+                config.tokenizer.as_ref(),
+                &config.normalizer,
+                config.invalid_utf8,
+                &config.invalid_lines,
+            )?;
+            frame.end = start + input.len() - decoder.into_inner().len();
+            Ok(frame)
+        }
+        Compression::Zstd => {
+            let mut decoder = zstd::stream::read::Decoder::with_buffer(input)?.single_frame();
+            let mut frame = count_decoded(
+                &mut decoder,
+                config.tokenizer.as_ref(),
+                &config.normalizer,
+                config.invalid_utf8,
+                &config.invalid_lines,
+            )?;
+            frame.end = start + input.len();
+            Ok(frame)
+        }
+        Compression::Bzip2 | Compression::None => {
+            Err(io::Error::other("format cannot be split into frames"))
+        }
+    }
+}
+
+/// Counts the complete lines a decoder produces, keeping the partial first and last lines.
+fn count_decoded<R: Read>(
+    decoder: &mut R,
+    tokenizer: &dyn Tokenizer,
+    normalizer: &Normalizer,
+    invalid_utf8: Utf8Policy,
+    invalid_lines: &InvalidLines,
+) -> io::Result<DecodedFrame> {
+    let mut frame = DecodedFrame {
+        end: 0,
+        len: 0,
+        head: None,
+        tail: Vec::new(),
+        counts: HashMap::new(),
+        invalid_utf8_at: None,
+    };
+    let mut buffer = vec![0; DECODE_BUFFER_SIZE];
+    loop {
+        let bytes_read = match decoder.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if frame.invalid_utf8_at.is_some() {
+            // Keep decoding so the frame's end is still found, but stop counting.
+            frame.len += bytes_read as u64;
+            continue;
+        }
+        frame.tail.extend_from_slice(&buffer[..bytes_read]);
+        frame.len += bytes_read as u64;
+        progress::add_bytes(bytes_read as u64);
+
+        let Some(last_newline) = frame.tail.iter().rposition(|&b| b == b'\n') else {
+            continue;
+        };
+        let tail = frame.tail.split_off(last_newline + 1);
+        let mut lines = std::mem::replace(&mut frame.tail, tail);
+        let tail_len = frame.tail.len() as u64;
+        let mut lines_offset = frame.len - tail_len - lines.len() as u64;
+        if frame.head.is_none() {
+            let first_newline = lines.iter().position(|&b| b == b'\n').unwrap_or(0);
+            let rest = lines.split_off(first_newline + 1);
+            lines.pop(); // the newline itself
+            frame.head = Some(lines);
+            lines = rest;
+            lines_offset += first_newline as u64 + 1;
+        }
+        match encoding::decode(&lines, invalid_utf8, invalid_lines) {
+            Ok(text) => {
+                let mut raw_count = HashMap::new();
+                let words = tally_raw_words(&text, tokenizer, &mut raw_count);
+                progress::add_words(words as u64);
+                merge_counts(
+                    &mut frame.counts,
+                    normalize_counts(raw_count, tokenizer, normalizer),
+                );
+            }
+            Err(at) => frame.invalid_utf8_at = Some(lines_offset + at as u64),
+        }
+    }
+    Ok(frame)
+}
+
+/// Counts one line that was stitched together from the ends of neighbouring frames.
+fn count_line(
+    line: &[u8],
+    offset: u64,
+    tokenizer: &dyn Tokenizer,
+    normalizer: &Normalizer,
+    invalid_utf8: Utf8Policy,
+    invalid_lines: &InvalidLines,
+    word_count: &mut HashMap<String, usize>,
+) -> io::Result<()> {
+    let text = encoding::decode_at(line, offset, invalid_utf8, invalid_lines)?;
+    let mut raw_count = HashMap::new();
+    let words = tally_raw_words(&text, tokenizer, &mut raw_count);
+    progress::add_words(words as u64);
+    merge_counts(
+        word_count,
+        normalize_counts(raw_count, tokenizer, normalizer),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count_file;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    const TEXT: &str = "The quick brown fox\njumps over the lazy dog.\nThe END\n";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// One BGZF block: a gzip member whose "BC" extra field holds its total size minus one.
+    fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        let mut block = encoder.finish().unwrap();
+        let block_size = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&block_size.to_le_bytes());
+        block
+    }
+
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        zstd::encode_all(data, 1).unwrap()
+    }
+
+    fn bzip2(data: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Compresses `text` as one frame per piece, cutting it at arbitrary (not line) boundaries.
+    fn in_frames(text: &str, pieces: usize, compress: fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let bytes = text.as_bytes();
+        let piece_len = bytes.len().div_ceil(pieces);
+        bytes.chunks(piece_len).flat_map(compress).collect()
+    }
+
+    fn corpus() -> String {
+        (0..300)
+            .map(|i| {
+                format!(
+                    "Line {} of the corpus, word{} and Word{}!\n",
+                    i,
+                    i % 17,
+                    i % 5
+                )
+            })
+            .collect()
+    }
+
+    fn expected_counts(text: &str) -> HashMap<String, usize> {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("plain.txt");
+        fs::write(&path, text).unwrap();
+        count_file(&path, &Config::default()).unwrap()
+    }
+
+    fn count_bytes(data: &[u8], num_threads: usize) -> io::Result<HashMap<String, usize>> {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("input.bin");
+        fs::write(&path, data).unwrap();
+        let config = Config {
+            num_threads,
+            ..Config::default()
+        };
+        count_file(&path, &config)
+    }
+
+    mod test_detect_compression {
+        use super::*;
+
+        #[test]
+        fn test_detect_compression_by_magic_bytes() {
+            assert_eq!(detect_compression(&gzip(b"x")), Compression::Gzip);
+            assert_eq!(detect_compression(&zstd(b"x")), Compression::Zstd);
+            assert_eq!(detect_compression(&bzip2(b"x")), Compression::Bzip2);
+            assert_eq!(detect_compression(b"plain text"), Compression::None);
+            assert_eq!(detect_compression(b""), Compression::None);
+        }
+    }
+
+    mod test_frame_layouts {
+        use super::*;
+
+        #[test]
+        fn test_bgzf_blocks_splits_members() {
+            // Given
+            let first = bgzf_block(b"one\n");
+            let second = bgzf_block(b"two\n");
+            let data = [first.clone(), second.clone()].concat();
+
+            // When
+            let blocks = bgzf_blocks(&data).unwrap();
+
+            // Then
+            assert_eq!(blocks, vec![0..first.len(), first.len()..data.len()]);
+        }
+
+        #[test]
+        fn test_bgzf_blocks_rejects_plain_gzip() {
+            assert!(bgzf_blocks(&gzip(b"one\n")).is_none());
+        }
+
+        #[test]
+        fn test_gzip_member_candidates_finds_every_member() {
+            // Given
+            let first = gzip(b"one\n");
+            let data = [first.clone(), gzip(b"two\n")].concat();
+
+            // When
+            let candidates = gzip_member_candidates(&data);
+
+            // Then
+            assert!(candidates.contains(&0));
+            assert!(candidates.contains(&first.len()));
+        }
+
+        #[test]
+        fn test_zstd_frames_splits_frames() {
+            // Given
+            let first = zstd(b"one\n");
+            let data = [first.clone(), zstd(b"two\n"), zstd(b"three\n")].concat();
+
+            // When
+            let frames = zstd_frames(&data).unwrap();
+
+            // Then
+            assert_eq!(frames.len(), 3);
+            assert_eq!(frames[0], 0..first.len());
+            assert_eq!(frames[2].end, data.len());
+        }
+
+        #[test]
+        fn test_zstd_frames_rejects_truncated_input() {
+            // Given
+            let data = zstd(corpus().as_bytes());
+
+            // When
+            let frames = zstd_frames(&data[..data.len() - 3]);
+
+            // Then
+            assert!(frames.is_none());
+        }
+    }
+
+    mod test_count_compressed_file {
+        use super::*;
+
+        #[test]
+        fn test_count_single_member_formats() {
+            // Given
+            let expected = expected_counts(TEXT);
+
+            for (name, data) in [
+                ("gzip", gzip(TEXT.as_bytes())),
+                ("zstd", zstd(TEXT.as_bytes())),
+                ("bzip2", bzip2(TEXT.as_bytes())),
+            ] {
+                // When
+                let result = count_bytes(&data, 2).unwrap();
+
+                // Then
+                assert_eq!(result, expected, "Mismatch for {}", name);
+            }
+        }
+
+        #[test]
+        fn test_count_multi_frame_formats_across_line_boundaries() {
+            // Given
+            let text = corpus();
+            let expected = expected_counts(&text);
+
+            for pieces in [2, 3, 7, 40] {
+                for (name, data) in [
+                    ("gzip", in_frames(&text, pieces, gzip)),
+                    ("bgzf", in_frames(&text, pieces, bgzf_block)),
+                    ("zstd", in_frames(&text, pieces, zstd)),
+                    ("bzip2", in_frames(&text, pieces, bzip2)),
+                ] {
+                    for num_threads in [1, 4] {
+                        // When
+                        let result = count_bytes(&data, num_threads).unwrap();
+
+                        // Then
+                        assert_eq!(
+                            result, expected,
+                            "Mismatch for {} in {} frames on {} threads",
+                            name, pieces, num_threads
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_count_frames_without_any_newline() {
+            // Given
+            let text = "one two three four five six seven eight nine ten";
+            let data = in_frames(text, 5, zstd);
+
+            // When
+            let result = count_bytes(&data, 3).unwrap();
+
+            // Then
+            assert_eq!(result, expected_counts(text));
+        }
+
+        #[test]
+        fn test_count_frames_with_zstd_skippable_frame() {
+            // Given
+            let skippable = [&[0x50, 0x2a, 0x4d, 0x18, 4, 0, 0, 0][..], b"meta"].concat();
+            let data = [zstd(b"alpha beta\n"), skippable, zstd(b"beta gamma\n")].concat();
+
+            // When
+            let result = count_bytes(&data, 2).unwrap();
+
+            // Then
+            assert_eq!(result, expected_counts("alpha beta\nbeta gamma\n"));
+        }
+
+        #[test]
+        fn test_count_frames_reports_invalid_utf8_offset() {
+            // Given
+            let data = [zstd(b"first line\nsec"), zstd(b"ond \xff line\n")].concat();
+
+            // When
+            let err = count_bytes(&data, 2).unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("byte offset 18"), "{}", err);
+        }
+
+        #[test]
+        fn test_count_frames_reports_invalid_utf8_before_the_member_boundary_first() {
+            // Given
+            let data = [gzip(b"abc\xff"), gzip(b"def\n\xfe\n")].concat();
+
+            // When
+            let err = count_bytes(&data, 2).unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("byte offset 3"), "{}", err);
+            let plain = count_bytes(b"abc\xffdef\n\xfe\n", 2).unwrap_err();
+            assert!(plain.to_string().contains("byte offset 3"), "{}", plain);
+        }
+
+        #[test]
+        fn test_count_corrupt_gzip_fails() {
+            // Given
+            let mut data = in_frames(&corpus(), 3, gzip);
+            let middle = data.len() / 2;
+            data[middle..middle + 8].fill(0xaa);
+
+            // When
+            let result = count_bytes(&data, 2);
+
+            // Then
+            assert!(result.is_err());
+        }
+    }
+
+    mod test_decompressing_reader {
+        use super::*;
+
+        #[test]
+        fn test_decompressing_reader_decodes_every_format() {
+            for data in [
+                gzip(TEXT.as_bytes()),
+                in_frames(TEXT, 3, gzip),
+                in_frames(TEXT, 3, zstd),
+                in_frames(TEXT, 3, bzip2),
+                TEXT.as_bytes().to_vec(),
+            ] {
+                // When
+                let mut decoded = String::new();
+                decompressing_reader(Cursor::new(data))
+                    .unwrap()
+                    .read_to_string(&mut decoded)
+                    .unwrap();
+
+                // Then
+                assert_eq!(decoded, TEXT);
+            }
+        }
+
+        #[test]
+        fn test_decompressing_reader_with_short_plain_input() {
+            // When
+            let mut decoded = String::new();
+            decompressing_reader(Cursor::new(b"hi".to_vec()))
+                .unwrap()
+                .read_to_string(&mut decoded)
+                .unwrap();
+
+            // Then
+            assert_eq!(decoded, "hi");
+        }
+    }
+}
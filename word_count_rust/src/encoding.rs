@@ -0,0 +1,438 @@
+use std::borrow::Cow;
+use std::char::REPLACEMENT_CHARACTER;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::error::WordCountError;
+use crate::progress;
+
+/// Bytes of UTF-16 read from the input at a time.
+const UTF16_BLOCK_SIZE: usize = 64 * 1024;
+
+/// What happens to a line that is not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Utf8Policy {
+    /// Stop the run and report the byte offset of the first invalid byte
+    #[default]
+    Fail,
+    /// Leave the line out
+    Skip,
+    /// Count the line with every invalid sequence replaced by U+FFFD
+    Replace,
+    /// Decode the whole line as Windows-1252, the superset of Latin-1 that web pages use
+    Latin1,
+}
+
+impl Utf8Policy {
+    /// What happened to the lines, for the summary.
+    pub(crate) fn outcome(self) -> &'static str {
+        match self {
+            Utf8Policy::Fail => "rejected",
+            Utf8Policy::Skip => "left out",
+            Utf8Policy::Replace => "counted with U+FFFD replacements",
+            Utf8Policy::Latin1 => "decoded as Windows-1252",
+        }
+    }
+
+    /// The policy's key in saved `--state` settings.
+    pub(crate) fn fingerprint(self) -> &'static str {
+        match self {
+            Utf8Policy::Fail => "fail",
+            Utf8Policy::Skip => "skip",
+            Utf8Policy::Replace => "replace",
+            Utf8Policy::Latin1 => "latin1",
+        }
+    }
+}
+
+/// The lines of one run that were not valid UTF-8. Clones share the count, so the workers of a
+/// run add to the same tally while runs that overlap keep apart.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InvalidLines(Arc<AtomicU64>);
+
+impl InvalidLines {
+    fn add(&self, lines: u64) {
+        self.0.fetch_add(lines, Ordering::Relaxed);
+        progress::add_invalid_lines(lines);
+    }
+
+    /// Lines added so far.
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Decodes `bytes`, made of whole lines, as UTF-8. Valid text is borrowed; otherwise every
+/// invalid line is handled as `policy` says and added to `invalid_lines`, or, with
+/// `Utf8Policy::Fail`, the offset of the first invalid byte is returned.
+pub(crate) fn decode<'a>(
+    bytes: &'a [u8],
+    policy: Utf8Policy,
+    invalid_lines: &InvalidLines,
+) -> Result<Cow<'a, str>, usize> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(Cow::Borrowed(text)),
+        Err(e) if policy == Utf8Policy::Fail => Err(e.valid_up_to()),
+        Err(_) => {
+            let (text, repaired) = repair(bytes, policy);
+            invalid_lines.add(repaired as u64);
+            Ok(Cow::Owned(text))
+        }
+    }
+}
+
+/// Like `decode`, for `bytes` found at `offset` in the input.
+pub(crate) fn decode_at<'a>(
+    bytes: &'a [u8],
+    offset: u64,
+    policy: Utf8Policy,
+    invalid_lines: &InvalidLines,
+) -> io::Result<Cow<'a, str>> {
+    decode(bytes, policy, invalid_lines)
+        .map_err(|at| WordCountError::invalid_utf8(offset + at as u64).into())
+}
+
+/// Handles each line of `bytes` that is not valid UTF-8 as `policy` says, and returns the text
+/// with the number of such lines.
+fn repair(bytes: &[u8], policy: Utf8Policy) -> (String, usize) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut invalid_lines = 0;
+    for line in bytes.split_inclusive(|&b| b == b'\n') {
+        if let Ok(line) = std::str::from_utf8(line) {
+            text.push_str(line);
+            continue;
+        }
+        invalid_lines += 1;
+        match policy {
+            Utf8Policy::Fail | Utf8Policy::Skip => {}
+            Utf8Policy::Replace => text.push_str(&String::from_utf8_lossy(line)),
+            Utf8Policy::Latin1 => text.extend(line.iter().map(|&b| windows_1252(b))),
+        }
+    }
+    (text, invalid_lines)
+}
+
+/// The character Windows-1252 maps `byte` to. The five bytes it leaves undefined map to the C1
+/// control of the same value, as in Latin-1.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}',
+        '\u{8f}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}',
+        '\u{2014}', '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}',
+        '\u{178}',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH[(byte - 0x80) as usize],
+        _ => char::from(byte),
+    }
+}
+
+/// Byte order of a UTF-16 input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Utf16 {
+    Le,
+    Be,
+}
+
+/// The byte order a UTF-16 byte order mark at the start of `head` announces.
+pub(crate) fn detect_utf16(head: &[u8]) -> Option<Utf16> {
+    match head {
+        [0xff, 0xfe, ..] => Some(Utf16::Le),
+        [0xfe, 0xff, ..] => Some(Utf16::Be),
+        _ => None,
+    }
+}
+
+/// Whether `reader` starts with a UTF-16 byte order mark.
+pub(crate) fn starts_with_utf16_bom(reader: impl Read) -> io::Result<bool> {
+    let mut head = Vec::with_capacity(2);
+    reader.take(2).read_to_end(&mut head)?;
+    Ok(detect_utf16(&head).is_some())
+}
+
+/// Whether the file at `path` starts with a UTF-16 byte order mark.
+pub(crate) fn detect_file_utf16(path: &Path) -> io::Result<bool> {
+    starts_with_utf16_bom(File::open(path)?)
+}
+
+/// Transcodes `reader` to UTF-8 when it starts with a UTF-16 byte order mark; anything else is
+/// passed through.
+pub(crate) fn utf8_reader<'a, R: Read + Send + 'a>(
+    mut reader: R,
+) -> io::Result<Box<dyn Read + Send + 'a>> {
+    let mut head = Vec::with_capacity(2);
+    (&mut reader).take(2).read_to_end(&mut head)?;
+    Ok(match detect_utf16(&head) {
+        // The byte order mark itself is dropped.
+        Some(byte_order) => Box::new(Utf16Reader {
+            inner: reader,
+            byte_order,
+            raw: Vec::new(),
+            decoded: Vec::new(),
+            pos: 0,
+            eof: false,
+        }),
+        // The peeked bytes are put back in front of the rest of the stream.
+        None => Box::new(Cursor::new(head).chain(reader)),
+    })
+}
+
+/// Reads UTF-16 from `inner` and hands it out as UTF-8. Unpaired surrogates and a dangling
+/// odd byte at the end become U+FFFD.
+struct Utf16Reader<R> {
+    inner: R,
+    byte_order: Utf16,
+    /// Bytes read but not decoded yet: an odd byte, or a high surrogate waiting for its pair.
+    raw: Vec<u8>,
+    /// UTF-8 not handed out yet, from `pos` on.
+    decoded: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> Utf16Reader<R> {
+    fn fill(&mut self) -> io::Result<()> {
+        self.decoded.clear();
+        self.pos = 0;
+        let start = self.raw.len();
+        self.raw.resize(start + UTF16_BLOCK_SIZE, 0);
+        let read = loop {
+            match self.inner.read(&mut self.raw[start..]) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.raw.truncate(start);
+                    return Err(e);
+                }
+            }
+        };
+        self.raw.truncate(start + read);
+        self.eof = read == 0;
+
+        let mut units: Vec<u16> = self
+            .raw
+            .chunks_exact(2)
+            .map(|pair| match self.byte_order {
+                Utf16::Le => u16::from_le_bytes([pair[0], pair[1]]),
+                Utf16::Be => u16::from_be_bytes([pair[0], pair[1]]),
+            })
+            .collect();
+        // A high surrogate at the end may pair with the first unit of the next read.
+        if !self.eof
+            && units
+                .last()
+                .is_some_and(|unit| (0xd800..0xdc00).contains(unit))
+        {
+            units.pop();
+        }
+        self.raw.drain(..units.len() * 2);
+
+        let mut utf8 = [0; 4];
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or(REPLACEMENT_CHARACTER);
+            self.decoded
+                .extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        }
+        if self.eof && !self.raw.is_empty() {
+            self.raw.clear();
+            self.decoded
+                .extend_from_slice(REPLACEMENT_CHARACTER.encode_utf8(&mut utf8).as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Utf16Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, byte_order: Utf16) -> Vec<u8> {
+        let mut bytes = match byte_order {
+            Utf16::Le => vec![0xff, 0xfe],
+            Utf16::Be => vec![0xfe, 0xff],
+        };
+        for unit in text.encode_utf16() {
+            bytes.extend_from_slice(&match byte_order {
+                Utf16::Le => unit.to_le_bytes(),
+                Utf16::Be => unit.to_be_bytes(),
+            });
+        }
+        bytes
+    }
+
+    fn read_all(reader: impl Read + Send) -> String {
+        let mut text = String::new();
+        utf8_reader(reader)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    mod test_decode {
+        use super::*;
+
+        #[test]
+        fn test_decode_borrows_valid_text() {
+            // When
+            let result = decode(
+                b"caf\xc3\xa9 ok\n",
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert!(matches!(result, Cow::Borrowed("café ok\n")));
+        }
+
+        #[test]
+        fn test_decode_fails_with_offset() {
+            // When
+            let result = decode(
+                b"ok\nbad \xff\n",
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            );
+
+            // Then
+            assert_eq!(result.unwrap_err(), 7);
+        }
+
+        #[test]
+        fn test_decode_at_adds_offset_of_bytes() {
+            // When
+            let err = decode_at(
+                b"ok\nbad \xff\n",
+                100,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("byte offset 107"), "{}", err);
+        }
+
+        #[test]
+        fn test_decode_adds_repaired_lines_to_its_own_tally() {
+            // Given
+            let (run, other) = (InvalidLines::default(), InvalidLines::default());
+
+            // When
+            decode(b"ok\nbad \xff\n\xfe\n", Utf8Policy::Skip, &run).unwrap();
+            decode(b"ok\n", Utf8Policy::Skip, &other).unwrap();
+
+            // Then
+            assert_eq!(run.clone().get(), 2);
+            assert_eq!(other.get(), 0);
+        }
+
+        #[test]
+        fn test_repair_applies_policy_to_invalid_lines_only() {
+            // Given
+            let bytes = b"first\ncaf\xe9 \x93quoted\x94\nlast \xff\xfe\nend";
+
+            for (policy, expected) in [
+                (Utf8Policy::Skip, "first\nend"),
+                (
+                    Utf8Policy::Replace,
+                    "first\ncaf\u{fffd} \u{fffd}quoted\u{fffd}\nlast \u{fffd}\u{fffd}\nend",
+                ),
+                (
+                    Utf8Policy::Latin1,
+                    "first\ncafé \u{201c}quoted\u{201d}\nlast ÿþ\nend",
+                ),
+            ] {
+                // When
+                let (text, invalid_lines) = repair(bytes, policy);
+
+                // Then
+                assert_eq!(text, expected, "{:?}", policy);
+                assert_eq!(invalid_lines, 2, "{:?}", policy);
+            }
+        }
+
+        #[test]
+        fn test_windows_1252_maps_c1_range() {
+            // Then
+            assert_eq!(windows_1252(b'A'), 'A');
+            assert_eq!(windows_1252(0x80), '€');
+            assert_eq!(windows_1252(0x8d), '\u{8d}');
+            assert_eq!(windows_1252(0x9f), 'Ÿ');
+            assert_eq!(windows_1252(0xe9), 'é');
+        }
+    }
+
+    mod test_utf8_reader {
+        use super::*;
+
+        #[test]
+        fn test_utf8_reader_transcodes_both_byte_orders() {
+            // Given
+            let text = "Grüße 𝄞 music\nzweite Zeile\n";
+
+            for byte_order in [Utf16::Le, Utf16::Be] {
+                // When
+                let result = read_all(Cursor::new(utf16(text, byte_order)));
+
+                // Then
+                assert_eq!(result, text, "{:?}", byte_order);
+            }
+        }
+
+        #[test]
+        fn test_utf8_reader_keeps_surrogate_pairs_across_reads() {
+            // Given: a pair straddling the end of the first block
+            let text = "a".repeat(UTF16_BLOCK_SIZE / 2 - 2) + "𝄞b";
+
+            // When
+            let result = read_all(Cursor::new(utf16(&text, Utf16::Le)));
+
+            // Then
+            assert_eq!(result, text);
+        }
+
+        #[test]
+        fn test_utf8_reader_replaces_unpaired_surrogate_and_odd_byte() {
+            // Given
+            let mut bytes = vec![0xff, 0xfe, b'a', 0, 0x00, 0xd8, b'b', 0];
+            bytes.push(b'c');
+
+            // When
+            let result = read_all(Cursor::new(bytes));
+
+            // Then
+            assert_eq!(result, "a\u{fffd}b\u{fffd}");
+        }
+
+        #[test]
+        fn test_utf8_reader_passes_other_input_through() {
+            // When
+            let result = read_all(Cursor::new(b"plain text\n".to_vec()));
+
+            // Then
+            assert_eq!(result, "plain text\n");
+        }
+    }
+}
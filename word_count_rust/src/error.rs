@@ -0,0 +1,236 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a run failed, and so which status the process exits with.
+///
+/// The counters work on `io::Result` like the readers they wrap; an error that is not a plain
+/// I/O failure travels as the payload of an `io::Error` and is taken back out by the `From`
+/// conversion at the top, so callers of `process_file` and of the library can tell the cases
+/// apart.
+#[derive(Debug)]
+pub enum WordCountError {
+    /// Reading an input or writing the results failed.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// An input is not valid UTF-8 from `offset`, counted in bytes from the start of the input
+    /// (of the decompressed data for compressed inputs).
+    InvalidUtf8 { path: Option<PathBuf>, offset: u64 },
+    /// A worker or reader thread panicked.
+    WorkerPanic { worker: String, message: String },
+    /// The command line or a `WordCounter` asks for something that cannot be done.
+    Config(String),
+}
+
+impl WordCountError {
+    pub(crate) fn invalid_utf8(offset: u64) -> Self {
+        WordCountError::InvalidUtf8 { path: None, offset }
+    }
+
+    /// Turns the payload of a thread that panicked into an error naming `worker`.
+    pub(crate) fn worker_panic(worker: impl fmt::Display, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic payload".to_string(),
+            },
+        };
+        WordCountError::WorkerPanic {
+            worker: worker.to_string(),
+            message,
+        }
+    }
+
+    /// Records the input or output the error happened in, unless one is known already.
+    pub(crate) fn with_path(mut self, file: &Path) -> Self {
+        match &mut self {
+            WordCountError::Io { path, .. } | WordCountError::InvalidUtf8 { path, .. } => {
+                path.get_or_insert_with(|| file.to_path_buf());
+            }
+            WordCountError::WorkerPanic { .. } | WordCountError::Config(_) => {}
+        }
+        self
+    }
+
+    /// The process exit status: 1 for I/O errors, 2 for the command line (as clap uses for
+    /// parse errors), 3 for input that is not UTF-8 and 4 when a worker panicked.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            WordCountError::Io { .. } => 1,
+            WordCountError::Config(_) => 2,
+            WordCountError::InvalidUtf8 { .. } => 3,
+            WordCountError::WorkerPanic { .. } => 4,
+        }
+    }
+}
+
+/// For `map_err` in code that returns `io::Result`: records `path` in the error it is given.
+pub(crate) fn at_path(path: &Path) -> impl FnOnce(io::Error) -> io::Error + '_ {
+    move |err| WordCountError::from(err).with_path(path).into()
+}
+
+impl fmt::Display for WordCountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WordCountError::Io {
+                path: Some(path), ..
+            } => write!(f, "{}", path.display()),
+            WordCountError::Io { path: None, .. } => write!(f, "I/O error"),
+            WordCountError::InvalidUtf8 { path, offset } => {
+                let input = path
+                    .as_ref()
+                    .map_or("stream".to_string(), |path| path.display().to_string());
+                write!(
+                    f,
+                    "{} did not contain valid UTF-8 at byte offset {}",
+                    input, offset
+                )
+            }
+            WordCountError::WorkerPanic { worker, message } => {
+                write!(f, "{} panicked: {}", worker, message)
+            }
+            WordCountError::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for WordCountError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WordCountError::Io { source, .. } => Some(source),
+            WordCountError::InvalidUtf8 { .. }
+            | WordCountError::WorkerPanic { .. }
+            | WordCountError::Config(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for WordCountError {
+    fn from(err: io::Error) -> Self {
+        if err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<WordCountError>())
+        {
+            let inner = err.into_inner().expect("checked above");
+            return *inner.downcast::<WordCountError>().expect("checked above");
+        }
+        WordCountError::Io {
+            path: None,
+            source: err,
+        }
+    }
+}
+
+impl From<WordCountError> for io::Error {
+    fn from(err: WordCountError) -> Self {
+        let kind = match &err {
+            WordCountError::Io { source, .. } => source.kind(),
+            WordCountError::InvalidUtf8 { .. } => io::ErrorKind::InvalidData,
+            WordCountError::WorkerPanic { .. } => io::ErrorKind::Other,
+            WordCountError::Config(_) => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// The error followed by each of its sources, separated by colons.
+pub(crate) fn describe(err: &dyn Error) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        description.push_str(": ");
+        description.push_str(&err.to_string());
+        source = err.source();
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod test_conversions {
+        use super::*;
+
+        #[test]
+        fn test_error_survives_a_trip_through_io_error() {
+            // Given
+            let err = WordCountError::invalid_utf8(42);
+
+            // When
+            let io_err = io::Error::from(err);
+            let back = WordCountError::from(io_err);
+
+            // Then
+            assert!(matches!(
+                back,
+                WordCountError::InvalidUtf8 {
+                    path: None,
+                    offset: 42
+                }
+            ));
+            assert_eq!(back.exit_code(), 3);
+        }
+
+        #[test]
+        fn test_plain_io_error_becomes_io_variant_with_source() {
+            // Given
+            let io_err = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+
+            // When
+            let err = WordCountError::from(io_err).with_path(Path::new("in.txt"));
+
+            // Then
+            assert_eq!(err.exit_code(), 1);
+            assert_eq!(describe(&err), "in.txt: No such file or directory");
+            let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+            assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        }
+
+        #[test]
+        fn test_at_path_keeps_kind_and_first_path() {
+            // Given
+            let io_err = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+
+            // When
+            let inner = at_path(Path::new("inner.txt"))(io_err);
+            let outer = at_path(Path::new("outer.txt"))(inner);
+
+            // Then
+            assert_eq!(outer.kind(), io::ErrorKind::PermissionDenied);
+            assert_eq!(describe(&WordCountError::from(outer)), "inner.txt: denied");
+        }
+    }
+
+    mod test_worker_panic {
+        use super::*;
+
+        #[test]
+        fn test_worker_panic_keeps_panic_message() {
+            // Given
+            let handle = std::thread::spawn(|| panic!("chunk {} is broken", 3));
+
+            // When
+            let err = WordCountError::worker_panic("Thread 1", handle.join().unwrap_err());
+
+            // Then
+            assert_eq!(err.to_string(), "Thread 1 panicked: chunk 3 is broken");
+            assert_eq!(err.exit_code(), 4);
+            assert!(err.source().is_none());
+        }
+
+        #[test]
+        fn test_worker_panic_with_static_message() {
+            // When
+            let err = WordCountError::worker_panic("Reader thread", Box::new("boom"));
+
+            // Then
+            assert_eq!(err.to_string(), "Reader thread panicked: boom");
+        }
+    }
+}
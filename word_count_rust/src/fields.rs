@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::decompress::decompressing_reader;
+use crate::encoding;
+use crate::error::WordCountError;
+use crate::mapped::{normalize_counts, tally_raw_words};
+use crate::merge::{merge_counts, tree_reduce};
+use crate::normalize::Normalizer;
+use crate::progress::{debug, info};
+use crate::stream::RecordScanner;
+use crate::tokenizer::Tokenizer;
+use crate::{stream, Config, STDIN_PATH};
+
+/// Longest header record read while a quoted column name is open.
+const MAX_HEADER_SIZE: usize = 1024 * 1024;
+
+/// A column of CSV input, as given to `--csv-column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Column {
+    /// Position in the record, from 0.
+    Index(usize),
+    /// Name in the header record.
+    Name(String),
+}
+
+impl Column {
+    /// Parses a column number, counted from 1, or else a column name.
+    pub(crate) fn parse(value: &str) -> Result<Self, String> {
+        match value.parse::<usize>() {
+            Ok(0) => Err("columns are numbered from 1".to_string()),
+            Ok(number) => Ok(Column::Index(number - 1)),
+            Err(_) if value.is_empty() => Err("the column name is empty".to_string()),
+            Err(_) => Ok(Column::Name(value.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Column::Index(index) => write!(f, "column {}", index + 1),
+            Column::Name(name) => write!(f, "column \"{}\"", name),
+        }
+    }
+}
+
+/// Which text of each record is counted, instead of whole lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FieldSelector {
+    /// One column of CSV records; a quoted field may span lines.
+    Csv {
+        column: Column,
+        delimiter: u8,
+        /// The first record names the columns and is not counted.
+        header: bool,
+    },
+    /// The strings at a JSON pointer (RFC 6901) of NDJSON records, one per line.
+    Json { pointer: String },
+}
+
+impl FieldSelector {
+    /// The delimiter of CSV records, whose quoted fields may hold newlines.
+    pub(crate) fn csv_delimiter(&self) -> Option<u8> {
+        match self {
+            FieldSelector::Csv { delimiter, .. } => Some(*delimiter),
+            FieldSelector::Json { .. } => None,
+        }
+    }
+}
+
+/// What one worker saw of an input.
+#[derive(Debug, Default)]
+struct FieldCounts {
+    counts: HashMap<String, usize>,
+    /// Records without the selected field.
+    missing: u64,
+    /// Lines that are not JSON.
+    malformed: u64,
+}
+
+/// Field counterpart of `count_file`: counts only the selected field of every record of
+/// `input_path`, which may be stdin, a pipe or a compressed file.
+///
+/// The input is read as a stream, so that the workers get blocks of whole records: for CSV a
+/// block only ends on a newline outside quotes, so a quoted field that spans lines is never
+/// split between workers.
+pub(crate) fn count_file(
+    input_path: &Path,
+    config: &Config,
+    fields: &FieldSelector,
+) -> io::Result<HashMap<String, usize>> {
+    let reader = if input_path == Path::new(STDIN_PATH) {
+        decompressing_reader(io::stdin())?
+    } else {
+        decompressing_reader(File::open(input_path)?)?
+    };
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+
+    let thread_counts = match fields {
+        FieldSelector::Csv {
+            column,
+            delimiter,
+            header,
+        } => {
+            let mut reader = BufReader::new(encoding::utf8_reader(reader)?);
+            let header_record = if *header {
+                read_header(&mut reader, *delimiter)?
+            } else {
+                Vec::new()
+            };
+            let index = match column {
+                Column::Index(index) => *index,
+                Column::Name(name) => {
+                    let header_text = encoding::decode_at(
+                        &header_record,
+                        0,
+                        config.invalid_utf8,
+                        &config.invalid_lines,
+                    )?;
+                    column_index(&header_text, *delimiter, name).ok_or_else(|| {
+                        WordCountError::Config(format!(
+                            "{} has no column named \"{}\"",
+                            input_path.display(),
+                            name
+                        ))
+                    })?
+                }
+            };
+            // The header goes back in front, so offsets still count from the start of the input.
+            let reader = Cursor::new(header_record).chain(reader);
+            stream::fold_stream(
+                reader,
+                config,
+                FieldCounts::default,
+                |result, text, offset| {
+                    // Only the first block starts with the header.
+                    let skip_header = *header && offset == 0;
+                    let text = csv_column_text(text, *delimiter, skip_header, index, result);
+                    count_text(result, &text, tokenizer, normalizer)
+                },
+            )?
+        }
+        FieldSelector::Json { pointer } => stream::fold_stream(
+            reader,
+            config,
+            FieldCounts::default,
+            |result, text, _offset| {
+                let text = json_field_text(text, pointer, result);
+                count_text(result, &text, tokenizer, normalizer)
+            },
+        )?,
+    };
+
+    let (mut missing, mut malformed) = (0, 0);
+    let mut word_counts = Vec::with_capacity(thread_counts.len());
+    for thread_count in thread_counts {
+        missing += thread_count.missing;
+        malformed += thread_count.malformed;
+        word_counts.push(thread_count.counts);
+    }
+    let input = if input_path == Path::new(STDIN_PATH) {
+        "stdin".to_string()
+    } else {
+        input_path.display().to_string()
+    };
+    if malformed > 0 {
+        info!(
+            "{} {} of {} not JSON and left out",
+            malformed,
+            if malformed == 1 {
+                "line was"
+            } else {
+                "lines were"
+            },
+            input
+        );
+    }
+    if missing > 0 {
+        let field = match fields {
+            FieldSelector::Csv { column, .. } => column.to_string(),
+            FieldSelector::Json { pointer } => format!("text at \"{}\"", pointer),
+        };
+        info!(
+            "{} {} of {} had no {} and {} left out",
+            missing,
+            if missing == 1 { "record" } else { "records" },
+            input,
+            field,
+            if missing == 1 { "was" } else { "were" }
+        );
+    }
+
+    debug!("All threads finished, merging results");
+    tree_reduce(word_counts)
+}
+
+/// Counts the words of the text extracted from one block.
+fn count_text(
+    result: &mut FieldCounts,
+    text: &str,
+    tokenizer: &dyn Tokenizer,
+    normalizer: &Normalizer,
+) -> usize {
+    let mut raw_count = HashMap::new();
+    let words = tally_raw_words(text, tokenizer, &mut raw_count);
+    merge_counts(
+        &mut result.counts,
+        normalize_counts(raw_count, tokenizer, normalizer),
+    );
+    words
+}
+
+/// Reads the header record, with its newline, from `reader`: lines are read until one ends
+/// outside a quoted field. Fails when a quote is still open after `MAX_HEADER_SIZE` bytes.
+fn read_header(reader: &mut impl BufRead, delimiter: u8) -> io::Result<Vec<u8>> {
+    let mut record = Vec::new();
+    let mut scanner = RecordScanner::new(delimiter);
+    loop {
+        let start = record.len();
+        if reader.read_until(b'\n', &mut record)? == 0 || scanner.scan(&record[start..]).is_some() {
+            return Ok(record);
+        }
+        if record.len() > MAX_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the quote at byte offset {} of the CSV header is not closed",
+                    scanner.open_quote().unwrap_or(0)
+                ),
+            ));
+        }
+    }
+}
+
+/// The position of the column called `name` in `header`, a CSV record. A UTF-8 byte order
+/// mark in front of the first name is ignored.
+fn column_index(header: &str, delimiter: u8, name: &str) -> Option<usize> {
+    let header = header.strip_prefix('\u{feff}').unwrap_or(header);
+    let record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(header.as_bytes())
+        .records()
+        .next()?
+        .ok()?;
+    record.iter().position(|field| field == name)
+}
+
+/// The `index` field of every CSV record of `text`, one per line.
+fn csv_column_text(
+    text: &str,
+    delimiter: u8,
+    skip_header: bool,
+    index: usize,
+    result: &mut FieldCounts,
+) -> String {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(skip_header)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut column_text = String::new();
+    let mut record = csv::StringRecord::new();
+    // The text is valid UTF-8 in memory and records may have any length, so reading a record
+    // cannot fail.
+    while reader.read_record(&mut record).unwrap_or(false) {
+        match record.get(index) {
+            Some(field) => {
+                column_text.push_str(field);
+                column_text.push('\n');
+            }
+            None => result.missing += 1,
+        }
+    }
+    column_text
+}
+
+/// The strings at `pointer` of every NDJSON record of `text`, one per line. Blank lines are
+/// skipped.
+fn json_field_text(text: &str, pointer: &str, result: &mut FieldCounts) -> String {
+    let mut field_text = String::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<Value>(line) {
+            Ok(record) => {
+                let found = record
+                    .pointer(pointer)
+                    .is_some_and(|value| push_strings(value, &mut field_text));
+                if !found {
+                    result.missing += 1;
+                }
+            }
+            Err(_) => result.malformed += 1,
+        }
+    }
+    field_text
+}
+
+/// Appends `value` to `text` when it is a string, or the strings of an array (of arrays) of
+/// strings, one per line; returns whether there were any. Numbers, booleans, null and objects
+/// are not text.
+fn push_strings(value: &Value, text: &mut String) -> bool {
+    match value {
+        Value::String(string) => {
+            text.push_str(string);
+            text.push('\n');
+            true
+        }
+        Value::Array(items) => {
+            let mut found = false;
+            for item in items {
+                found |= push_strings(item, text);
+            }
+            found
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counts;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn config(fields: FieldSelector) -> Config {
+        Config {
+            fields: Some(fields),
+            ..Config::default()
+        }
+    }
+
+    fn csv_column(column: &str) -> FieldSelector {
+        FieldSelector::Csv {
+            column: Column::parse(column).unwrap(),
+            delimiter: b',',
+            header: true,
+        }
+    }
+
+    mod test_column {
+        use super::*;
+
+        #[test]
+        fn test_parse_reads_numbers_from_one_and_names() {
+            // When / Then
+            assert_eq!(Column::parse("3"), Ok(Column::Index(2)));
+            assert_eq!(Column::parse("text"), Ok(Column::Name("text".to_string())));
+            assert!(Column::parse("0").is_err());
+            assert!(Column::parse("").is_err());
+        }
+    }
+
+    mod test_count_file {
+        use super::*;
+
+        #[test]
+        fn test_count_file_counts_named_csv_column_across_block_boundaries() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("reviews.csv");
+            let csv = "\u{feff}id,text,stars\n\
+                       1,\"great phone\nreally great\",5\n\
+                       2,\"a \"\"great\"\" case, sadly\",2\n\
+                       3,battery\n\
+                       4\n";
+            fs::write(&path, csv).unwrap();
+            let config = Config {
+                num_threads: 3,
+                block_size: 8,
+                ..config(csv_column("text"))
+            };
+
+            // When
+            let result = count_file(&path, &config, config.fields.as_ref().unwrap()).unwrap();
+
+            // Then
+            assert_eq!(
+                result,
+                counts(&[
+                    ("great", 3),
+                    ("phone", 1),
+                    ("really", 1),
+                    ("a", 1),
+                    ("case", 1),
+                    ("sadly", 1),
+                    ("battery", 1),
+                ])
+            );
+        }
+
+        #[test]
+        fn test_count_file_reads_numbered_column_without_header() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("data.tsv");
+            fs::write(&path, "1\tred fish\n2\tblue fish\n").unwrap();
+            let fields = FieldSelector::Csv {
+                column: Column::Index(1),
+                delimiter: b'\t',
+                header: false,
+            };
+
+            // When
+            let result = count_file(&path, &config(fields.clone()), &fields).unwrap();
+
+            // Then
+            assert_eq!(result, counts(&[("red", 1), ("blue", 1), ("fish", 2)]));
+        }
+
+        #[test]
+        fn test_count_file_fails_on_unknown_column_name() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("data.csv");
+            fs::write(&path, "id,body\n1,hello\n").unwrap();
+            let fields = csv_column("text");
+
+            // When
+            let err = count_file(&path, &config(fields.clone()), &fields).unwrap_err();
+
+            // Then
+            let err = WordCountError::from(err);
+            assert_eq!(err.exit_code(), 2);
+            assert!(err.to_string().contains("no column named \"text\""));
+        }
+
+        #[test]
+        fn test_count_file_counts_json_pointer_of_ndjson_records() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("events.ndjson");
+            let ndjson = "{\"msg\": {\"text\": \"disk full\"}, \"level\": \"error\"}\n\
+                          {\"msg\": {\"text\": [\"disk\", [\"ok\"]]}}\n\
+                          \n\
+                          {\"msg\": {\"text\": 42}}\n\
+                          not json\n\
+                          {\"level\": \"info\"}\n";
+            fs::write(&path, ndjson).unwrap();
+            let fields = FieldSelector::Json {
+                pointer: "/msg/text".to_string(),
+            };
+
+            // When
+            let result = count_file(&path, &config(fields.clone()), &fields).unwrap();
+
+            // Then
+            assert_eq!(result, counts(&[("disk", 2), ("full", 1), ("ok", 1)]));
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::encoding::decode_at;
+use crate::error::at_path;
+use crate::format::Footer;
+use crate::incremental::last_line_end;
+use crate::mapped::{normalize_counts, tally_raw_words};
+use crate::merge::merge_counts;
+use crate::progress::{self, info};
+use crate::schedule::{self, chunkable_size};
+use crate::{create_output, write_results_to, Config, FileChunk, Output};
+
+/// How `--follow` reports the counts as they change, as selected with `--follow-updates`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum Updates {
+    /// Write the whole report again, as --format and --top say; on a terminal the screen is
+    /// cleared first, and an output file is replaced
+    #[default]
+    Render,
+    /// Write one JSON line per update with the counts added since the last one
+    Delta,
+}
+
+/// Settings of `--follow`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FollowConfig {
+    /// How long to wait between looks at the inputs.
+    pub(crate) interval: Duration,
+    pub(crate) updates: Updates,
+}
+
+/// A file that is being followed, and where reading it stopped.
+struct Followed {
+    path: PathBuf,
+    file: File,
+    id: Option<(u64, u64)>,
+    /// Where the next read starts.
+    offset: u64,
+    /// The start of the last line, while its newline has not been written yet.
+    pending: Vec<u8>,
+}
+
+/// Identifies the file behind a path, so a rotated log is told apart from the one that
+/// replaced it; without inode numbers, only truncation is noticed.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Counts the lines appended to a set of files, as `tail -F` shows them.
+///
+/// The files are counted once in full, on the worker pool like any other run; after that,
+/// [`Follower::poll`] reads what was appended since. A file that is truncated is read again from
+/// the start. When the path of a file names another file (it was rotated), the rest of the old
+/// file is read first and the new one is then read from the start. A file that was moved away
+/// and not replaced yet is read through the handle that is still open.
+pub(crate) struct Follower<'a> {
+    files: Vec<Followed>,
+    config: &'a Config,
+}
+
+impl<'a> Follower<'a> {
+    /// Opens every input and counts what it holds so far, up to its last complete line.
+    pub(crate) fn start(
+        input_files: &[PathBuf],
+        config: &'a Config,
+    ) -> io::Result<(Self, HashMap<String, usize>)> {
+        let mut files = Vec::with_capacity(input_files.len());
+        let mut ranges = Vec::with_capacity(input_files.len());
+        for path in input_files {
+            let (followed, range) = Followed::open(path, config).map_err(at_path(path))?;
+            files.push(followed);
+            ranges.push(Some(range));
+        }
+        let file_counts = schedule::count_file_ranges(input_files, &ranges, config)?;
+        let follower = Follower { files, config };
+        Ok((follower, crate::merge::tree_reduce(file_counts)?))
+    }
+
+    /// The counts of the complete lines written to the files since the last call.
+    pub(crate) fn poll(&mut self) -> io::Result<HashMap<String, usize>> {
+        let mut counts = HashMap::new();
+        for followed in &mut self.files {
+            followed
+                .poll(self.config, &mut counts)
+                .map_err(at_path(&followed.path))?;
+        }
+        Ok(counts)
+    }
+}
+
+impl Followed {
+    /// Opens `path` and returns the range of it that is already complete.
+    fn open(path: &Path, config: &Config) -> io::Result<(Self, FileChunk)> {
+        if chunkable_size(path, config)?.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only regular, uncompressed UTF-8 files can be followed",
+            ));
+        }
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let end = last_line_end(&mut file, 0, metadata.len())?;
+        let followed = Followed {
+            path: path.to_path_buf(),
+            file,
+            id: file_id(&metadata),
+            offset: end,
+            pending: Vec::new(),
+        };
+        Ok((followed, FileChunk { start: 0, end }))
+    }
+
+    fn poll(&mut self, config: &Config, counts: &mut HashMap<String, usize>) -> io::Result<()> {
+        self.read_new(config, counts)?;
+        let replaced = match fs::metadata(&self.path) {
+            Ok(metadata) => self.id.is_some() && file_id(&metadata) != self.id,
+            // Moved away and not recreated yet: keep reading the old file.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => return Err(err),
+        };
+        if replaced {
+            info!(
+                "{} was rotated; following the new file",
+                self.path.display()
+            );
+            self.finish_line(config, counts)?;
+            self.file = File::open(&self.path)?;
+            self.id = file_id(&self.file.metadata()?);
+            self.offset = 0;
+            self.read_new(config, counts)?;
+        }
+        Ok(())
+    }
+
+    /// Counts the complete lines after `offset` and keeps the incomplete one.
+    fn read_new(&mut self, config: &Config, counts: &mut HashMap<String, usize>) -> io::Result<()> {
+        let len = self.file.metadata()?.len();
+        if len < self.offset {
+            info!(
+                "{} was truncated; following it from the start",
+                self.path.display()
+            );
+            self.finish_line(config, counts)?;
+            self.offset = 0;
+        }
+        if len == self.offset {
+            return Ok(());
+        }
+        let start = self.offset - self.pending.len() as u64;
+        let mut bytes = std::mem::take(&mut self.pending);
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let read = (&mut self.file)
+            .take(len - self.offset)
+            .read_to_end(&mut bytes)?;
+        self.offset += read as u64;
+        let complete = bytes
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |i| i + 1);
+        self.pending = bytes.split_off(complete);
+        count_bytes(&bytes, start, config, counts)
+    }
+
+    /// Counts the incomplete last line as it is, once the file it was in is gone.
+    fn finish_line(
+        &mut self,
+        config: &Config,
+        counts: &mut HashMap<String, usize>,
+    ) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        count_bytes(&pending, self.offset - pending.len() as u64, config, counts)
+    }
+}
+
+/// Adds the words of `bytes`, found at `offset` in their file, to `counts`.
+fn count_bytes(
+    bytes: &[u8],
+    offset: u64,
+    config: &Config,
+    counts: &mut HashMap<String, usize>,
+) -> io::Result<()> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    let text = decode_at(bytes, offset, config.invalid_utf8, &config.invalid_lines)?;
+    let tokenizer = config.tokenizer.as_ref();
+    let mut raw_count = HashMap::new();
+    let words = tally_raw_words(&text, tokenizer, &mut raw_count);
+    merge_counts(
+        counts,
+        normalize_counts(raw_count, tokenizer, &config.normalizer),
+    );
+    progress::add_bytes(bytes.len() as u64);
+    progress::add_words(words as u64);
+    Ok(())
+}
+
+/// Follows `input_files`, writing an update to `output` after the first count and whenever
+/// lines were added, until `stop` returns true or writing fails.
+pub(crate) fn follow_files(
+    input_files: &[PathBuf],
+    output: &Output,
+    config: &Config,
+    follow: &FollowConfig,
+    mut stop: impl FnMut() -> bool,
+) -> io::Result<()> {
+    let (mut follower, mut totals) = Follower::start(input_files, config)?;
+    let mut updates = UpdateWriter::new(output, config, follow.updates)?;
+    updates.write(&totals, &totals)?;
+    while !stop() {
+        thread::sleep(follow.interval);
+        let counts = follower.poll()?;
+        if counts.is_empty() {
+            continue;
+        }
+        merge_counts(&mut totals, counts.clone());
+        updates.write(&totals, &counts)?;
+    }
+    Ok(())
+}
+
+/// Where updates go.
+enum Sink {
+    /// Renders follow each other, or replace each other when `clear` is set.
+    Stdout { clear: bool },
+    /// Every render replaces the file.
+    Replace(PathBuf),
+    /// Deltas are appended to the file.
+    Append(BufWriter<File>),
+}
+
+/// Writes each update as `--follow-updates` says.
+struct UpdateWriter<'a> {
+    sink: Sink,
+    config: &'a Config,
+    updates: Updates,
+    written: usize,
+}
+
+impl<'a> UpdateWriter<'a> {
+    fn new(output: &Output, config: &'a Config, updates: Updates) -> io::Result<Self> {
+        let sink = match output {
+            Output::Stdout => Sink::Stdout {
+                clear: updates == Updates::Render && io::stdout().is_terminal(),
+            },
+            Output::File(path) if updates == Updates::Render => Sink::Replace(path.clone()),
+            Output::File(path) => Sink::Append(create_output(path).map_err(at_path(path))?),
+        };
+        Ok(UpdateWriter {
+            sink,
+            config,
+            updates,
+            written: 0,
+        })
+    }
+
+    /// Writes an update, with `totals` the counts so far and `delta` those added since the last
+    /// update; stop words and stemming are applied to both as they would be to a full run.
+    fn write(
+        &mut self,
+        totals: &HashMap<String, usize>,
+        delta: &HashMap<String, usize>,
+    ) -> io::Result<()> {
+        let first = self.written == 0;
+        self.written += 1;
+        match (self.updates, &mut self.sink) {
+            (Updates::Render, Sink::Replace(path)) => {
+                let (counts, footer) = filter(self.config, totals.clone());
+                create_output(path)
+                    .and_then(|writer| {
+                        write_results_to(writer, &counts, &self.config.report, &footer)
+                    })
+                    .map_err(at_path(path))
+            }
+            (Updates::Render, Sink::Stdout { clear }) => {
+                let (counts, footer) = filter(self.config, totals.clone());
+                let mut stdout = io::stdout().lock();
+                if *clear {
+                    write!(stdout, "\x1b[H\x1b[2J")?;
+                } else if !first {
+                    writeln!(stdout)?;
+                }
+                write_results_to(stdout, &counts, &self.config.report, &footer)
+            }
+            (_, sink) => {
+                let (counts, _) = filter(self.config, delta.clone());
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64);
+                let event = json!({"time_ms": time, "counts": counts});
+                match sink {
+                    Sink::Append(writer) => write_event(writer, &event),
+                    _ => write_event(io::stdout().lock(), &event),
+                }
+            }
+        }
+    }
+}
+
+fn write_event(mut writer: impl Write, event: &serde_json::Value) -> io::Result<()> {
+    writeln!(writer, "{}", event)?;
+    writer.flush()
+}
+
+/// Applies the stop words and the stemmer to `counts`, without logging what was filtered.
+fn filter(config: &Config, mut counts: HashMap<String, usize>) -> (HashMap<String, usize>, Footer) {
+    let filtered = config.stop_words.remove_from(&mut counts, false);
+    let footer = Footer {
+        filtered: (!config.stop_words.is_empty()).then_some(filtered),
+    };
+    (config.stemmer.apply(counts, false), footer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{append, counts};
+    use tempfile::TempDir;
+
+    mod test_follower {
+        use super::*;
+
+        #[test]
+        fn test_follower_counts_complete_appended_lines() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            fs::write(&path, "start here\npart").unwrap();
+            let config = Config::default();
+            let (mut follower, initial) =
+                Follower::start(std::slice::from_ref(&path), &config).unwrap();
+
+            // When
+            let nothing = follower.poll().unwrap();
+            append(&path, "ial line\nnext");
+            let appended = follower.poll().unwrap();
+
+            // Then
+            assert_eq!(initial, counts(&[("start", 1), ("here", 1)]));
+            assert!(nothing.is_empty());
+            assert_eq!(appended, counts(&[("partial", 1), ("line", 1)]));
+        }
+
+        #[test]
+        fn test_follower_reads_truncated_file_from_start() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            fs::write(&path, "a long first line\n").unwrap();
+            let config = Config::default();
+            let (mut follower, _) = Follower::start(std::slice::from_ref(&path), &config).unwrap();
+
+            // When
+            fs::write(&path, "short\n").unwrap();
+            let result = follower.poll().unwrap();
+
+            // Then
+            assert_eq!(result, counts(&[("short", 1)]));
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn test_follower_finishes_rotated_file_then_follows_new_one() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            let rotated = temp_dir.path().join("app.log.1");
+            fs::write(&path, "before\n").unwrap();
+            let config = Config::default();
+            let (mut follower, _) = Follower::start(std::slice::from_ref(&path), &config).unwrap();
+
+            // When
+            fs::rename(&path, &rotated).unwrap();
+            append(&rotated, "late\nunfinished");
+            let moved = follower.poll().unwrap();
+            append(&path, "fresh\n");
+            let result = follower.poll().unwrap();
+
+            // Then
+            assert_eq!(moved, counts(&[("late", 1)]));
+            assert_eq!(result, counts(&[("unfinished", 1), ("fresh", 1)]));
+        }
+
+        #[test]
+        fn test_follower_rejects_compressed_input() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log.gz");
+            let mut encoder =
+                flate2::write::GzEncoder::new(File::create(&path).unwrap(), Default::default());
+            encoder.write_all(b"zipped\n").unwrap();
+            encoder.finish().unwrap();
+
+            let config = Config::default();
+
+            // When
+            let result = Follower::start(&[path], &config);
+
+            // Then
+            assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    mod test_follow_files {
+        use super::*;
+
+        fn follow_once(updates: Updates, config: &Config) -> (PathBuf, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            let output_path = temp_dir.path().join("out/updates.txt");
+            fs::write(&path, "the first words\n").unwrap();
+            let follow = FollowConfig {
+                interval: Duration::from_millis(1),
+                updates,
+            };
+            let mut calls = 0;
+            follow_files(
+                std::slice::from_ref(&path),
+                &Output::File(output_path.clone()),
+                config,
+                &follow,
+                || {
+                    calls += 1;
+                    if calls == 1 {
+                        append(&path, "the words again\n");
+                    }
+                    calls > 1
+                },
+            )
+            .unwrap();
+            (output_path, temp_dir)
+        }
+
+        #[test]
+        fn test_follow_files_appends_delta_events() {
+            // When
+            let (output_path, _temp_dir) = follow_once(Updates::Delta, &Config::default());
+
+            // Then
+            let events: Vec<serde_json::Value> = fs::read_to_string(&output_path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0]["counts"]["first"], 1);
+            assert_eq!(
+                events[1]["counts"],
+                serde_json::json!({"the": 1, "words": 1, "again": 1})
+            );
+            assert!(events[1]["time_ms"].as_u64().unwrap() > 0);
+        }
+
+        #[test]
+        fn test_follow_files_renders_filtered_totals() {
+            // Given
+            let mut stop_words = crate::stopwords::StopWords::default();
+            stop_words.add_pattern("^the$").unwrap();
+            let config = Config {
+                stop_words: std::sync::Arc::new(stop_words),
+                ..Config::default()
+            };
+
+            // When
+            let (output_path, _temp_dir) = follow_once(Updates::Render, &config);
+
+            // Then: the file holds the last render only.
+            assert_eq!(
+                fs::read_to_string(&output_path).unwrap(),
+                "again: 1\nfirst: 1\nwords: 2\n\n# filtered stop words: 2\n"
+            );
+        }
+    }
+}
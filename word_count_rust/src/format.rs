@@ -0,0 +1,666 @@
+use std::io::{self, Write};
+
+/// Output format of the results, as selected with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// One "word: count" line per word
+    #[default]
+    Plain,
+    /// A single JSON object mapping each word to its count
+    Json,
+    /// "word,count" rows after a header, quoted where needed
+    Csv,
+    /// Like csv, separated by tabs
+    Tsv,
+    /// One {"word": ..., "count": ...} object per line
+    Ndjson,
+}
+
+impl Format {
+    pub(crate) fn writer(self) -> Box<dyn ResultWriter> {
+        match self {
+            Format::Plain => Box::new(PlainWriter),
+            Format::Json => Box::new(JsonWriter),
+            Format::Csv => Box::new(DelimitedWriter { delimiter: b',' }),
+            Format::Tsv => Box::new(DelimitedWriter { delimiter: b'\t' }),
+            Format::Ndjson => Box::new(NdjsonWriter),
+        }
+    }
+}
+
+/// Totals written after the entries, by the formats that have room for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Footer {
+    /// Occurrences left out as stop words, when stop words are in use.
+    pub(crate) filtered: Option<usize>,
+}
+
+/// The sorted entries of one input, for `--per-file` reports.
+pub(crate) type FileEntries<'a> = (&'a str, Vec<(&'a str, usize)>);
+
+/// The counts of a word in every time bucket, for `--buckets` reports.
+pub(crate) type WordSeries<'a> = (&'a str, Vec<usize>);
+
+/// A word trending up into the last bucket, with its counts in the bucket before and in the last.
+pub(crate) type Trend<'a> = (&'a str, usize, usize);
+
+/// Writes sorted `(word, count)` entries in one output format.
+pub(crate) trait ResultWriter {
+    fn write_entries(&self, writer: &mut dyn Write, entries: &[(&str, usize)]) -> io::Result<()>;
+
+    /// Writes the entries of every input, in the order given.
+    fn write_files(&self, writer: &mut dyn Write, files: &[FileEntries]) -> io::Result<()>;
+
+    /// Writes the series of every word, each with one count per bucket of `buckets`, then the
+    /// `trending` words.
+    fn write_series(
+        &self,
+        writer: &mut dyn Write,
+        buckets: &[String],
+        series: &[WordSeries],
+        trending: &[Trend],
+    ) -> io::Result<()>;
+
+    /// Structured formats write nothing, so that every reader of the format keeps working.
+    fn write_footer(&self, _writer: &mut dyn Write, _footer: &Footer) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The original `word: count` lines. Words are written as they are, so the format can only be
+/// read back by splitting each line at its last ": ".
+pub(crate) struct PlainWriter;
+
+impl ResultWriter for PlainWriter {
+    fn write_entries(&self, writer: &mut dyn Write, entries: &[(&str, usize)]) -> io::Result<()> {
+        for (word, count) in entries {
+            writeln!(writer, "{}: {}", word, count)?;
+        }
+        Ok(())
+    }
+
+    /// A `==> path <==` header above the lines of every input, as `head` and `tail` do.
+    fn write_files(&self, writer: &mut dyn Write, files: &[FileEntries]) -> io::Result<()> {
+        for (i, (file, entries)) in files.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            writeln!(writer, "==> {} <==", file)?;
+            self.write_entries(writer, entries)?;
+        }
+        Ok(())
+    }
+
+    /// A `# buckets:` line naming the buckets, then the counts of each word in bucket order and
+    /// a `# trending: word before -> after` line per trending word.
+    fn write_series(
+        &self,
+        writer: &mut dyn Write,
+        buckets: &[String],
+        series: &[WordSeries],
+        trending: &[Trend],
+    ) -> io::Result<()> {
+        writeln!(writer, "# buckets: {}", buckets.join(" "))?;
+        for (word, counts) in series {
+            let counts: Vec<String> = counts.iter().map(usize::to_string).collect();
+            writeln!(writer, "{}: {}", word, counts.join(" "))?;
+        }
+        for (word, before, after) in trending {
+            writeln!(writer, "# trending: {} {} -> {}", word, before, after)?;
+        }
+        Ok(())
+    }
+
+    /// A blank line, then one `# name: value` line per total.
+    fn write_footer(&self, writer: &mut dyn Write, footer: &Footer) -> io::Result<()> {
+        if let Some(filtered) = footer.filtered {
+            writeln!(writer)?;
+            writeln!(writer, "# filtered stop words: {}", filtered)?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON object with one member per word, in the order of the entries.
+pub(crate) struct JsonWriter;
+
+impl ResultWriter for JsonWriter {
+    fn write_entries(&self, writer: &mut dyn Write, entries: &[(&str, usize)]) -> io::Result<()> {
+        if entries.is_empty() {
+            return writeln!(writer, "{{}}");
+        }
+        writeln!(writer, "{{")?;
+        for (i, (word, count)) in entries.iter().enumerate() {
+            let separator = if i + 1 < entries.len() { "," } else { "" };
+            writeln!(writer, "  {}: {}{}", json_string(word), count, separator)?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    /// An object with the list of buckets, the counts of each word under `series` and, under
+    /// `trending`, one `{"word", "before", "after"}` object per trending word.
+    fn write_series(
+        &self,
+        writer: &mut dyn Write,
+        buckets: &[String],
+        series: &[WordSeries],
+        trending: &[Trend],
+    ) -> io::Result<()> {
+        let buckets: Vec<String> = buckets.iter().map(|bucket| json_string(bucket)).collect();
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"buckets\": [{}],", buckets.join(", "))?;
+        if series.is_empty() {
+            writeln!(writer, "  \"series\": {{}},")?;
+        } else {
+            writeln!(writer, "  \"series\": {{")?;
+            for (i, (word, counts)) in series.iter().enumerate() {
+                let counts: Vec<String> = counts.iter().map(usize::to_string).collect();
+                let separator = if i + 1 < series.len() { "," } else { "" };
+                writeln!(
+                    writer,
+                    "    {}: [{}]{}",
+                    json_string(word),
+                    counts.join(", "),
+                    separator
+                )?;
+            }
+            writeln!(writer, "  }},")?;
+        }
+        if trending.is_empty() {
+            writeln!(writer, "  \"trending\": []")?;
+            return writeln!(writer, "}}");
+        }
+        writeln!(writer, "  \"trending\": [")?;
+        for (i, (word, before, after)) in trending.iter().enumerate() {
+            let separator = if i + 1 < trending.len() { "," } else { "" };
+            writeln!(
+                writer,
+                "    {{\"word\": {}, \"before\": {}, \"after\": {}}}{}",
+                json_string(word),
+                before,
+                after,
+                separator
+            )?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")
+    }
+
+    /// An object with one member per input, each holding the object of its words.
+    fn write_files(&self, writer: &mut dyn Write, files: &[FileEntries]) -> io::Result<()> {
+        if files.is_empty() {
+            return writeln!(writer, "{{}}");
+        }
+        writeln!(writer, "{{")?;
+        for (i, (file, entries)) in files.iter().enumerate() {
+            let separator = if i + 1 < files.len() { "," } else { "" };
+            if entries.is_empty() {
+                writeln!(writer, "  {}: {{}}{}", json_string(file), separator)?;
+                continue;
+            }
+            writeln!(writer, "  {}: {{", json_string(file))?;
+            for (j, (word, count)) in entries.iter().enumerate() {
+                let comma = if j + 1 < entries.len() { "," } else { "" };
+                writeln!(writer, "    {}: {}{}", json_string(word), count, comma)?;
+            }
+            writeln!(writer, "  }}{}", separator)?;
+        }
+        writeln!(writer, "}}")
+    }
+}
+
+/// CSV or TSV with a `word,count` header; fields are quoted when they contain the delimiter,
+/// a quote or a line break.
+pub(crate) struct DelimitedWriter {
+    delimiter: u8,
+}
+
+impl ResultWriter for DelimitedWriter {
+    fn write_entries(&self, writer: &mut dyn Write, entries: &[(&str, usize)]) -> io::Result<()> {
+        let mut csv_writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(writer);
+        csv_writer.write_record(["word", "count"])?;
+        for (word, count) in entries {
+            csv_writer.write_record([*word, &count.to_string()])?;
+        }
+        csv_writer.flush()
+    }
+
+    /// A header of `word` and the buckets, then one row per word. Trending words follow after a
+    /// blank line, as a second table with a `trending,before,after` header.
+    fn write_series(
+        &self,
+        writer: &mut dyn Write,
+        buckets: &[String],
+        series: &[WordSeries],
+        trending: &[Trend],
+    ) -> io::Result<()> {
+        let mut csv_writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(writer);
+        csv_writer
+            .write_record(std::iter::once("word").chain(buckets.iter().map(String::as_str)))?;
+        for (word, counts) in series {
+            let counts = counts.iter().map(usize::to_string);
+            csv_writer.write_record(std::iter::once(word.to_string()).chain(counts))?;
+        }
+        csv_writer.flush()?;
+        if trending.is_empty() {
+            return Ok(());
+        }
+        let writer = csv_writer.into_inner().map_err(|err| err.into_error())?;
+        writeln!(writer)?;
+        let mut csv_writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(writer);
+        csv_writer.write_record(["trending", "before", "after"])?;
+        for (word, before, after) in trending {
+            csv_writer.write_record([*word, &before.to_string(), &after.to_string()])?;
+        }
+        csv_writer.flush()
+    }
+
+    /// A `file,word,count` header, then one row per word of every input.
+    fn write_files(&self, writer: &mut dyn Write, files: &[FileEntries]) -> io::Result<()> {
+        let mut csv_writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(writer);
+        csv_writer.write_record(["file", "word", "count"])?;
+        for (file, entries) in files {
+            for (word, count) in entries {
+                csv_writer.write_record([*file, *word, &count.to_string()])?;
+            }
+        }
+        csv_writer.flush()
+    }
+}
+
+/// One JSON object per line, so results can be streamed into line-oriented tools.
+pub(crate) struct NdjsonWriter;
+
+impl ResultWriter for NdjsonWriter {
+    fn write_entries(&self, writer: &mut dyn Write, entries: &[(&str, usize)]) -> io::Result<()> {
+        for (word, count) in entries {
+            writeln!(
+                writer,
+                "{{\"word\":{},\"count\":{}}}",
+                json_string(word),
+                count
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_files(&self, writer: &mut dyn Write, files: &[FileEntries]) -> io::Result<()> {
+        for (file, entries) in files {
+            for (word, count) in entries {
+                writeln!(
+                    writer,
+                    "{{\"file\":{},\"word\":{},\"count\":{}}}",
+                    json_string(file),
+                    json_string(word),
+                    count
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// One object per word, with its count in each bucket, then one
+    /// `{"trending": ..., "before": ..., "after": ...}` object per trending word.
+    fn write_series(
+        &self,
+        writer: &mut dyn Write,
+        buckets: &[String],
+        series: &[WordSeries],
+        trending: &[Trend],
+    ) -> io::Result<()> {
+        for (word, counts) in series {
+            let counts: Vec<String> = buckets
+                .iter()
+                .zip(counts)
+                .map(|(bucket, count)| format!("{}:{}", json_string(bucket), count))
+                .collect();
+            writeln!(
+                writer,
+                "{{\"word\":{},\"series\":{{{}}}}}",
+                json_string(word),
+                counts.join(",")
+            )?;
+        }
+        for (word, before, after) in trending {
+            writeln!(
+                writer,
+                "{{\"trending\":{},\"before\":{},\"after\":{}}}",
+                json_string(word),
+                before,
+                after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn json_string(word: &str) -> String {
+    serde_json::Value::from(word).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+
+    /// Entries that trip up naive writers: separators, quotes, escapes and non-ASCII text.
+    const TRICKY: &[(&str, usize)] = &[
+        ("plain", 1),
+        ("http://example.com", 2),
+        ("a,b", 3),
+        ("tab\there", 4),
+        ("say \"hi\"", 5),
+        ("back\\slash", 6),
+        ("line\nbreak", 7),
+        ("北京", 8),
+        ("", 9),
+    ];
+
+    fn write(format: Format, entries: &[(&str, usize)]) -> String {
+        let mut buffer = Vec::new();
+        format.writer().write_entries(&mut buffer, entries).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// The readers matching each writer.
+    fn read(format: Format, text: &str) -> Vec<(String, usize)> {
+        match format {
+            Format::Plain => text
+                .lines()
+                .map(|line| {
+                    let (word, count) = line.rsplit_once(": ").unwrap();
+                    (word.to_string(), count.parse().unwrap())
+                })
+                .collect(),
+            Format::Json => {
+                let object: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_str(text).unwrap();
+                object
+                    .into_iter()
+                    .map(|(word, count)| (word, count.as_u64().unwrap() as usize))
+                    .collect()
+            }
+            Format::Csv | Format::Tsv => {
+                let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+                let mut reader = csv::ReaderBuilder::new()
+                    .delimiter(delimiter)
+                    .from_reader(text.as_bytes());
+                assert_eq!(reader.headers().unwrap(), vec!["word", "count"]);
+                reader
+                    .records()
+                    .map(|record| {
+                        let record = record.unwrap();
+                        (record[0].to_string(), record[1].parse().unwrap())
+                    })
+                    .collect()
+            }
+            Format::Ndjson => text
+                .as_bytes()
+                .lines()
+                .map(|line| {
+                    let value: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+                    (
+                        value["word"].as_str().unwrap().to_string(),
+                        value["count"].as_u64().unwrap() as usize,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn sorted(mut entries: Vec<(String, usize)>) -> Vec<(String, usize)> {
+        entries.sort();
+        entries
+    }
+
+    fn owned(entries: &[(&str, usize)]) -> Vec<(String, usize)> {
+        entries.iter().map(|&(w, c)| (w.to_string(), c)).collect()
+    }
+
+    mod test_round_trip {
+        use super::*;
+
+        #[test]
+        fn test_structured_formats_round_trip_tricky_words() {
+            for format in [Format::Json, Format::Csv, Format::Tsv, Format::Ndjson] {
+                // When
+                let text = write(format, TRICKY);
+
+                // Then
+                assert_eq!(
+                    sorted(read(format, &text)),
+                    sorted(owned(TRICKY)),
+                    "{:?}:\n{}",
+                    format,
+                    text
+                );
+            }
+        }
+
+        #[test]
+        fn test_plain_round_trips_words_with_colons() {
+            // Given
+            let entries = [("plain", 1), ("http://example.com", 2), ("key: value", 3)];
+
+            // When
+            let text = write(Format::Plain, &entries);
+
+            // Then
+            assert_eq!(read(Format::Plain, &text), owned(&entries));
+        }
+
+        #[test]
+        fn test_every_format_round_trips_empty_results() {
+            for format in [
+                Format::Plain,
+                Format::Json,
+                Format::Csv,
+                Format::Tsv,
+                Format::Ndjson,
+            ] {
+                // When
+                let text = write(format, &[]);
+
+                // Then
+                assert!(read(format, &text).is_empty(), "{:?}: {:?}", format, text);
+            }
+        }
+    }
+
+    mod test_writers {
+        use super::*;
+
+        #[test]
+        fn test_plain_writer_keeps_original_format() {
+            // When
+            let text = write(Format::Plain, &[("apple", 2), ("banana", 1)]);
+
+            // Then
+            assert_eq!(text, "apple: 2\nbanana: 1\n");
+        }
+
+        #[test]
+        fn test_only_plain_writer_writes_footer() {
+            // Given
+            let footer = Footer { filtered: Some(7) };
+
+            for format in [Format::Plain, Format::Json, Format::Csv, Format::Ndjson] {
+                // When
+                let mut buffer = Vec::new();
+                let writer = format.writer();
+                writer.write_entries(&mut buffer, &[("cat", 1)]).unwrap();
+                writer.write_footer(&mut buffer, &footer).unwrap();
+                writer
+                    .write_footer(&mut buffer, &Footer::default())
+                    .unwrap();
+
+                // Then
+                let text = String::from_utf8(buffer).unwrap();
+                assert_eq!(
+                    text == write(format, &[("cat", 1)]),
+                    format != Format::Plain
+                );
+                if format == Format::Plain {
+                    assert_eq!(text, "cat: 1\n\n# filtered stop words: 7\n");
+                }
+            }
+        }
+
+        #[test]
+        fn test_per_file_writers_keep_file_order() {
+            // Given
+            let files: Vec<FileEntries> =
+                vec![("b.txt", vec![("cat", 2), ("a,b", 1)]), ("a.txt", vec![])];
+
+            for (format, expected) in [
+                (Format::Plain, "==> b.txt <==\ncat: 2\na,b: 1\n\n==> a.txt <==\n"),
+                (
+                    Format::Json,
+                    "{\n  \"b.txt\": {\n    \"cat\": 2,\n    \"a,b\": 1\n  },\n  \"a.txt\": {}\n}\n",
+                ),
+                (Format::Csv, "file,word,count\nb.txt,cat,2\nb.txt,\"a,b\",1\n"),
+                (
+                    Format::Ndjson,
+                    "{\"file\":\"b.txt\",\"word\":\"cat\",\"count\":2}\n\
+                     {\"file\":\"b.txt\",\"word\":\"a,b\",\"count\":1}\n",
+                ),
+            ] {
+                // When
+                let mut buffer = Vec::new();
+                format.writer().write_files(&mut buffer, &files).unwrap();
+
+                // Then
+                assert_eq!(String::from_utf8(buffer).unwrap(), expected, "{:?}", format);
+            }
+        }
+
+        #[test]
+        fn test_writers_write_series_per_word() {
+            // Given
+            let buckets = ["10:00".to_string(), "11:00".to_string()];
+            let series = [("cat", vec![2, 0]), ("a,b", vec![0, 1])];
+
+            for (format, expected) in [
+                (Format::Plain, "# buckets: 10:00 11:00\ncat: 2 0\na,b: 0 1\n"),
+                (
+                    Format::Json,
+                    "{\n  \"buckets\": [\"10:00\", \"11:00\"],\n  \"series\": {\n    \"cat\": [2, 0],\n    \"a,b\": [0, 1]\n  },\n  \"trending\": []\n}\n",
+                ),
+                (Format::Csv, "word,10:00,11:00\ncat,2,0\n\"a,b\",0,1\n"),
+                (
+                    Format::Ndjson,
+                    "{\"word\":\"cat\",\"series\":{\"10:00\":2,\"11:00\":0}}\n\
+                     {\"word\":\"a,b\",\"series\":{\"10:00\":0,\"11:00\":1}}\n",
+                ),
+            ] {
+                // When
+                let mut buffer = Vec::new();
+                format
+                    .writer()
+                    .write_series(&mut buffer, &buckets, &series, &[])
+                    .unwrap();
+
+                // Then
+                assert_eq!(String::from_utf8(buffer).unwrap(), expected, "{:?}", format);
+            }
+        }
+
+        #[test]
+        fn test_writers_write_trending_words_after_series() {
+            // Given
+            let buckets = ["10:00".to_string(), "11:00".to_string()];
+            let series = [("cat", vec![2, 0])];
+            let trending = [("a,b", 0, 3), ("dog", 1, 2)];
+
+            for (format, expected) in [
+                (
+                    Format::Plain,
+                    "# buckets: 10:00 11:00\ncat: 2 0\n# trending: a,b 0 -> 3\n# trending: dog 1 -> 2\n",
+                ),
+                (
+                    Format::Json,
+                    "{\n  \"buckets\": [\"10:00\", \"11:00\"],\n  \"series\": {\n    \"cat\": [2, 0]\n  },\n  \"trending\": [\n    {\"word\": \"a,b\", \"before\": 0, \"after\": 3},\n    {\"word\": \"dog\", \"before\": 1, \"after\": 2}\n  ]\n}\n",
+                ),
+                (
+                    Format::Csv,
+                    "word,10:00,11:00\ncat,2,0\n\ntrending,before,after\n\"a,b\",0,3\ndog,1,2\n",
+                ),
+                (
+                    Format::Tsv,
+                    "word\t10:00\t11:00\ncat\t2\t0\n\ntrending\tbefore\tafter\na,b\t0\t3\ndog\t1\t2\n",
+                ),
+                (
+                    Format::Ndjson,
+                    "{\"word\":\"cat\",\"series\":{\"10:00\":2,\"11:00\":0}}\n\
+                     {\"trending\":\"a,b\",\"before\":0,\"after\":3}\n\
+                     {\"trending\":\"dog\",\"before\":1,\"after\":2}\n",
+                ),
+            ] {
+                // When
+                let mut buffer = Vec::new();
+                format
+                    .writer()
+                    .write_series(&mut buffer, &buckets, &series, &trending)
+                    .unwrap();
+
+                // Then
+                let text = String::from_utf8(buffer).unwrap();
+                assert_eq!(text, expected, "{:?}", format);
+                if format == Format::Json {
+                    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    assert_eq!(value["trending"][0]["after"], 3);
+                }
+            }
+        }
+
+        #[test]
+        fn test_json_writer_keeps_entry_order() {
+            // When
+            let text = write(Format::Json, &[("b", 2), ("a", 1)]);
+
+            // Then
+            assert_eq!(text, "{\n  \"b\": 2,\n  \"a\": 1\n}\n");
+        }
+
+        #[test]
+        fn test_csv_writer_quotes_only_when_needed() {
+            // When
+            let text = write(Format::Csv, &[("plain", 1), ("a,b", 2), ("say \"hi\"", 3)]);
+
+            // Then
+            assert_eq!(
+                text,
+                "word,count\nplain,1\n\"a,b\",2\n\"say \"\"hi\"\"\",3\n"
+            );
+        }
+
+        #[test]
+        fn test_tsv_writer_uses_tabs() {
+            // When
+            let text = write(Format::Tsv, &[("a,b", 2), ("tab\there", 1)]);
+
+            // Then
+            assert_eq!(text, "word\tcount\na,b\t2\n\"tab\there\"\t1\n");
+        }
+
+        #[test]
+        fn test_ndjson_writer_writes_one_object_per_line() {
+            // When
+            let text = write(Format::Ndjson, &[("x", 1), ("y\"", 2)]);
+
+            // Then
+            assert_eq!(
+                text,
+                "{\"word\":\"x\",\"count\":1}\n{\"word\":\"y\\\"\",\"count\":2}\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::error::at_path;
+use crate::merge::merge_counts;
+use crate::progress::{debug, info};
+use crate::schedule::{self, chunkable_size};
+use crate::sketch::hash_bytes;
+use crate::{Config, FileChunk};
+
+/// Version of the state file; a state file of another version is ignored and every input is
+/// counted again.
+const STATE_VERSION: u32 = 1;
+/// Bytes at the start of a file, and just before its offset, that its fingerprint covers.
+const FINGERPRINT_SIZE: u64 = 4096;
+/// Bytes read at a time when looking back for the last newline of a file.
+const SCAN_SIZE: usize = 64 * 1024;
+
+/// What a run with `--state` remembers of each regular input for the next one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct State {
+    version: u32,
+    /// How the words were counted; counts made another way are not added to.
+    settings: String,
+    /// Keyed by canonical path, so a file is found again from any working directory.
+    files: BTreeMap<PathBuf, FileState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileState {
+    /// End of the last complete line counted.
+    offset: u64,
+    fingerprint: Fingerprint,
+    /// Counts of the bytes before `offset`, before stop words and stemming.
+    counts: HashMap<String, usize>,
+}
+
+/// Hashes of the first bytes of a file and of the bytes just before an offset. A file that was
+/// only appended to since still has both; one that was truncated below the offset or rewritten
+/// almost certainly has not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    head: u64,
+    tail: u64,
+}
+
+impl Fingerprint {
+    /// The fingerprint of `file` at `offset`, which must not be past its end.
+    fn of(file: &mut File, offset: u64) -> io::Result<Self> {
+        let len = offset.min(FINGERPRINT_SIZE);
+        Ok(Fingerprint {
+            head: hash_bytes(&read_at(file, 0, len)?),
+            tail: hash_bytes(&read_at(file, offset - len, len)?),
+        })
+    }
+}
+
+fn read_at(file: &mut File, start: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; len as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Everything in `config` that changes which words the bytes of a file are counted as, or
+/// `None` for a tokenizer without a fingerprint. Stop words and stemming are applied to the
+/// totals afterwards, so they may change freely.
+fn settings(config: &Config) -> Option<String> {
+    Some(format!(
+        "{}; {}; {}",
+        config.tokenizer.fingerprint()?,
+        config.normalizer.fingerprint(),
+        config.invalid_utf8.fingerprint()
+    ))
+}
+
+impl State {
+    fn new(settings: String) -> Self {
+        State {
+            version: STATE_VERSION,
+            settings,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// The state saved at `path`, or an empty one when there is none or it cannot be added to.
+    fn load(path: &Path, settings: String) -> io::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(State::new(settings)),
+            Err(err) => return Err(err),
+        };
+        let state: State = serde_json::from_reader(BufReader::new(file))?;
+        if state.version != STATE_VERSION {
+            info!(
+                "{} has state version {}; counting every input again",
+                path.display(),
+                state.version
+            );
+            return Ok(State::new(settings));
+        }
+        if state.settings != settings {
+            info!(
+                "{} was counted with other tokenizer, normalization or UTF-8 settings; counting \
+                 every input again",
+                path.display()
+            );
+            return Ok(State::new(settings));
+        }
+        Ok(state)
+    }
+
+    /// Replaces the file at `path` as a whole, so an interrupted save leaves the old state.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        let file = NamedTempFile::new_in(dir)?;
+        let mut writer = BufWriter::new(file.as_file());
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+}
+
+/// How one input is counted.
+enum Plan {
+    /// Counted in full and not remembered: stdin, pipes, compressed and UTF-16 inputs.
+    Whole,
+    /// A regular file, counted from `start` (where the last run stopped, or 0) to `end`, the end
+    /// of its last complete line, which is remembered; then from `end` to `size`, which is not.
+    Range {
+        key: PathBuf,
+        start: u64,
+        end: u64,
+        size: u64,
+    },
+}
+
+/// Counts `input_files` like `schedule::count_files`, but resumes every regular file where the
+/// run that saved `state_path` stopped, if it was only appended to since; then saves the new
+/// state. The counts returned are of the whole inputs either way.
+///
+/// A line that does not end in a newline yet is counted but not remembered, since it may still
+/// be growing; the next run counts it again once it is complete.
+pub(crate) fn count_files(
+    input_files: &[PathBuf],
+    config: &Config,
+    state_path: &Path,
+) -> io::Result<Vec<HashMap<String, usize>>> {
+    let mut state = match settings(config) {
+        Some(settings) => State::load(state_path, settings).map_err(at_path(state_path))?,
+        None => {
+            info!("The tokenizer has no fingerprint; counting every input again");
+            State::new(String::new())
+        }
+    };
+
+    let mut plans = Vec::with_capacity(input_files.len());
+    let (mut paths, mut ranges) = (Vec::new(), Vec::new());
+    for path in input_files {
+        let plan = plan(path, &state, config).map_err(at_path(path))?;
+        match &plan {
+            Plan::Whole => {
+                paths.push(path.clone());
+                ranges.push(None);
+            }
+            &Plan::Range {
+                start, end, size, ..
+            } => {
+                paths.push(path.clone());
+                ranges.push(Some(FileChunk { start, end }));
+                if end < size {
+                    paths.push(path.clone());
+                    ranges.push(Some(FileChunk {
+                        start: end,
+                        end: size,
+                    }));
+                }
+            }
+        }
+        plans.push(plan);
+    }
+
+    let mut counted = schedule::count_file_ranges(&paths, &ranges, config)?.into_iter();
+    let mut file_counts = Vec::with_capacity(input_files.len());
+    let mut resumed = 0;
+    for (path, plan) in input_files.iter().zip(plans) {
+        let new_counts = counted.next().expect("one result per range");
+        let Plan::Range {
+            key,
+            start,
+            end,
+            size,
+        } = plan
+        else {
+            file_counts.push(new_counts);
+            continue;
+        };
+        let mut counts = match state.files.get(&key) {
+            Some(file_state) if start > 0 => {
+                resumed += 1;
+                file_state.counts.clone()
+            }
+            _ => HashMap::new(),
+        };
+        merge_counts(&mut counts, new_counts);
+        let fingerprint = File::open(path)
+            .and_then(|mut file| Fingerprint::of(&mut file, end))
+            .map_err(at_path(path))?;
+        let mut whole_counts = counts.clone();
+        if end < size {
+            merge_counts(
+                &mut whole_counts,
+                counted.next().expect("one result per range"),
+            );
+        }
+        state.files.insert(
+            key,
+            FileState {
+                offset: end,
+                fingerprint,
+                counts,
+            },
+        );
+        file_counts.push(whole_counts);
+    }
+
+    state.save(state_path).map_err(at_path(state_path))?;
+    info!(
+        "Resumed {} of {} inputs from {}",
+        resumed,
+        input_files.len(),
+        state_path.display()
+    );
+    Ok(file_counts)
+}
+
+/// Decides where counting `path` starts and what of it is remembered.
+fn plan(path: &Path, state: &State, config: &Config) -> io::Result<Plan> {
+    let Some(size) = chunkable_size(path, config)? else {
+        return Ok(Plan::Whole);
+    };
+    let key = fs::canonicalize(path)?;
+    let mut file = File::open(path)?;
+    let start = match state.files.get(&key) {
+        Some(file_state)
+            if file_state.offset <= size
+                && Fingerprint::of(&mut file, file_state.offset)? == file_state.fingerprint =>
+        {
+            debug!(
+                "Resuming {} at byte {} of {}",
+                path.display(),
+                file_state.offset,
+                size
+            );
+            file_state.offset
+        }
+        Some(_) => {
+            info!(
+                "{} was truncated or rewritten; counting it again",
+                path.display()
+            );
+            0
+        }
+        None => 0,
+    };
+    let end = last_line_end(&mut file, start, size)?;
+    Ok(Plan::Range {
+        key,
+        start,
+        end,
+        size,
+    })
+}
+
+/// The position just after the last newline between `start` and `size`, or `start` if there is
+/// none.
+pub(crate) fn last_line_end(file: &mut File, start: u64, size: u64) -> io::Result<u64> {
+    let mut end = size;
+    while end > start {
+        let block_start = end.saturating_sub(SCAN_SIZE as u64).max(start);
+        let block = read_at(file, block_start, end - block_start)?;
+        if let Some(i) = block.iter().rposition(|&byte| byte == b'\n') {
+            return Ok(block_start + i as u64 + 1);
+        }
+        end = block_start;
+    }
+    Ok(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cjk::{CjkSegmenter, Lexicon};
+    use crate::normalize::{CaseMode, Normalizer};
+    use crate::test_util::append;
+    use crate::tokenizer::{Tokenizer, WhitespaceTokenizer};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn count(path: &Path, config: &Config, state_path: &Path) -> HashMap<String, usize> {
+        count_files(&[path.to_path_buf()], config, state_path)
+            .unwrap()
+            .remove(0)
+    }
+
+    fn saved_offset(state_path: &Path) -> u64 {
+        let state: State = serde_json::from_str(&fs::read_to_string(state_path).unwrap()).unwrap();
+        state.files.values().next().unwrap().offset
+    }
+
+    mod test_count_files {
+        use super::*;
+
+        #[test]
+        fn test_count_files_counts_only_appended_lines() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let (path, state_path) = (
+                temp_dir.path().join("app.log"),
+                temp_dir.path().join("state.json"),
+            );
+            fs::write(&path, "alpha beta\n").unwrap();
+            let config = Config::default();
+            count(&path, &config, &state_path);
+            append(&path, "beta gamma\n");
+
+            // When
+            let result = count(&path, &config, &state_path);
+
+            // Then
+            assert_eq!(result, crate::count_file(&path, &config).unwrap());
+            assert_eq!(saved_offset(&state_path), 22);
+            // The saved counts are taken as they are, not counted again.
+            let mut state: State =
+                serde_json::from_str(&fs::read_to_string(&state_path).unwrap()).unwrap();
+            state
+                .files
+                .values_mut()
+                .next()
+                .unwrap()
+                .counts
+                .insert("marker".to_string(), 7);
+            fs::write(&state_path, serde_json::to_string(&state).unwrap()).unwrap();
+            append(&path, "delta\n");
+            let result = count(&path, &config, &state_path);
+            assert_eq!(result["marker"], 7);
+            assert_eq!(result["delta"], 1);
+            assert_eq!(result["beta"], 2);
+        }
+
+        #[test]
+        fn test_count_files_leaves_incomplete_line_for_next_run() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let (path, state_path) = (
+                temp_dir.path().join("app.log"),
+                temp_dir.path().join("state.json"),
+            );
+            fs::write(&path, "one two\nthr").unwrap();
+            let config = Config::default();
+
+            // When
+            let first = count(&path, &config, &state_path);
+            append(&path, "ee\n");
+            let second = count(&path, &config, &state_path);
+
+            // Then
+            assert_eq!(first["thr"], 1);
+            assert_eq!(second.get("thr"), None);
+            assert_eq!(second["three"], 1);
+            assert_eq!(saved_offset(&state_path), 14);
+        }
+
+        #[test]
+        fn test_count_files_recounts_truncated_and_rewritten_files() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let (path, state_path) = (
+                temp_dir.path().join("app.log"),
+                temp_dir.path().join("state.json"),
+            );
+            let config = Config::default();
+            fs::write(&path, "old old old\n").unwrap();
+            count(&path, &config, &state_path);
+
+            // When
+            fs::write(&path, "new\n").unwrap();
+            let truncated = count(&path, &config, &state_path);
+            fs::write(&path, "NEW\nmore lines\n").unwrap();
+            let rewritten = count(&path, &config, &state_path);
+
+            // Then
+            assert_eq!(truncated, HashMap::from([("new".to_string(), 1)]));
+            assert_eq!(rewritten, crate::count_file(&path, &config).unwrap());
+        }
+
+        #[test]
+        fn test_count_files_recounts_with_other_settings() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let (path, state_path) = (
+                temp_dir.path().join("app.log"),
+                temp_dir.path().join("state.json"),
+            );
+            fs::write(&path, "Word word\n").unwrap();
+            count(&path, &Config::default(), &state_path);
+            let preserve = Config {
+                normalizer: Normalizer {
+                    case: CaseMode::Preserve,
+                    ..Normalizer::default()
+                },
+                ..Config::default()
+            };
+
+            // When
+            let result = count(&path, &preserve, &state_path);
+
+            // Then
+            assert_eq!(
+                result,
+                HashMap::from([("Word".to_string(), 1), ("word".to_string(), 1)])
+            );
+        }
+
+        #[test]
+        fn test_count_files_recounts_with_another_cjk_dictionary() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let (path, state_path) = (
+                temp_dir.path().join("app.log"),
+                temp_dir.path().join("state.json"),
+            );
+            fs::write(&path, "学习中文\n").unwrap();
+            let with_words = |entries: &str| {
+                let mut lexicon = Lexicon::default();
+                lexicon.add_entries(entries);
+                Config {
+                    tokenizer: Arc::new(CjkSegmenter::new(Arc::new(WhitespaceTokenizer), lexicon)),
+                    ..Config::default()
+                }
+            };
+            count(&path, &with_words("学习\n中文\n"), &state_path);
+
+            // When
+            let result = count(&path, &with_words("学习中文\n"), &state_path);
+
+            // Then
+            assert_eq!(result, HashMap::from([("学习中文".to_string(), 1)]));
+        }
+
+        #[test]
+        fn test_count_files_never_resumes_without_a_tokenizer_fingerprint() {
+            // Given
+            #[derive(Debug)]
+            struct Split(char);
+            impl Tokenizer for Split {
+                fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+                    text.split(self.0).for_each(emit);
+                }
+            }
+            let temp_dir = TempDir::new().unwrap();
+            let (path, state_path) = (
+                temp_dir.path().join("app.log"),
+                temp_dir.path().join("state.json"),
+            );
+            fs::write(&path, "a,b c\n").unwrap();
+            let split_on = |separator| Config {
+                tokenizer: Arc::new(Split(separator)),
+                ..Config::default()
+            };
+            count(&path, &split_on(','), &state_path);
+
+            // When
+            let result = count(&path, &split_on(' '), &state_path);
+
+            // Then
+            assert_eq!(
+                result,
+                HashMap::from([("a,b".to_string(), 1), ("c".to_string(), 1)])
+            );
+        }
+
+        #[test]
+        fn test_count_files_counts_streams_whole_without_remembering_them() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log.gz");
+            let state_path = temp_dir.path().join("state/state.json");
+            let mut encoder =
+                flate2::write::GzEncoder::new(File::create(&path).unwrap(), Default::default());
+            encoder.write_all(b"zipped words\n").unwrap();
+            encoder.finish().unwrap();
+
+            // When
+            let first = count(&path, &Config::default(), &state_path);
+            let second = count(&path, &Config::default(), &state_path);
+
+            // Then
+            assert_eq!(first, second);
+            assert_eq!(first["zipped"], 1);
+            let state: State =
+                serde_json::from_str(&fs::read_to_string(&state_path).unwrap()).unwrap();
+            assert!(state.files.is_empty());
+        }
+    }
+}
@@ -0,0 +1,2245 @@
+//! Counts word frequencies in text files, streams and compressed inputs.
+//!
+//! The `word_count_rust` binary is a thin wrapper around [`run_cli`]; the same pipeline is
+//! available in code through [`WordCounter`], which returns a [`CountResult`] to query or
+//! write out.
+//!
+//! ```
+//! use word_count_rust::{CaseMode, TokenizerKind, WordCounter};
+//!
+//! let result = WordCounter::new()
+//!     .tokenizer(TokenizerKind::Words)
+//!     .case(CaseMode::Fold)
+//!     .count_text("Don't panic. DON'T panic!")?;
+//! assert_eq!(result.get("don't"), 2);
+//! assert_eq!(result.total(), 4);
+//! # Ok::<(), word_count_rust::WordCountError>(())
+//! ```
+
+mod approx;
+mod cjk;
+mod cli;
+mod counter;
+mod decompress;
+mod encoding;
+mod error;
+mod fields;
+mod follow;
+mod format;
+mod incremental;
+mod mapped;
+mod merge;
+mod ngram;
+mod normalize;
+mod progress;
+mod schedule;
+mod sketch;
+mod stem;
+mod stopwords;
+mod stream;
+mod timeline;
+mod tokenizer;
+mod topk;
+mod walk;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use encoding::InvalidLines;
+use format::Footer;
+use normalize::{normalize_token, Normalizer};
+use progress::{debug, info, trace, warning};
+use stem::Stemmer;
+use stopwords::StopWords;
+use tokenizer::{tokenize_lines, WhitespaceTokenizer};
+use walk::WalkConfig;
+
+pub use cli::run as run_cli;
+pub use counter::{CountResult, WordCounter};
+pub use encoding::Utf8Policy;
+pub use error::WordCountError;
+pub use format::Format;
+pub use normalize::{CaseMode, Form};
+pub use stem::StemLanguage;
+pub use stopwords::StopList;
+pub use tokenizer::{Tokenizer, TokenizerKind};
+pub use topk::Order;
+
+const NUM_THREADS: usize = 2; // default for --threads when the number of cores is unknown
+const BUFFER_SIZE: usize = 8192; // 8 KB buffer, default for --buffer-size
+const BLOCK_SIZE: usize = 1024 * 1024; // 1 MB, default for --block-size
+
+/// Input path that stands for standard input.
+const STDIN_PATH: &str = "-";
+
+/// The default for --threads: one worker per available core.
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(NUM_THREADS, |n| n.get())
+}
+
+/// Tunables for a single run, filled in from the command line.
+#[derive(Debug, Clone)]
+struct Config {
+    num_threads: usize,
+    buffer_size: usize,
+    /// Fixed size of the chunks workers take from the queue; shrinks as the input runs out when
+    /// unset.
+    chunk_size: Option<usize>,
+    /// Opt-in: copy each chunk into a file in this directory and count from those copies.
+    chunk_dir: Option<PathBuf>,
+    pipeline: Pipeline,
+    /// Size of the line-aligned blocks handed to workers when reading a stream.
+    block_size: usize,
+    /// What happens to lines that are not valid UTF-8.
+    invalid_utf8: Utf8Policy,
+    /// Tally of the lines of this run that were not valid UTF-8; every run starts a fresh one.
+    invalid_lines: InvalidLines,
+    /// Splits lines into the tokens that are counted.
+    tokenizer: Arc<dyn Tokenizer>,
+    /// Turns tokens into the words that are counted.
+    normalizer: Normalizer,
+    /// Which words are written, in which order and format.
+    report: Report,
+    /// Count with sketches instead of exact maps.
+    approx: Option<approx::SketchConfig>,
+    /// Count runs of words instead of single words.
+    ngrams: Option<ngram::NgramConfig>,
+    /// Words that are counted but left out of the results.
+    stop_words: Arc<StopWords>,
+    /// Folds the counts of inflected forms together.
+    stemmer: Arc<Stemmer>,
+    /// Which files below directory inputs are counted.
+    walk: WalkConfig,
+    /// Counts saved by the previous run, to resume regular files from; updated after this one.
+    state: Option<PathBuf>,
+    /// Keep reading the inputs as they grow and report the counts as they change.
+    follow: Option<follow::FollowConfig>,
+    /// Count per time bucket of the timestamps lines start with.
+    timeline: Option<timeline::TimelineConfig>,
+    /// Only count one field of every record instead of whole lines.
+    fields: Option<fields::FieldSelector>,
+}
+
+/// How workers get at the text of their chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum Pipeline {
+    /// Memory-map the input and count borrowed tokens in place
+    #[default]
+    Mmap,
+    /// Read each chunk into a `Vec<String>` of lines first
+    Lines,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            num_threads: default_threads(),
+            buffer_size: BUFFER_SIZE,
+            chunk_size: None,
+            chunk_dir: None,
+            pipeline: Pipeline::default(),
+            block_size: BLOCK_SIZE,
+            invalid_utf8: Utf8Policy::default(),
+            invalid_lines: InvalidLines::default(),
+            tokenizer: Arc::new(WhitespaceTokenizer),
+            normalizer: Normalizer::default(),
+            report: Report::default(),
+            approx: None,
+            ngrams: None,
+            stop_words: Arc::new(StopWords::default()),
+            stemmer: Arc::new(Stemmer::default()),
+            walk: WalkConfig::default(),
+            state: None,
+            follow: None,
+            timeline: None,
+            fields: None,
+        }
+    }
+}
+
+/// What `write_results` writes: every word, or only the `top` most frequent ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Report {
+    format: Format,
+    order: Order,
+    top: Option<usize>,
+    /// One section per input file instead of a single total.
+    per_file: bool,
+    /// Threads that select the `top` words, the run's --threads.
+    threads: usize,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report {
+            format: Format::default(),
+            order: Order::default(),
+            top: None,
+            per_file: false,
+            threads: default_threads(),
+        }
+    }
+}
+
+/// Destination of the final word counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 添加 Clone 和 Copy
+struct FileChunk {
+    start: u64,
+    end: u64,
+}
+
+fn create_chunk_file(
+    input_path: &Path,
+    chunk: &FileChunk,
+    chunk_index: usize,
+    chunk_dir: &Path,
+    buffer_size: usize,
+) -> io::Result<PathBuf> {
+    let stem = input_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_else(|| "input".into());
+    let chunk_path = chunk_dir.join(format!("{}_chunk_{}.txt", stem, chunk_index));
+    let mut chunk_file = File::create(&chunk_path)?;
+
+    if chunk.start < chunk.end {
+        let mut input_file = File::open(input_path)?;
+        input_file.seek(SeekFrom::Start(chunk.start))?;
+
+        let mut buffer = vec![0; buffer_size];
+        let mut bytes_to_read = chunk.end - chunk.start;
+
+        while bytes_to_read > 0 {
+            let bytes_read = input_file
+                .read(&mut buffer[..std::cmp::min(buffer_size as u64, bytes_to_read) as usize])?;
+            if bytes_read == 0 {
+                break;
+            }
+            chunk_file.write_all(&buffer[..bytes_read])?;
+            bytes_to_read -= bytes_read as u64;
+        }
+    }
+
+    trace!("Created chunk file: {}", chunk_path.display());
+    Ok(chunk_path)
+}
+
+/// Chunk files exported by `export_chunk_files`; they are deleted again on drop.
+struct ChunkFiles {
+    paths: Vec<PathBuf>,
+    created_dir: Option<PathBuf>,
+}
+
+impl ChunkFiles {
+    fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+impl Drop for ChunkFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            if let Err(e) = fs::remove_file(path) {
+                warning!("Failed to remove chunk file {}: {}", path.display(), e);
+            }
+        }
+        if let Some(dir) = &self.created_dir {
+            // Only removes the directory if nothing else was put in it.
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Copies every chunk of `input_path` into its own file under `chunk_dir`.
+fn export_chunk_files(
+    input_path: &Path,
+    chunks: &[FileChunk],
+    chunk_dir: &Path,
+    buffer_size: usize,
+) -> io::Result<ChunkFiles> {
+    let created_dir = if chunk_dir.exists() {
+        None
+    } else {
+        fs::create_dir_all(chunk_dir)?;
+        Some(chunk_dir.to_path_buf())
+    };
+    let mut chunk_files = ChunkFiles {
+        paths: Vec::with_capacity(chunks.len()),
+        created_dir,
+    };
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        // On error the files written so far are cleaned up when chunk_files is dropped.
+        let path = create_chunk_file(input_path, chunk, i, chunk_dir, buffer_size)?;
+        chunk_files.paths.push(path);
+    }
+
+    Ok(chunk_files)
+}
+
+fn divide_file_into_chunks(file_path: &Path, num_chunks: usize) -> io::Result<Vec<FileChunk>> {
+    let file = File::open(file_path)?;
+    let file_size = file.metadata()?.len();
+
+    trace!("File size: {} bytes", file_size);
+
+    let mut chunks = Vec::new();
+    if file_size == 0 {
+        chunks.push(FileChunk { start: 0, end: 0 });
+        return Ok(chunks);
+    }
+
+    let target_chunk_size = std::cmp::max(1, file_size / num_chunks as u64);
+    let mut current_pos = 0;
+    let mut reader = BufReader::new(file);
+
+    for i in 0..num_chunks {
+        if current_pos >= file_size {
+            break;
+        }
+
+        let mut chunk = FileChunk {
+            start: current_pos,
+            end: std::cmp::min(current_pos + target_chunk_size, file_size),
+        };
+
+        if i < num_chunks - 1 && chunk.end < file_size {
+            reader.seek(SeekFrom::Start(chunk.end))?;
+            // Split on raw bytes: finding the line end must not depend on the text being UTF-8.
+            let mut buf = Vec::new();
+            reader.read_until(b'\n', &mut buf)?;
+            chunk.end = reader.stream_position()?;
+
+            // If this chunk is too small, extend it to the next line
+            if chunk.end - chunk.start < target_chunk_size / 2 && chunk.end < file_size {
+                reader.read_until(b'\n', &mut buf)?;
+                chunk.end = reader.stream_position()?;
+            }
+        } else {
+            chunk.end = file_size;
+        }
+
+        trace!("Chunk: {:?}", chunk);
+        chunks.push(chunk);
+
+        if chunk.end == file_size {
+            break;
+        }
+
+        current_pos = chunk.end;
+    }
+
+    Ok(chunks)
+}
+
+fn read_file_chunk(
+    file_path: &Path,
+    chunk: &FileChunk,
+    buffer_size: usize,
+    invalid_utf8: Utf8Policy,
+    invalid_lines: &InvalidLines,
+) -> io::Result<Vec<String>> {
+    let file = File::open(file_path)?;
+    let mut reader = BufReader::with_capacity(buffer_size, file);
+    reader.seek(io::SeekFrom::Start(chunk.start))?;
+
+    let mut lines = Vec::new();
+
+    if chunk.start == chunk.end {
+        return Ok(lines); // Ensure the result is empty for an empty chunk
+    }
+
+    let mut buffer = Vec::new();
+    let mut bytes_read = 0;
+
+    while reader.read_until(b'\n', &mut buffer)? > 0 {
+        let line_bytes = buffer.len() as u64;
+        if bytes_read + line_bytes > chunk.end - chunk.start {
+            // If this line would exceed the chunk size, only include it if we haven't read anything yet
+            if !lines.is_empty() {
+                break;
+            }
+        }
+        let line = encoding::decode_at(
+            &buffer,
+            chunk.start + bytes_read,
+            invalid_utf8,
+            invalid_lines,
+        )?;
+        bytes_read += line_bytes;
+        if !line.trim().is_empty() {
+            lines.push(line.trim().to_string());
+        }
+        buffer.clear();
+        if bytes_read >= chunk.end - chunk.start {
+            break;
+        }
+    }
+
+    trace!("Read {} bytes from chunk", bytes_read);
+    progress::add_bytes(bytes_read);
+    Ok(lines)
+}
+
+fn count_words(
+    lines: &[String],
+    tokenizer: &dyn Tokenizer,
+    normalizer: &Normalizer,
+    thread_id: usize,
+) -> HashMap<String, usize> {
+    let mut word_count = HashMap::new();
+    let mut total_words = 0;
+
+    for line in lines {
+        tokenize_lines(tokenizer, line, &mut |word| {
+            let processed_word = normalize_token(tokenizer, normalizer, word);
+            if !processed_word.is_empty() {
+                *word_count.entry(processed_word).or_insert(0) += 1;
+                total_words += 1;
+            }
+        });
+    }
+
+    debug!(
+        "Thread {} finished processing {} words",
+        thread_id, total_words
+    );
+    progress::add_words(total_words as u64);
+    word_count
+}
+
+/// Creates the output file, and any missing parent directories.
+fn create_output(output_path: &Path) -> io::Result<BufWriter<File>> {
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(BufWriter::new(File::create(output_path)?))
+}
+
+fn write_results(
+    output_path: &Path,
+    word_count: &HashMap<String, usize>,
+    report: &Report,
+    footer: &Footer,
+) -> io::Result<()> {
+    write_results_to(create_output(output_path)?, word_count, report, footer)?;
+
+    info!("Results written to {}", output_path.display());
+    Ok(())
+}
+
+fn write_results_to<W: Write>(
+    mut writer: W,
+    word_count: &HashMap<String, usize>,
+    report: &Report,
+    footer: &Footer,
+) -> io::Result<()> {
+    let entries = report_entries(word_count, report)?;
+    let result_writer = report.format.writer();
+    result_writer.write_entries(&mut writer, &entries)?;
+    result_writer.write_footer(&mut writer, footer)?;
+    writer.flush()
+}
+
+/// Writes the counts of every input, each selected and sorted as `report` says.
+fn write_file_results_to<W: Write>(
+    mut writer: W,
+    file_counts: &[(PathBuf, HashMap<String, usize>)],
+    report: &Report,
+    footer: &Footer,
+) -> io::Result<()> {
+    let names: Vec<String> = file_counts
+        .iter()
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    let files = names
+        .iter()
+        .zip(file_counts)
+        .map(|(name, (_, word_count))| Ok((name.as_str(), report_entries(word_count, report)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let result_writer = report.format.writer();
+    result_writer.write_files(&mut writer, &files)?;
+    result_writer.write_footer(&mut writer, footer)?;
+    writer.flush()
+}
+
+/// The entries `report` asks for, in its order.
+fn report_entries<'a>(
+    word_count: &'a HashMap<String, usize>,
+    report: &Report,
+) -> io::Result<Vec<(&'a str, usize)>> {
+    match report.top {
+        Some(k) => topk::top_entries(word_count, k, report.order, report.threads),
+        None => Ok(topk::sorted_entries(word_count, report.order)),
+    }
+}
+
+fn count_file(input_path: &Path, config: &Config) -> io::Result<HashMap<String, usize>> {
+    if let Some(ngrams) = &config.ngrams {
+        if schedule::chunkable_size(input_path, config)?.is_none() {
+            return ngram::count_file_ngrams(input_path, config, ngrams);
+        }
+    }
+    if let Some(fields) = &config.fields {
+        return fields::count_file(input_path, config, fields);
+    }
+    if input_path == Path::new(STDIN_PATH) {
+        return stream::count_stream(decompress::decompressing_reader(io::stdin())?, config);
+    }
+    // Pipes, FIFOs and character devices cannot be split into chunks up front.
+    if !fs::metadata(input_path)?.is_file() {
+        let reader = decompress::decompressing_reader(File::open(input_path)?)?;
+        return stream::count_stream(reader, config);
+    }
+    match decompress::detect_file_compression(input_path)? {
+        decompress::Compression::None => {}
+        compression => return decompress::count_compressed_file(input_path, compression, config),
+    }
+    // UTF-16 is transcoded as it is read, so it cannot be split into chunks up front either.
+    if encoding::detect_file_utf16(input_path)? {
+        return stream::count_stream(File::open(input_path)?, config);
+    }
+
+    match &config.chunk_dir {
+        Some(chunk_dir) => count_exported_chunks(input_path, chunk_dir, config),
+        None => Ok(schedule::count_files(&[input_path.to_path_buf()], config)?.remove(0)),
+    }
+}
+
+/// Copies one chunk per thread of `input_path` into files in `chunk_dir` and counts those
+/// copies on the work queue, through the lines pipeline. The copies are removed afterwards.
+fn count_exported_chunks(
+    input_path: &Path,
+    chunk_dir: &Path,
+    config: &Config,
+) -> io::Result<HashMap<String, usize>> {
+    let chunks = divide_file_into_chunks(input_path, config.num_threads)?;
+    let chunk_files = export_chunk_files(input_path, &chunks, chunk_dir, config.buffer_size)?;
+    let config = Config {
+        chunk_dir: None,
+        pipeline: Pipeline::Lines,
+        ..config.clone()
+    };
+    let chunk_counts = schedule::count_files(chunk_files.paths(), &config)?;
+    drop(chunk_files);
+
+    debug!("All chunks counted, merging results");
+    merge::tree_reduce(chunk_counts)
+}
+
+fn process_file(
+    input_files: &[PathBuf],
+    output: &Output,
+    config: &Config,
+) -> Result<(), WordCountError> {
+    let start = std::time::Instant::now();
+
+    debug!("Starting file processing");
+
+    let config = &Config {
+        invalid_lines: InvalidLines::default(),
+        ..config.clone()
+    };
+    let input_files = walk::expand_inputs(input_files, &config.walk)?;
+    debug!("Counting {} input files", input_files.len());
+    progress::start(input_files.len(), input_size(&input_files));
+
+    if let Some(follow) = &config.follow {
+        // Runs until the process is interrupted or the output is closed.
+        follow::follow_files(&input_files, output, config, follow, || false)?;
+    } else if let Some(timeline_config) = &config.timeline {
+        timeline::process_files(&input_files, output, config, timeline_config)?;
+        invalid_utf8_summary(config, config.invalid_lines.get());
+    } else if let Some(sketch_config) = &config.approx {
+        approx::process_files(&input_files, output, config, sketch_config)?;
+        invalid_utf8_summary(config, config.invalid_lines.get());
+    } else {
+        count_inputs(&input_files, config)?.write(output)?;
+    }
+
+    let duration = start.elapsed();
+    info!("Total processing time: {} ms", duration.as_millis());
+
+    Ok(())
+}
+
+/// Counts the expanded inputs and applies the stop words and the stemmer to the totals, and
+/// to the counts of each input when they are kept apart.
+fn count_inputs(input_files: &[PathBuf], config: &Config) -> Result<CountResult, WordCountError> {
+    let config = &Config {
+        invalid_lines: InvalidLines::default(),
+        ..config.clone()
+    };
+    let file_counts = match &config.state {
+        Some(state_path) => incremental::count_files(input_files, config, state_path)?,
+        None => schedule::count_files(input_files, config)?,
+    };
+    let invalid_lines = config.invalid_lines.get();
+    invalid_utf8_summary(config, invalid_lines);
+    let ngrams = config.ngrams.is_some();
+
+    let files = config.report.per_file.then(|| {
+        input_files
+            .iter()
+            .cloned()
+            .zip(file_counts.iter().cloned())
+            .map(|(path, mut word_count)| {
+                config.stop_words.remove_from(&mut word_count, ngrams);
+                (path, config.stemmer.apply(word_count, ngrams))
+            })
+            .collect()
+    });
+
+    let mut total_word_count = merge::tree_reduce(file_counts)?;
+
+    // Every distinct word is checked once, however often it occurs; per file, the same
+    // occurrences are left out, so the total is what the footer reports either way.
+    let filtered = config.stop_words.remove_from(&mut total_word_count, ngrams);
+    let footer = stop_word_footer(config, filtered);
+
+    Ok(CountResult {
+        counts: config.stemmer.apply(total_word_count, ngrams),
+        files,
+        filtered: footer.filtered,
+        invalid_lines,
+        report: config.report,
+    })
+}
+
+/// The bytes the counters will read, when every input is a regular uncompressed file; the
+/// size of streams and decompressed data is only known once they have been read.
+fn input_size(input_files: &[PathBuf]) -> Option<u64> {
+    let mut total = 0;
+    for path in input_files {
+        let metadata = fs::metadata(path).ok()?;
+        let compression = decompress::detect_file_compression(path).ok()?;
+        let utf16 = encoding::detect_file_utf16(path).ok()?;
+        if !metadata.is_file() || compression != decompress::Compression::None || utf16 {
+            return None;
+        }
+        total += metadata.len();
+    }
+    Some(total)
+}
+
+/// Reports how many lines were not valid UTF-8, if any.
+fn invalid_utf8_summary(config: &Config, invalid_lines: u64) {
+    if invalid_lines > 0 {
+        info!(
+            "{} {} not valid UTF-8 and {}",
+            invalid_lines,
+            if invalid_lines == 1 {
+                "line was"
+            } else {
+                "lines were"
+            },
+            config.invalid_utf8.outcome()
+        );
+    }
+}
+
+/// The footer of a report, reporting `filtered` only when stop words are in use.
+fn stop_word_footer(config: &Config, filtered: usize) -> Footer {
+    let footer = Footer {
+        filtered: (!config.stop_words.is_empty()).then_some(filtered),
+    };
+    if let Some(filtered) = footer.filtered {
+        info!("Filtered {} stop word occurrences", filtered);
+    }
+    footer
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    //! Fixtures shared by the tests of several modules.
+
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+
+    /// A count map from word and count pairs.
+    pub(crate) fn counts(entries: &[(&str, usize)]) -> HashMap<String, usize> {
+        entries.iter().map(|&(w, c)| (w.to_string(), c)).collect()
+    }
+
+    /// Appends `text` to the file at `path`, creating it if needed.
+    pub(crate) fn append(path: &Path, text: &str) {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    mod test_divide_file_into_chunks {
+        use super::*;
+
+        #[test]
+        fn test_divide_file_into_equal_chunks() {
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_equal_chunks.txt");
+            let content = "Line 1\nLine 2\nLine 3\nLine 4\nLine 5\nLine 6\n";
+            fs::write(&file_path, content).unwrap();
+
+            let chunks = divide_file_into_chunks(&file_path, 3).unwrap();
+
+            assert!(
+                chunks.len() >= 2 && chunks.len() <= 3,
+                "Expected 2 or 3 chunks, got {}",
+                chunks.len()
+            );
+            assert_eq!(chunks[0].start, 0);
+            assert!(chunks[0].end >= 14); // At least "Line 1\nLine 2\n"
+            assert_eq!(chunks[1].start, chunks[0].end);
+            if chunks.len() == 3 {
+                assert!(chunks[1].end >= 28); // At least up to "Line 3\nLine 4\n"
+                assert_eq!(chunks[2].start, chunks[1].end);
+                assert_eq!(chunks[2].end, 42); // Total file size
+            } else {
+                assert_eq!(chunks[1].end, 42); // Total file size
+            }
+        }
+
+        #[test]
+        fn test_divide_empty_file() {
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("empty_file.txt");
+            fs::write(&file_path, "").unwrap();
+
+            let chunks = divide_file_into_chunks(&file_path, 3).unwrap();
+
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].start, 0);
+            assert_eq!(chunks[0].end, 0);
+        }
+
+        #[test]
+        fn test_divide_file_more_chunks_than_lines() {
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("more_chunks.txt");
+            let content = "Line 1\nLine 2\n";
+            fs::write(&file_path, content).unwrap();
+
+            let chunks = divide_file_into_chunks(&file_path, 5).unwrap();
+
+            assert!(
+                chunks.len() <= 5,
+                "Expected at most 5 chunks, got {}",
+                chunks.len()
+            );
+            assert!(
+                chunks.len() >= 2,
+                "Expected at least 2 chunks, got {}",
+                chunks.len()
+            );
+            assert_eq!(chunks[0].start, 0);
+            assert!(chunks[0].end > 0);
+            assert_eq!(chunks.last().unwrap().end, 14); // Total file size
+        }
+
+        #[test]
+        fn test_divide_file_with_very_long_line() {
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("long_line.txt");
+            let content =
+                "Short line\n".to_string() + &"A".repeat(10000) + "\nAnother short line\n";
+            let content_len = content.len() as u64;
+            fs::write(&file_path, content).unwrap();
+
+            let chunks = divide_file_into_chunks(&file_path, 3).unwrap();
+
+            assert!(
+                chunks.len() <= 3,
+                "Expected at most 3 chunks, got {}",
+                chunks.len()
+            );
+
+            // Check if any chunk contains the long line
+            let long_line_chunk = chunks.iter().find(|chunk| chunk.end - chunk.start >= 10000);
+            assert!(long_line_chunk.is_some(), "No chunk contains the long line");
+
+            // Ensure the last chunk ends at the file size
+            assert_eq!(chunks.last().unwrap().end, content_len);
+        }
+
+        #[test]
+        fn test_divide_file_into_unequal_chunks() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_unequal_chunks.txt");
+            let content = "Line 1\nLine 2\nLine 3\nLine 4\nLine 5\n";
+            fs::write(&file_path, content).unwrap();
+
+            // When
+            let chunks = divide_file_into_chunks(&file_path, 3).unwrap();
+
+            // Then
+            assert_eq!(chunks.len(), 3);
+            assert!(chunks[0].end > chunks[0].start);
+            assert!(chunks[1].end > chunks[1].start);
+            assert!(chunks[2].end > chunks[2].start);
+            assert_eq!(chunks[2].end, 35); // Total file size
+        }
+
+        #[test]
+        fn test_divide_file_with_one_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("one_chunk.txt");
+            let content = "Line 1\nLine 2\nLine 3\n";
+            fs::write(&file_path, content).unwrap();
+
+            // When
+            let chunks = divide_file_into_chunks(&file_path, 1).unwrap();
+
+            // Then
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].start, 0);
+            assert_eq!(chunks[0].end, 21); // Total file size
+        }
+
+        #[test]
+        #[should_panic(expected = "No such file or directory")]
+        fn test_divide_non_existent_file() {
+            // Given
+            let non_existent_file = Path::new("non_existent_file.txt");
+
+            // When
+            divide_file_into_chunks(non_existent_file, 3).unwrap();
+
+            // Then
+            // The function should panic with "No such file or directory" error
+        }
+    }
+
+    mod test_read_file_chunk {
+        use super::*;
+
+        #[test]
+        fn test_read_entire_file_as_single_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            let content = "Line 1\nLine 2\nLine 3\n";
+            fs::write(&file_path, content).unwrap();
+            let chunk = FileChunk {
+                start: 0,
+                end: content.len() as u64,
+            };
+
+            // When
+            let result = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(result, vec!["Line 1", "Line 2", "Line 3"]);
+        }
+
+        #[test]
+        fn test_read_partial_file_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            let content = "Line 1\nLine 2\nLine 3\nLine 4\n";
+            fs::write(&file_path, content).unwrap();
+            let chunk = FileChunk { start: 7, end: 20 }; // Should include "Line 2" and "Line 3"
+
+            // When
+            let result = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(result, vec!["Line 2"]);
+        }
+
+        #[test]
+        fn test_read_chunk_with_partial_lines() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            let content = "Line 1\nLine 2\nLine 3\nLine 4\n";
+            fs::write(&file_path, content).unwrap();
+            let chunk = FileChunk { start: 3, end: 17 }; // Should include partial "e 1", "Line 2", and partial "Lin"
+
+            // When
+            let result = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(result, vec!["e 1", "Line 2"]);
+        }
+
+        #[test]
+        fn test_read_empty_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            let content = "Line 1\nLine 2\n";
+            fs::write(&file_path, content).unwrap();
+            let chunk = FileChunk { start: 5, end: 5 };
+
+            // When
+            let result = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert!(result.is_empty());
+        }
+
+        #[test]
+        fn test_read_chunk_with_empty_lines() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            let content = "Line 1\n\nLine 3\n";
+            fs::write(&file_path, content).unwrap();
+            let chunk = FileChunk {
+                start: 0,
+                end: content.len() as u64,
+            };
+
+            // When
+            let result = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(result, vec!["Line 1", "Line 3"]);
+        }
+
+        #[test]
+        fn test_read_chunk_beyond_file_end() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            let content = "Line 1\nLine 2\n";
+            fs::write(&file_path, content).unwrap();
+            let chunk = FileChunk {
+                start: 0,
+                end: (content.len() + 10) as u64,
+            };
+
+            // When
+            let result = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(result, vec!["Line 1", "Line 2"]);
+        }
+
+        #[test]
+        fn test_read_chunk_reports_offset_of_invalid_utf8() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let file_path = temp_dir.path().join("test_file.txt");
+            fs::write(&file_path, b"Line 1\nLine \xff2\n").unwrap();
+            let chunk = FileChunk { start: 0, end: 15 };
+
+            // When
+            let err = read_file_chunk(
+                &file_path,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(matches!(
+                WordCountError::from(err),
+                WordCountError::InvalidUtf8 { offset: 12, .. }
+            ));
+        }
+
+        #[test]
+        #[should_panic(expected = "No such file or directory")]
+        fn test_read_chunk_from_non_existent_file() {
+            // Given
+            let non_existent_file = Path::new("non_existent_file.txt");
+            let chunk = FileChunk { start: 0, end: 10 };
+
+            // When
+            read_file_chunk(
+                non_existent_file,
+                &chunk,
+                BUFFER_SIZE,
+                Utf8Policy::Fail,
+                &InvalidLines::default(),
+            )
+            .unwrap();
+
+            // Then
+            // The function should panic with "No such file or directory" error
+        }
+    }
+
+    mod test_process_word {
+        use super::*;
+
+        /// What the default tokenizer turns a whitespace-separated token into.
+        fn process_word(word: &str) -> String {
+            normalize_token(&WhitespaceTokenizer, &Normalizer::default(), word)
+        }
+
+        #[test]
+        fn test_process_word_removes_punctuation() {
+            // Given
+            let input = "hello,world!";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "helloworld");
+        }
+
+        #[test]
+        fn test_process_word_converts_to_lowercase() {
+            // Given
+            let input = "HeLLo";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "hello");
+        }
+
+        #[test]
+        fn test_process_word_handles_empty_string() {
+            // Given
+            let input = "";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "");
+        }
+
+        #[test]
+        fn test_process_word_handles_only_punctuation() {
+            // Given
+            let input = "!@#$%^&*()";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "");
+        }
+
+        #[test]
+        fn test_process_word_preserves_numbers() {
+            // Given
+            let input = "hello123world";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "hello123world");
+        }
+
+        #[test]
+        fn test_process_word_handles_unicode() {
+            // Given
+            let input = "Hé!!ô, Wörld!";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "héô wörld");
+        }
+
+        #[test]
+        fn test_process_word_handles_mixed_case_and_punctuation() {
+            // Given
+            let input = "MiXeD.CaSe!WoRd";
+
+            // When
+            let result = process_word(input);
+
+            // Then
+            assert_eq!(result, "mixedcaseword");
+        }
+    }
+    mod test_count_words {
+        use super::*;
+
+        #[test]
+        fn test_count_words_with_simple_sentence() {
+            // Given
+            let lines = vec!["The quick brown fox".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 4);
+            assert_eq!(result.get("the"), Some(&1));
+            assert_eq!(result.get("quick"), Some(&1));
+            assert_eq!(result.get("brown"), Some(&1));
+            assert_eq!(result.get("fox"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_repeated_words() {
+            // Given
+            let lines = vec!["The quick quick fox".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 3);
+            assert_eq!(result.get("the"), Some(&1));
+            assert_eq!(result.get("quick"), Some(&2));
+            assert_eq!(result.get("fox"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_punctuation() {
+            // Given
+            let lines = vec!["Hello, world! How are you?".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 5);
+            assert_eq!(result.get("hello"), Some(&1));
+            assert_eq!(result.get("world"), Some(&1));
+            assert_eq!(result.get("how"), Some(&1));
+            assert_eq!(result.get("are"), Some(&1));
+            assert_eq!(result.get("you"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_mixed_case() {
+            // Given
+            let lines = vec!["The Quick BROWN fox".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 4);
+            assert_eq!(result.get("the"), Some(&1));
+            assert_eq!(result.get("quick"), Some(&1));
+            assert_eq!(result.get("brown"), Some(&1));
+            assert_eq!(result.get("fox"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_empty_lines() {
+            // Given
+            let lines = vec!["".to_string(), "Hello world".to_string(), "".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 2);
+            assert_eq!(result.get("hello"), Some(&1));
+            assert_eq!(result.get("world"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_multiple_lines() {
+            // Given
+            let lines = vec![
+                "The quick brown".to_string(),
+                "fox jumps over".to_string(),
+                "the lazy dog".to_string(),
+            ];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 8);
+            assert_eq!(result.get("the"), Some(&2));
+            assert_eq!(result.get("quick"), Some(&1));
+            assert_eq!(result.get("brown"), Some(&1));
+            assert_eq!(result.get("fox"), Some(&1));
+            assert_eq!(result.get("jumps"), Some(&1));
+            assert_eq!(result.get("over"), Some(&1));
+            assert_eq!(result.get("lazy"), Some(&1));
+            assert_eq!(result.get("dog"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_numbers() {
+            // Given
+            let lines = vec!["There are 3 apples and 2 oranges".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 7);
+            assert_eq!(result.get("there"), Some(&1));
+            assert_eq!(result.get("are"), Some(&1));
+            assert_eq!(result.get("3"), Some(&1));
+            assert_eq!(result.get("apples"), Some(&1));
+            assert_eq!(result.get("and"), Some(&1));
+            assert_eq!(result.get("2"), Some(&1));
+            assert_eq!(result.get("oranges"), Some(&1));
+        }
+
+        #[test]
+        fn test_count_words_with_special_characters() {
+            // Given
+            let lines = vec!["Hello@world! How_are you?".to_string()];
+
+            // When
+            let result = count_words(&lines, &WhitespaceTokenizer, &Normalizer::default(), 0);
+
+            // Then
+            assert_eq!(result.len(), 3);
+            assert_eq!(result.get("helloworld"), Some(&1));
+            assert_eq!(result.get("howare"), Some(&1));
+            assert_eq!(result.get("you"), Some(&1));
+        }
+    }
+
+    mod test_write_results {
+        use super::*;
+        use std::collections::HashMap;
+        use std::fs;
+        use tempfile::TempDir;
+
+        #[test]
+        fn test_write_results_with_empty_hashmap() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("empty_output.txt");
+            let word_count = HashMap::new();
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert!(content.is_empty(), "File should be empty");
+        }
+
+        #[test]
+        fn test_write_results_in_csv_keeps_sort_order() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("output.csv");
+            let word_count = HashMap::from([
+                ("b:c".to_string(), 1),
+                ("10".to_string(), 2),
+                ("9".to_string(), 3),
+                ("a,b".to_string(), 4),
+            ]);
+
+            // When
+            let report = Report {
+                format: Format::Csv,
+                ..Report::default()
+            };
+            write_results(&output_path, &word_count, &report, &Footer::default()).unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(content, "word,count\n9,3\n10,2\n\"a,b\",4\nb:c,1\n");
+        }
+
+        #[test]
+        fn test_write_results_with_single_word() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("single_word_output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("hello".to_string(), 1);
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(content, "hello: 1\n", "File should contain 'hello: 1'");
+        }
+
+        #[test]
+        fn test_write_results_with_multiple_words() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("multiple_words_output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("hello".to_string(), 2);
+            word_count.insert("world".to_string(), 1);
+            word_count.insert("rust".to_string(), 3);
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            let expected = "hello: 2\nrust: 3\nworld: 1\n";
+            assert_eq!(
+                content, expected,
+                "File content should match expected output"
+            );
+        }
+
+        #[test]
+        fn test_write_results_sorts_words_alphabetically() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("sorted_output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("zebra".to_string(), 1);
+            word_count.insert("apple".to_string(), 1);
+            word_count.insert("banana".to_string(), 1);
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            let expected = "apple: 1\nbanana: 1\nzebra: 1\n";
+            assert_eq!(content, expected, "Words should be sorted alphabetically");
+        }
+
+        #[test]
+        fn test_write_results_handles_numbers() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("numbers_output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("10".to_string(), 1);
+            word_count.insert("2".to_string(), 1);
+            word_count.insert("1".to_string(), 1);
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            let expected = "1: 1\n2: 1\n10: 1\n";
+            assert_eq!(content, expected, "Numbers should be sorted numerically");
+        }
+
+        #[test]
+        fn test_write_results_handles_mixed_content() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("mixed_content_output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("apple".to_string(), 1);
+            word_count.insert("10".to_string(), 1);
+            word_count.insert("banana".to_string(), 1);
+            word_count.insert("2".to_string(), 1);
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            let expected = "2: 1\n10: 1\napple: 1\nbanana: 1\n";
+            assert_eq!(
+                content, expected,
+                "Numbers should be sorted first, then words"
+            );
+        }
+
+        #[test]
+        fn test_write_results_creates_parent_directories() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("nested/dir/output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("test".to_string(), 1);
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            assert!(output_path.exists(), "Output file should be created");
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(content, "test: 1\n", "File should contain 'test: 1'");
+        }
+
+        #[test]
+        fn test_write_results_fails_on_permission_error() {
+            use std::io::ErrorKind;
+
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("readonly_output.txt");
+            fs::write(&output_path, "").unwrap();
+
+            // Make the file read-only
+            let mut perms = fs::metadata(&output_path).unwrap().permissions();
+            perms.set_readonly(true);
+            fs::set_permissions(&output_path, perms).unwrap();
+
+            let word_count = HashMap::new();
+
+            // When
+            let result = write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            );
+
+            // Then
+            assert!(result.is_err());
+            if let Err(e) = result {
+                assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+            }
+        }
+
+        #[test]
+        fn test_write_results_handles_invalid_numbers() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let output_path = temp_dir.path().join("invalid_numbers_output.txt");
+            let mut word_count = HashMap::new();
+            word_count.insert("123".to_string(), 1);
+            word_count.insert("456".to_string(), 1);
+            word_count.insert("78a".to_string(), 1); // 无效数字
+
+            // When
+            write_results(
+                &output_path,
+                &word_count,
+                &Report::default(),
+                &Footer::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            let expected = "123: 1\n456: 1\n78a: 1\n";
+            assert_eq!(content, expected, "Should handle invalid number gracefully");
+        }
+    }
+
+    mod test_create_chunk_file {
+        use super::*;
+
+        #[test]
+        fn test_create_chunk_file_with_non_empty_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk = FileChunk { start: 0, end: 5 };
+
+            // When
+            let chunk_path =
+                create_chunk_file(&input_path, &chunk, 0, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_path, temp_dir.path().join("input_chunk_0.txt"));
+            assert!(chunk_path.exists());
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, "Hello");
+        }
+
+        #[test]
+        fn test_create_chunk_file_with_empty_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk = FileChunk { start: 5, end: 5 };
+
+            // When
+            let chunk_path =
+                create_chunk_file(&input_path, &chunk, 1, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_path, temp_dir.path().join("input_chunk_1.txt"));
+            assert!(chunk_path.exists());
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, "");
+        }
+
+        #[test]
+        fn test_create_chunk_file_with_chunk_larger_than_buffer() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            let large_content = "A".repeat(BUFFER_SIZE * 2);
+            fs::write(&input_path, &large_content).unwrap();
+            let chunk = FileChunk {
+                start: 0,
+                end: (BUFFER_SIZE * 2) as u64,
+            };
+
+            // When
+            let chunk_path =
+                create_chunk_file(&input_path, &chunk, 2, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_path, temp_dir.path().join("input_chunk_2.txt"));
+            assert!(chunk_path.exists());
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, large_content);
+        }
+
+        #[test]
+        fn test_create_chunk_file_with_chunk_at_end_of_file() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk = FileChunk { start: 7, end: 13 };
+
+            // When
+            let chunk_path =
+                create_chunk_file(&input_path, &chunk, 3, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_path, temp_dir.path().join("input_chunk_3.txt"));
+            assert!(chunk_path.exists());
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, "World!");
+        }
+
+        #[test]
+        fn test_create_chunk_file_with_non_existent_input_file() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("non_existent.txt");
+            let chunk = FileChunk { start: 0, end: 5 };
+
+            // When
+            let result = create_chunk_file(&input_path, &chunk, 4, temp_dir.path(), BUFFER_SIZE);
+
+            // Then
+            assert!(result.is_err());
+            assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        }
+
+        #[test]
+        fn test_create_chunk_file_with_invalid_chunk_range() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk = FileChunk { start: 10, end: 5 };
+
+            // When
+            let chunk_path =
+                create_chunk_file(&input_path, &chunk, 5, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_path, temp_dir.path().join("input_chunk_5.txt"));
+            assert!(chunk_path.exists());
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, "");
+        }
+
+        #[test]
+        fn test_create_chunk_file_overwrites_existing_file() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk_path = temp_dir.path().join("input_chunk_6.txt");
+            fs::write(&chunk_path, "Old content").unwrap();
+            let chunk = FileChunk { start: 0, end: 5 };
+
+            // When
+            create_chunk_file(&input_path, &chunk, 6, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert!(chunk_path.exists());
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, "Hello");
+        }
+
+        #[test]
+        fn test_create_chunk_file_with_small_buffer() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk = FileChunk { start: 0, end: 13 };
+
+            // When
+            let chunk_path = create_chunk_file(&input_path, &chunk, 7, temp_dir.path(), 3).unwrap();
+
+            // Then
+            let content = fs::read_to_string(&chunk_path).unwrap();
+            assert_eq!(content, "Hello, World!");
+        }
+
+        #[test]
+        fn test_create_chunk_file_names_file_after_input() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("corpus.log");
+            fs::write(&input_path, "Hello, World!").unwrap();
+            let chunk = FileChunk { start: 0, end: 5 };
+
+            // When
+            let chunk_path =
+                create_chunk_file(&input_path, &chunk, 0, temp_dir.path(), BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_path, temp_dir.path().join("corpus_chunk_0.txt"));
+        }
+    }
+
+    mod test_export_chunk_files {
+        use super::*;
+
+        #[test]
+        fn test_export_chunk_files_writes_every_chunk() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Line 1\nLine 2\nLine 3\n").unwrap();
+            let chunks = vec![
+                FileChunk { start: 0, end: 7 },
+                FileChunk { start: 7, end: 21 },
+            ];
+            let chunk_dir = temp_dir.path().join("chunks");
+
+            // When
+            let chunk_files =
+                export_chunk_files(&input_path, &chunks, &chunk_dir, BUFFER_SIZE).unwrap();
+
+            // Then
+            assert_eq!(chunk_files.paths().len(), 2);
+            assert_eq!(
+                fs::read_to_string(&chunk_files.paths()[0]).unwrap(),
+                "Line 1\n"
+            );
+            assert_eq!(
+                fs::read_to_string(&chunk_files.paths()[1]).unwrap(),
+                "Line 2\nLine 3\n"
+            );
+        }
+
+        #[test]
+        fn test_export_chunk_files_cleans_up_on_drop() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Line 1\nLine 2\n").unwrap();
+            let chunks = vec![
+                FileChunk { start: 0, end: 7 },
+                FileChunk { start: 7, end: 14 },
+            ];
+            let chunk_dir = temp_dir.path().join("chunks");
+            let chunk_files =
+                export_chunk_files(&input_path, &chunks, &chunk_dir, BUFFER_SIZE).unwrap();
+            let paths = chunk_files.paths().to_vec();
+
+            // When
+            drop(chunk_files);
+
+            // Then
+            assert!(paths.iter().all(|path| !path.exists()));
+            assert!(!chunk_dir.exists(), "Created directory should be removed");
+        }
+
+        #[test]
+        fn test_export_chunk_files_keeps_existing_directory() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Line 1\n").unwrap();
+            let chunks = vec![FileChunk { start: 0, end: 7 }];
+
+            // When
+            drop(export_chunk_files(&input_path, &chunks, temp_dir.path(), BUFFER_SIZE).unwrap());
+
+            // Then
+            assert!(temp_dir.path().exists());
+            assert!(input_path.exists());
+            assert!(!temp_dir.path().join("input_chunk_0.txt").exists());
+        }
+    }
+
+    mod test_process_file {
+        use super::*;
+
+        #[test]
+        fn test_process_file_with_read_error() {
+            use std::fs::File;
+            use std::io::Write;
+
+            // 创建一个临时目录
+            let temp_dir = TempDir::new().unwrap();
+
+            // 创建输入文件
+            let input_path = temp_dir.path().join("input.txt");
+            let mut file = File::create(&input_path).unwrap();
+            writeln!(file, "Line 1\nLine 2\nLine 3").unwrap();
+
+            // 创建输出文件路径
+            let output_path = temp_dir.path().join("output.txt");
+
+            // 使用一个不存在的文件路径来模拟读取错误
+            let non_existent_input = temp_dir.path().join("non_existent.txt");
+
+            // 处理文件
+            let result = process_file(
+                &[non_existent_input],
+                &Output::File(output_path.clone()),
+                &Config::default(),
+            );
+
+            // 验证结果
+            assert!(
+                result.is_err(),
+                "Process should fail due to non-existent input file"
+            );
+
+            // 检查输出文件是否不存在（因为处理应该失败）
+            assert!(
+                !output_path.exists(),
+                "Output file should not be created when input file doesn't exist"
+            );
+        }
+
+        #[test]
+        fn test_process_file_reports_typed_errors_with_path() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let missing = temp_dir.path().join("missing.txt");
+            let invalid = temp_dir.path().join("invalid.txt");
+            fs::write(&invalid, b"valid line\ninvalid \xc3\x28 line\n").unwrap();
+            let output = Output::File(temp_dir.path().join("output.txt"));
+
+            for pipeline in [Pipeline::Mmap, Pipeline::Lines] {
+                let config = Config {
+                    pipeline,
+                    ..Config::default()
+                };
+
+                // When
+                let missing_err =
+                    process_file(std::slice::from_ref(&missing), &output, &config).unwrap_err();
+                let invalid_err =
+                    process_file(std::slice::from_ref(&invalid), &output, &config).unwrap_err();
+
+                // Then
+                match &missing_err {
+                    WordCountError::Io { path, source } => {
+                        assert_eq!(path.as_deref(), Some(missing.as_path()));
+                        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+                    }
+                    other => panic!("expected an I/O error, got {:?}", other),
+                }
+                assert_eq!(missing_err.exit_code(), 1);
+                match &invalid_err {
+                    WordCountError::InvalidUtf8 { path, offset } => {
+                        assert_eq!(path.as_deref(), Some(invalid.as_path()));
+                        assert_eq!(*offset, 19);
+                    }
+                    other => panic!("expected invalid UTF-8, got {:?}", other),
+                }
+                assert_eq!(invalid_err.exit_code(), 3);
+            }
+        }
+
+        #[test]
+        fn test_process_file_reports_unwritable_output_path() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "some words\n").unwrap();
+            // A file where the output's parent directory should be.
+            let blocker = temp_dir.path().join("blocker");
+            fs::write(&blocker, "").unwrap();
+            let output_path = blocker.join("output.txt");
+
+            // When
+            let err = process_file(
+                &[input_path],
+                &Output::File(output_path.clone()),
+                &Config::default(),
+            )
+            .unwrap_err();
+
+            // Then
+            assert!(
+                matches!(&err, WordCountError::Io { path: Some(path), .. } if *path == output_path),
+                "{:?}",
+                err
+            );
+        }
+
+        #[test]
+        fn test_process_file_merges_all_inputs() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let first = temp_dir.path().join("first.txt");
+            let second = temp_dir.path().join("second.txt");
+            fs::write(&first, "apple banana\n").unwrap();
+            fs::write(&second, "banana cherry\n").unwrap();
+            let output_path = temp_dir.path().join("output.txt");
+
+            // When
+            process_file(
+                &[first, second],
+                &Output::File(output_path.clone()),
+                &Config::default(),
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(content, "apple: 1\nbanana: 2\ncherry: 1\n");
+        }
+
+        #[test]
+        fn test_process_file_filters_stop_words_with_footer() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(
+                &input_path,
+                "The cat and the hat
+of the cat
+",
+            )
+            .unwrap();
+            let output_path = temp_dir.path().join("output.txt");
+            let mut stop_words = StopWords::default();
+            stop_words.add_list(
+                stopwords::StopList::English,
+                &WhitespaceTokenizer,
+                &Normalizer::default(),
+            );
+            let config = Config {
+                stop_words: Arc::new(stop_words),
+                ..Config::default()
+            };
+
+            // When
+            process_file(&[input_path], &Output::File(output_path.clone()), &config).unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(content, "cat: 2\nhat: 1\n\n# filtered stop words: 5\n");
+        }
+
+        #[test]
+        fn test_process_file_walks_directories_per_file() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let docs = temp_dir.path().join("docs");
+            fs::create_dir_all(docs.join("sub")).unwrap();
+            fs::write(docs.join("a.txt"), "cat hat\n").unwrap();
+            fs::write(docs.join("sub/b.txt"), "cat\n").unwrap();
+            fs::write(docs.join("skip.md"), "dog\n").unwrap();
+            let output_path = temp_dir.path().join("output.txt");
+            let config = Config {
+                walk: WalkConfig {
+                    include: vec!["*.txt".to_string()],
+                    ..WalkConfig::default()
+                },
+                report: Report {
+                    per_file: true,
+                    ..Report::default()
+                },
+                ..Config::default()
+            };
+
+            // When
+            process_file(
+                std::slice::from_ref(&docs),
+                &Output::File(output_path.clone()),
+                &config,
+            )
+            .unwrap();
+
+            // Then
+            let content = fs::read_to_string(&output_path).unwrap();
+            assert_eq!(
+                content,
+                format!(
+                    "==> {} <==\ncat: 1\nhat: 1\n\n==> {} <==\ncat: 1\n",
+                    docs.join("a.txt").display(),
+                    docs.join("sub/b.txt").display()
+                )
+            );
+        }
+    }
+
+    mod test_count_file {
+        use super::*;
+
+        #[test]
+        fn test_count_file_pipelines_agree() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "One two, three.\nTWO three\n\nthree!\n").unwrap();
+            let lines_config = Config {
+                pipeline: Pipeline::Lines,
+                ..Config::default()
+            };
+
+            // When
+            let mapped = count_file(&input_path, &Config::default()).unwrap();
+            let lines = count_file(&input_path, &lines_config).unwrap();
+
+            // Then
+            assert_eq!(mapped, lines);
+            assert_eq!(mapped.get("three"), Some(&3));
+        }
+
+        /// Writes `content` to `name` and a gzipped copy next to it; returns both paths.
+        fn write_plain_and_gzipped(temp_dir: &TempDir, name: &str, content: &[u8]) -> [PathBuf; 2] {
+            use flate2::write::GzEncoder;
+
+            let plain = temp_dir.path().join(name);
+            fs::write(&plain, content).unwrap();
+            let gzipped = temp_dir.path().join(format!("{}.gz", name));
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(content).unwrap();
+            fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+            [plain, gzipped]
+        }
+
+        #[test]
+        fn test_count_file_applies_invalid_utf8_policy_in_every_path() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let inputs = write_plain_and_gzipped(
+                &temp_dir,
+                "input.txt",
+                b"ok caf\xe9\nplain words\n\x93quoted\x94 ok\n",
+            );
+
+            for (policy, expected) in [
+                (Utf8Policy::Skip, vec![("plain", 1), ("words", 1)]),
+                (
+                    Utf8Policy::Replace,
+                    vec![("caf\u{fffd}", 1), ("ok", 2), ("\u{fffd}quoted\u{fffd}", 1)],
+                ),
+                (
+                    Utf8Policy::Latin1,
+                    vec![("café", 1), ("ok", 2), ("\u{201c}quoted\u{201d}", 1)],
+                ),
+            ] {
+                for pipeline in [Pipeline::Mmap, Pipeline::Lines] {
+                    for input in &inputs {
+                        let config = Config {
+                            pipeline,
+                            invalid_utf8: policy,
+                            ..Config::default()
+                        };
+
+                        // When
+                        let result = count_file(input, &config).unwrap();
+
+                        // Then
+                        for &(word, count) in &expected {
+                            assert_eq!(
+                                result.get(word),
+                                Some(&count),
+                                "{:?} {:?} {}",
+                                policy,
+                                pipeline,
+                                input.display()
+                            );
+                        }
+                        assert_eq!(result.get("plain"), Some(&1));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_count_file_transcodes_utf16_inputs() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let text = "Grüße aus 𝄞 Köln\ngrüße\n";
+            let utf8_path = temp_dir.path().join("utf8.txt");
+            fs::write(&utf8_path, text).unwrap();
+            let expected = count_file(&utf8_path, &Config::default()).unwrap();
+
+            let mut little_endian = vec![0xff, 0xfe];
+            let mut big_endian = vec![0xfe, 0xff];
+            for unit in text.encode_utf16() {
+                little_endian.extend_from_slice(&unit.to_le_bytes());
+                big_endian.extend_from_slice(&unit.to_be_bytes());
+            }
+            let inputs = [
+                write_plain_and_gzipped(&temp_dir, "le.txt", &little_endian),
+                write_plain_and_gzipped(&temp_dir, "be.txt", &big_endian),
+            ];
+
+            for input in inputs.iter().flatten() {
+                // When
+                let result = count_file(input, &Config::default()).unwrap();
+
+                // Then
+                assert_eq!(result, expected, "{}", input.display());
+                assert_eq!(result.get("grüße"), Some(&2));
+            }
+        }
+
+        #[test]
+        fn test_count_file_pipelines_agree_for_every_tokenizer() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            let content: String = (0..200)
+                .map(|i| format!("  Don't parse_{}HTTPBody e-mail,\t«ça» 3.14\n", i % 9))
+                .collect();
+            fs::write(&input_path, content).unwrap();
+            let tokenizers = [
+                tokenizer::TokenizerKind::Whitespace.build(None),
+                tokenizer::TokenizerKind::Words.build(None),
+                tokenizer::TokenizerKind::Regex.build(Some(r"^\w+|\d+")),
+                tokenizer::TokenizerKind::Identifier.build(None),
+            ];
+
+            for tokenizer in tokenizers.map(Result::unwrap) {
+                let expected = count_file(
+                    &input_path,
+                    &Config {
+                        pipeline: Pipeline::Lines,
+                        tokenizer: Arc::clone(&tokenizer),
+                        ..Config::default()
+                    },
+                )
+                .unwrap();
+                let config = Config {
+                    num_threads: 3,
+                    block_size: 64,
+                    tokenizer: Arc::clone(&tokenizer),
+                    ..Config::default()
+                };
+
+                // When
+                let mapped = count_file(&input_path, &config).unwrap();
+                let streamed =
+                    stream::count_stream(File::open(&input_path).unwrap(), &config).unwrap();
+
+                // Then
+                assert_eq!(mapped, expected, "{:?}", tokenizer);
+                assert_eq!(streamed, expected, "{:?}", tokenizer);
+            }
+        }
+
+        #[test]
+        fn test_count_file_applies_normalizer_in_every_pipeline() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "Straße STRASSE\ncafe\u{301} CAF\u{c9}\n").unwrap();
+            let normalizer = Normalizer {
+                form: normalize::Form::Nfc,
+                case: normalize::CaseMode::Fold,
+                strip_diacritics: false,
+            };
+
+            for pipeline in [Pipeline::Mmap, Pipeline::Lines] {
+                let config = Config {
+                    pipeline,
+                    normalizer,
+                    ..Config::default()
+                };
+
+                // When
+                let result = count_file(&input_path, &config).unwrap();
+
+                // Then
+                assert_eq!(
+                    result,
+                    HashMap::from([("strasse".to_string(), 2), ("caf\u{e9}".to_string(), 2)]),
+                    "{:?}",
+                    pipeline
+                );
+            }
+        }
+
+        #[test]
+        fn test_count_file_does_not_leave_chunk_files_behind() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "one two\nthree four\nfive six\n").unwrap();
+
+            // When
+            let result = count_file(&input_path, &Config::default()).unwrap();
+
+            // Then
+            assert_eq!(result.len(), 6);
+            let entries: Vec<_> = fs::read_dir(temp_dir.path()).unwrap().collect();
+            assert_eq!(entries.len(), 1, "Only the input file should exist");
+        }
+
+        #[test]
+        fn test_count_file_with_exported_chunks() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "one two\ntwo three\nthree three\n").unwrap();
+            let chunk_dir = temp_dir.path().join("chunks");
+            let config = Config {
+                num_threads: 3,
+                chunk_dir: Some(chunk_dir.clone()),
+                ..Config::default()
+            };
+
+            // When
+            let result = count_file(&input_path, &config).unwrap();
+
+            // Then
+            assert_eq!(result.get("one"), Some(&1));
+            assert_eq!(result.get("two"), Some(&2));
+            assert_eq!(result.get("three"), Some(&3));
+            assert!(!chunk_dir.exists(), "Chunk files should be cleaned up");
+        }
+    }
+}
+// input.txt (Run command 'python3 generate_input.py' to generate the large input file.)
+// Short line
+// This is a much longer line that should be in its own chunk
+// Another short line
+//
+// Output:
+// Starting word count process
+// Starting file processing
+// File size: 89 bytes
+// Chunk 0: 0 - 70
+// Created chunk file: input_chunk_0.txt
+// Chunk 1: 70 - 89
+// Created chunk file: input_chunk_1.txt
+// Thread 0 started
+// Thread 1 started
+// Read 19 bytes from chunk
+// Thread 1 read 1 lines
+// Read 70 bytes from chunk
+// Thread 1 finished processing 3 words
+// Thread 0 read 2 lines
+// Thread 0 finished processing 15 words
+// All threads finished, merging results
+// Results written to ./output.txt
+// Total processing time: 2 ms
+// Processing completed successfully.
+//
+// output.txt
+// a: 1
+// another: 1
+// be: 1
+// chunk: 1
+// in: 1
+// is: 1
+// its: 1
+// line: 3
+// longer: 1
+// much: 1
+// own: 1
+// short: 2
+// should: 1
+// that: 1
+// this: 1
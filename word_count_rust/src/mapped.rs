@@ -0,0 +1,319 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::encoding::{self, InvalidLines, Utf8Policy};
+use crate::merge::merge_counts;
+use crate::normalize::{normalize_token, Normalizer};
+use crate::progress;
+use crate::tokenizer::{tokenize_lines, Tokenizer};
+use crate::{Config, FileChunk};
+
+/// Counts the words of the chunks of `path` that `next_chunk` hands out, tokenizing each in
+/// place through one memory mapping of the file.
+///
+/// Tokens are counted under `&str` keys that borrow from the mapping and only normalized once
+/// per distinct token, at the end. A chunk whose invalid UTF-8 had to be repaired is owned, so
+/// its tokens are normalized right away.
+pub(crate) fn count_chunks(
+    path: &Path,
+    mut next_chunk: impl FnMut() -> io::Result<Option<FileChunk>>,
+    config: &Config,
+) -> io::Result<HashMap<String, usize>> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read-only and lives until the end of this function. Like any
+    // mmap-based tool we rely on the input not being truncated while it is being counted.
+    let mmap = unsafe { Mmap::map(&file)? };
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let mut raw_count = HashMap::new();
+    let mut repaired = HashMap::new();
+    while let Some(chunk) = next_chunk()? {
+        let text = chunk_as_str(&mmap, &chunk, config.invalid_utf8, &config.invalid_lines)?;
+        let words = match text {
+            Cow::Borrowed(text) => tally_raw_words(text, tokenizer, &mut raw_count),
+            Cow::Owned(text) => {
+                let mut owned_count = HashMap::new();
+                let words = tally_raw_words(&text, tokenizer, &mut owned_count);
+                merge_counts(
+                    &mut repaired,
+                    normalize_counts(owned_count, tokenizer, normalizer),
+                );
+                words
+            }
+        };
+        progress::add_bytes(chunk.end - chunk.start);
+        progress::add_words(words as u64);
+    }
+    let mut word_count = normalize_counts(raw_count, tokenizer, normalizer);
+    merge_counts(&mut word_count, repaired);
+    Ok(word_count)
+}
+
+/// Borrows the bytes of `chunk` from the mapping as UTF-8 text; only lines that are not valid
+/// UTF-8 make `invalid_utf8` copy the chunk.
+pub(crate) fn chunk_as_str<'a>(
+    mmap: &'a [u8],
+    chunk: &FileChunk,
+    invalid_utf8: Utf8Policy,
+    invalid_lines: &InvalidLines,
+) -> io::Result<Cow<'a, str>> {
+    let end = std::cmp::min(chunk.end, mmap.len() as u64) as usize;
+    let start = std::cmp::min(chunk.start as usize, end);
+    encoding::decode_at(&mmap[start..end], start as u64, invalid_utf8, invalid_lines)
+}
+
+/// Adds the tokens of `text` to `raw_count` and returns how many there were.
+pub(crate) fn tally_raw_words<'a>(
+    text: &'a str,
+    tokenizer: &dyn Tokenizer,
+    raw_count: &mut HashMap<&'a str, usize>,
+) -> usize {
+    let mut total_words = 0;
+    tokenize_lines(tokenizer, text, &mut |word| {
+        *raw_count.entry(word).or_insert(0) += 1;
+        total_words += 1;
+    });
+    total_words
+}
+
+/// Normalizes each distinct raw token once and folds tokens that normalize to the same word.
+pub(crate) fn normalize_counts(
+    raw_count: HashMap<&str, usize>,
+    tokenizer: &dyn Tokenizer,
+    normalizer: &Normalizer,
+) -> HashMap<String, usize> {
+    let mut word_count = HashMap::with_capacity(raw_count.len());
+    for (raw_word, count) in raw_count {
+        let processed_word = normalize_token(tokenizer, normalizer, raw_word);
+        if !processed_word.is_empty() {
+            *word_count.entry(processed_word).or_insert(0) += count;
+        }
+    }
+    word_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::WhitespaceTokenizer;
+    use std::fs;
+    use tempfile::TempDir;
+
+    mod test_tally_raw_words {
+        use super::*;
+
+        fn tally(text: &str) -> HashMap<&str, usize> {
+            let mut raw_count = HashMap::new();
+            tally_raw_words(text, &WhitespaceTokenizer, &mut raw_count);
+            raw_count
+        }
+
+        #[test]
+        fn test_tally_raw_words_keeps_tokens_verbatim() {
+            // Given
+            let text = "the the, THE\nthe";
+
+            // When
+            let result = tally(text);
+
+            // Then
+            assert_eq!(result.len(), 3);
+            assert_eq!(result.get("the"), Some(&2));
+            assert_eq!(result.get("the,"), Some(&1));
+            assert_eq!(result.get("THE"), Some(&1));
+        }
+
+        #[test]
+        fn test_tally_raw_words_borrows_from_input() {
+            // Given
+            let text = String::from("alpha beta");
+
+            // When
+            let result = tally(&text);
+
+            // Then
+            let range = text.as_bytes().as_ptr_range();
+            assert!(result
+                .keys()
+                .all(|word| range.contains(&word.as_bytes().as_ptr())));
+        }
+
+        #[test]
+        fn test_tally_raw_words_with_empty_text() {
+            // When
+            let mut raw_count = HashMap::new();
+            let words = tally_raw_words("  \n\n ", &WhitespaceTokenizer, &mut raw_count);
+
+            // Then
+            assert_eq!(words, 0);
+            assert!(raw_count.is_empty());
+        }
+    }
+
+    mod test_normalize_counts {
+        use super::*;
+
+        #[test]
+        fn test_normalize_counts_folds_variants() {
+            // Given
+            let raw_count = HashMap::from([("The", 2), ("the,", 1), ("fox!", 4)]);
+
+            // When
+            let result = normalize_counts(raw_count, &WhitespaceTokenizer, &Normalizer::default());
+
+            // Then
+            assert_eq!(result.len(), 2);
+            assert_eq!(result.get("the"), Some(&3));
+            assert_eq!(result.get("fox"), Some(&4));
+        }
+
+        #[test]
+        fn test_normalize_counts_drops_punctuation_only_tokens() {
+            // Given
+            let raw_count = HashMap::from([("--", 5), ("word", 1)]);
+
+            // When
+            let result = normalize_counts(raw_count, &WhitespaceTokenizer, &Normalizer::default());
+
+            // Then
+            assert_eq!(result.len(), 1);
+            assert_eq!(result.get("word"), Some(&1));
+        }
+    }
+
+    mod test_chunk_as_str {
+        use super::*;
+
+        #[test]
+        fn test_chunk_as_str_clamps_to_mapping() {
+            // Given
+            let bytes = b"Line 1\nLine 2\n";
+            let chunk = FileChunk { start: 7, end: 100 };
+
+            // When
+            let result =
+                chunk_as_str(bytes, &chunk, Utf8Policy::Fail, &InvalidLines::default()).unwrap();
+
+            // Then
+            assert_eq!(result, "Line 2\n");
+        }
+
+        #[test]
+        fn test_chunk_as_str_reports_offset_of_invalid_utf8() {
+            // Given
+            let bytes = b"ok\nbad \xff here\n";
+            let chunk = FileChunk { start: 3, end: 15 };
+
+            // When
+            let err = chunk_as_str(bytes, &chunk, Utf8Policy::Fail, &InvalidLines::default())
+                .unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("byte offset 7"), "{}", err);
+        }
+    }
+
+    mod test_count_chunks {
+        use super::*;
+
+        fn chunks(bounds: &[(u64, u64)]) -> impl FnMut() -> io::Result<Option<FileChunk>> + '_ {
+            let mut bounds = bounds.iter();
+            move || Ok(bounds.next().map(|&(start, end)| FileChunk { start, end }))
+        }
+
+        #[test]
+        fn test_count_chunks_counts_only_the_chunks_handed_out() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "The fox\nskipped line\nthe DOG, the end\n").unwrap();
+
+            // When
+            let result =
+                count_chunks(&input_path, chunks(&[(0, 8), (21, 38)]), &Config::default()).unwrap();
+
+            // Then
+            assert_eq!(
+                result,
+                HashMap::from([
+                    ("the".to_string(), 3),
+                    ("fox".to_string(), 1),
+                    ("dog".to_string(), 1),
+                    ("end".to_string(), 1),
+                ])
+            );
+        }
+
+        #[test]
+        fn test_count_chunks_with_no_chunks() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            fs::write(&input_path, "some words\n").unwrap();
+
+            // When
+            let result = count_chunks(&input_path, chunks(&[]), &Config::default()).unwrap();
+
+            // Then
+            assert!(result.is_empty());
+        }
+
+        #[test]
+        fn test_count_chunks_with_non_existent_file() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("non_existent.txt");
+
+            // When
+            let result = count_chunks(&input_path, chunks(&[(0, 1)]), &Config::default());
+
+            // Then
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        }
+
+        #[test]
+        fn test_count_chunks_with_invalid_utf8() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("invalid.txt");
+            fs::write(&input_path, b"valid line\ninvalid \xc3\x28 line\n").unwrap();
+
+            // When
+            let result = count_chunks(
+                &input_path,
+                chunks(&[(0, 11), (11, 26)]),
+                &Config::default(),
+            );
+
+            // Then
+            let err = result.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("byte offset 19"), "{}", err);
+        }
+
+        #[test]
+        fn test_count_chunks_normalizes_repaired_chunks() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("invalid.txt");
+            fs::write(&input_path, b"Word here\nbad \xff\nWORD\n").unwrap();
+            let config = Config {
+                invalid_utf8: Utf8Policy::Skip,
+                ..Config::default()
+            };
+
+            // When
+            let result = count_chunks(&input_path, chunks(&[(0, 10), (10, 24)]), &config).unwrap();
+
+            // Then
+            assert_eq!(result.get("word"), Some(&2));
+            assert_eq!(result.get("bad"), None);
+            assert_eq!(config.invalid_lines.get(), 1);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::thread;
+
+use crate::error::WordCountError;
+
+/// Adds every count of `from` into `into`, iterating over whichever map is smaller.
+pub(crate) fn merge_counts<K: Eq + Hash>(
+    into: &mut HashMap<K, usize>,
+    mut from: HashMap<K, usize>,
+) {
+    if from.len() > into.len() {
+        std::mem::swap(into, &mut from);
+    }
+    into.reserve(from.len());
+    for (word, count) in from {
+        *into.entry(word).or_insert(0) += count;
+    }
+}
+
+/// Combines per-thread count maps pairwise, one round of parallel merges at a time.
+///
+/// Each round merges map `2i + 1` into map `2i` on its own scoped thread, so `n` maps are
+/// reduced in `ceil(log2(n))` rounds without any shared lock.
+pub(crate) fn tree_reduce<K: Eq + Hash + Send>(
+    mut maps: Vec<HashMap<K, usize>>,
+) -> io::Result<HashMap<K, usize>> {
+    while maps.len() > 1 {
+        let mut pairs = Vec::with_capacity(maps.len() / 2);
+        let mut leftover = None;
+        let mut iter = maps.into_iter();
+        while let Some(left) = iter.next() {
+            match iter.next() {
+                Some(right) => pairs.push((left, right)),
+                None => leftover = Some(left),
+            }
+        }
+
+        maps =
+            thread::scope(|scope| -> io::Result<_> {
+                let handles: Vec<_> = pairs
+                    .into_iter()
+                    .map(|(mut left, right)| {
+                        scope.spawn(move || {
+                            merge_counts(&mut left, right);
+                            left
+                        })
+                    })
+                    .collect();
+                let mut merged = Vec::with_capacity(handles.len());
+                for (i, handle) in handles.into_iter().enumerate() {
+                    merged.push(handle.join().map_err(|e| {
+                        WordCountError::worker_panic(format!("Merge thread {}", i), e)
+                    })?);
+                }
+                Ok(merged)
+            })?;
+        maps.extend(leftover);
+    }
+    Ok(maps.pop().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{count_file, Config, Pipeline};
+    use std::fs;
+    use std::hash::Hasher;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    /// The merge `process_file` used before tree reduction: every map is added under one lock.
+    fn mutex_merge(maps: Vec<HashMap<String, usize>>) -> HashMap<String, usize> {
+        let total = Arc::new(Mutex::new(HashMap::new()));
+        let handles: Vec<_> = maps
+            .into_iter()
+            .map(|map| {
+                let total = Arc::clone(&total);
+                thread::spawn(move || {
+                    let mut total = total.lock().unwrap();
+                    for (word, count) in map {
+                        *total.entry(word).or_insert(0) += count;
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        Arc::try_unwrap(total).unwrap().into_inner().unwrap()
+    }
+
+    fn sample_maps(n: usize) -> Vec<HashMap<String, usize>> {
+        (0..n)
+            .map(|i| {
+                (0..50)
+                    .filter(|j| (i + j) % 3 != 0)
+                    .map(|j| (format!("word{}", j), i * j + 1))
+                    .collect()
+            })
+            .collect()
+    }
+
+    mod test_merge_counts {
+        use super::*;
+
+        #[test]
+        fn test_merge_counts_adds_shared_and_new_words() {
+            // Given
+            let mut into = HashMap::from([("a", 1), ("b", 2)]);
+            let from = HashMap::from([("b", 3), ("c", 4)]);
+
+            // When
+            merge_counts(&mut into, from);
+
+            // Then
+            assert_eq!(into, HashMap::from([("a", 1), ("b", 5), ("c", 4)]));
+        }
+
+        #[test]
+        fn test_merge_counts_into_empty_map() {
+            // Given
+            let mut into = HashMap::new();
+            let from = HashMap::from([("a", 1), ("b", 2), ("c", 3)]);
+
+            // When
+            merge_counts(&mut into, from.clone());
+
+            // Then
+            assert_eq!(into, from);
+        }
+    }
+
+    mod test_tree_reduce {
+        use super::*;
+
+        #[test]
+        fn test_tree_reduce_with_no_maps() {
+            // When
+            let result: HashMap<String, usize> = tree_reduce(Vec::new()).unwrap();
+
+            // Then
+            assert!(result.is_empty());
+        }
+
+        #[test]
+        fn test_tree_reduce_reports_panicking_merge() {
+            // Given
+            static ARMED: AtomicBool = AtomicBool::new(false);
+            #[derive(Debug, PartialEq, Eq)]
+            struct Fragile(u32);
+            impl Hash for Fragile {
+                fn hash<H: Hasher>(&self, state: &mut H) {
+                    assert!(!ARMED.load(Ordering::Relaxed), "hashed after arming");
+                    self.0.hash(state);
+                }
+            }
+            let maps = vec![
+                HashMap::from([(Fragile(1), 1)]),
+                HashMap::from([(Fragile(2), 1)]),
+            ];
+            ARMED.store(true, Ordering::Relaxed);
+
+            // When
+            let err = tree_reduce(maps).unwrap_err();
+
+            // Then
+            let err = WordCountError::from(err);
+            assert!(
+                matches!(&err, WordCountError::WorkerPanic { worker, .. } if worker == "Merge thread 0"),
+                "{:?}",
+                err
+            );
+        }
+
+        #[test]
+        fn test_tree_reduce_with_one_map() {
+            // Given
+            let maps = sample_maps(1);
+            let expected = maps[0].clone();
+
+            // When
+            let result = tree_reduce(maps).unwrap();
+
+            // Then
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn test_tree_reduce_matches_mutex_merge_for_any_map_count() {
+            for n in 1..=17 {
+                // Given
+                let maps = sample_maps(n);
+
+                // When
+                let reduced = tree_reduce(maps.clone()).unwrap();
+
+                // Then
+                assert_eq!(reduced, mutex_merge(maps), "Mismatch with {} maps", n);
+            }
+        }
+
+        #[test]
+        fn test_tree_reduce_with_borrowed_keys() {
+            // Given
+            let text = String::from("a b a c b a");
+            let maps: Vec<HashMap<&str, usize>> = text
+                .split(' ')
+                .map(|word| HashMap::from([(word, 1)]))
+                .collect();
+
+            // When
+            let result = tree_reduce(maps).unwrap();
+
+            // Then
+            assert_eq!(result, HashMap::from([("a", 3), ("b", 2), ("c", 1)]));
+        }
+    }
+
+    mod test_count_file_thread_counts {
+        use super::*;
+
+        #[test]
+        fn test_count_file_is_identical_for_any_thread_count() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            let content: String = (0..400)
+                .map(|i| format!("Line {} has word{} and Word{}, again.\n", i, i % 7, i % 11))
+                .collect();
+            fs::write(&input_path, content).unwrap();
+            let single_threaded = count_file(
+                &input_path,
+                &Config {
+                    num_threads: 1,
+                    pipeline: Pipeline::Lines,
+                    ..Config::default()
+                },
+            )
+            .unwrap();
+
+            for pipeline in [Pipeline::Lines, Pipeline::Mmap] {
+                for num_threads in 1..=12 {
+                    // When
+                    let result = count_file(
+                        &input_path,
+                        &Config {
+                            num_threads,
+                            pipeline,
+                            ..Config::default()
+                        },
+                    )
+                    .unwrap();
+
+                    // Then
+                    assert_eq!(
+                        result, single_threaded,
+                        "Mismatch for {:?} with {} threads",
+                        pipeline, num_threads
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::decompress::decompressing_reader;
+use crate::mapped::chunk_as_str;
+use crate::merge::merge_counts;
+use crate::normalize::{normalize_token, Normalizer};
+use crate::progress::{self, debug};
+use crate::tokenizer::Tokenizer;
+use crate::{stream, Config, FileChunk, STDIN_PATH};
+
+/// Where an n-gram may not continue, as selected with `--ngram-boundary`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum Boundary {
+    /// At the end of a sentence (".", "!", "?", "…" and their CJK forms) and at blank lines
+    #[default]
+    Sentence,
+    /// At the end of every line
+    Line,
+    /// Only at the end of each input
+    None,
+}
+
+/// N-gram counting, as selected with `--ngrams`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct NgramConfig {
+    /// Number of words per n-gram, at least 2.
+    pub(crate) n: usize,
+    pub(crate) boundary: Boundary,
+}
+
+/// The n-grams of a contiguous piece of input, plus what is needed to count the n-grams that
+/// cross into the neighbouring pieces.
+///
+/// `prefix` holds the first words before any boundary and `suffix` the last words after any
+/// boundary, at most `n - 1` of each. Appending the next piece counts the n-grams of
+/// `suffix ++ next.prefix`, so pieces can be counted independently, by any worker, and joined
+/// in input order afterwards.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Segment {
+    pub(crate) counts: HashMap<String, usize>,
+    prefix: Vec<String>,
+    suffix: Vec<String>,
+    has_boundary: bool,
+}
+
+/// The edges of the pieces of one input, each with the offset the piece starts at.
+pub(crate) type Edges = Vec<(u64, Segment)>;
+
+impl Segment {
+    /// Counts the n-grams of `text`, a run of whole lines.
+    pub(crate) fn from_text(
+        text: &str,
+        ngrams: &NgramConfig,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) -> Self {
+        let mut builder = Builder::new(ngrams.n);
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                if ngrams.boundary == Boundary::Sentence {
+                    builder.boundary();
+                }
+                continue;
+            }
+
+            let mut tokens = Vec::new();
+            tokenizer.tokenize(line, &mut |token| tokens.push(token));
+            for (i, token) in tokens.iter().enumerate() {
+                let word = normalize_token(tokenizer, normalizer, token);
+                if !word.is_empty() {
+                    builder.word(word);
+                }
+                if ngrams.boundary == Boundary::Sentence {
+                    // The text up to the next token: the token's own trailing punctuation and
+                    // anything the tokenizer skipped.
+                    let start = offset_in(line, token);
+                    let end = tokens
+                        .get(i + 1)
+                        .map_or(line.len(), |next| offset_in(line, next));
+                    if ends_sentence(line.get(start..end).unwrap_or(token)) {
+                        builder.boundary();
+                    }
+                }
+            }
+            if ngrams.boundary == Boundary::Line {
+                builder.boundary();
+            }
+        }
+        builder.finish()
+    }
+
+    /// Joins `next`, the piece that directly follows this one in the input.
+    pub(crate) fn append(&mut self, next: Segment, n: usize) {
+        let joined: Vec<&String> = self.suffix.iter().chain(&next.prefix).collect();
+        for window in joined.windows(n) {
+            *self.counts.entry(join(window)).or_insert(0) += 1;
+        }
+
+        if !self.has_boundary {
+            self.prefix.extend(next.prefix.iter().cloned());
+            self.prefix.truncate(n - 1);
+        }
+        if next.has_boundary {
+            self.suffix = next.suffix;
+        } else {
+            self.suffix.extend(next.suffix);
+            let excess = self.suffix.len().saturating_sub(n - 1);
+            self.suffix.drain(..excess);
+        }
+        self.has_boundary |= next.has_boundary;
+        merge_counts(&mut self.counts, next.counts);
+    }
+
+    /// Splits off the counts, so the edges can be kept until the pieces are joined.
+    fn take_counts(&mut self) -> HashMap<String, usize> {
+        std::mem::take(&mut self.counts)
+    }
+}
+
+/// Feeds words and boundaries of one piece of input, in order, into a `Segment`.
+struct Builder {
+    n: usize,
+    segment: Segment,
+    /// The last words since the most recent boundary, at most `n`.
+    window: VecDeque<String>,
+}
+
+impl Builder {
+    fn new(n: usize) -> Self {
+        Builder {
+            n,
+            segment: Segment::default(),
+            window: VecDeque::with_capacity(n),
+        }
+    }
+
+    fn word(&mut self, word: String) {
+        if !self.segment.has_boundary && self.segment.prefix.len() < self.n - 1 {
+            self.segment.prefix.push(word.clone());
+        }
+        if self.window.len() == self.n {
+            self.window.pop_front();
+        }
+        self.window.push_back(word);
+        if self.window.len() == self.n {
+            let ngram = join(&self.window.iter().collect::<Vec<_>>());
+            *self.segment.counts.entry(ngram).or_insert(0) += 1;
+        }
+    }
+
+    fn boundary(&mut self) {
+        self.segment.has_boundary = true;
+        self.window.clear();
+    }
+
+    fn finish(mut self) -> Segment {
+        let skip = self.window.len().saturating_sub(self.n - 1);
+        self.segment.suffix = self.window.into_iter().skip(skip).collect();
+        self.segment
+    }
+}
+
+fn join(words: &[&String]) -> String {
+    words
+        .iter()
+        .map(|word| word.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Byte offset of `token`, a slice of `line`, within `line`.
+fn offset_in(line: &str, token: &str) -> usize {
+    token.as_ptr() as usize - line.as_ptr() as usize
+}
+
+/// Whether a token, with the text that follows it up to the next token, ends a sentence: there
+/// is sentence-ending punctuation after its last letter or digit. Closing quotes may follow, a
+/// full stop inside a token ("3.14") does not count, and abbreviations ("e.g.") are taken as
+/// sentence ends.
+fn ends_sentence(text: &str) -> bool {
+    let tail = text.rsplit(char::is_alphanumeric).next().unwrap_or("");
+    tail.contains(['.', '!', '?', '…', '。', '！', '？'])
+}
+
+/// Counts the n-grams of an input the work queue cannot cut into chunks: stdin, pipes,
+/// compressed and UTF-16 files, and inputs counted while `--export-chunks` is set.
+pub(crate) fn count_file_ngrams(
+    input_path: &Path,
+    config: &Config,
+    ngrams: &NgramConfig,
+) -> io::Result<HashMap<String, usize>> {
+    if input_path == Path::new(STDIN_PATH) {
+        return count_stream_ngrams(decompressing_reader(io::stdin())?, config, ngrams);
+    }
+    let reader = decompressing_reader(File::open(input_path)?)?;
+    count_stream_ngrams(reader, config, ngrams)
+}
+
+/// Counts the n-grams of the chunks of `path` that `next_chunk` hands out, through one memory
+/// mapping of the file. Returns the counts with the edges of every chunk by offset, to be
+/// joined by [`join_edges`] once all chunks of the file are counted.
+pub(crate) fn count_chunks(
+    path: &Path,
+    mut next_chunk: impl FnMut() -> io::Result<Option<FileChunk>>,
+    config: &Config,
+    ngrams: &NgramConfig,
+) -> io::Result<(HashMap<String, usize>, Edges)> {
+    let file = File::open(path)?;
+    // SAFETY: as in `mapped::count_chunks`, the mapping is read-only and the input is expected
+    // not to be truncated while it is being counted.
+    let mmap = unsafe { Mmap::map(&file)? };
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let mut counts = HashMap::new();
+    let mut edges = Vec::new();
+    while let Some(chunk) = next_chunk()? {
+        let text = chunk_as_str(&mmap, &chunk, config.invalid_utf8, &config.invalid_lines)?;
+        let mut segment = Segment::from_text(&text, ngrams, tokenizer, normalizer);
+        let chunk_counts = segment.take_counts();
+        progress::add_bytes(chunk.end - chunk.start);
+        progress::add_words(chunk_counts.values().sum::<usize>() as u64);
+        merge_counts(&mut counts, chunk_counts);
+        edges.push((chunk.start, segment));
+    }
+    Ok((counts, edges))
+}
+
+/// Counts the n-grams of a stream. Workers keep the counts of all their blocks in one map and
+/// only the edges of each block, which are joined in offset order at the end.
+fn count_stream_ngrams<R: io::Read + Send>(
+    reader: R,
+    config: &Config,
+    ngrams: &NgramConfig,
+) -> io::Result<HashMap<String, usize>> {
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let thread_results = stream::fold_stream(
+        reader,
+        config,
+        || (HashMap::new(), Vec::new()),
+        |(counts, edges): &mut (HashMap<String, usize>, Edges), text, offset| {
+            let mut segment = Segment::from_text(text, ngrams, tokenizer, normalizer);
+            let block_counts = segment.take_counts();
+            let words = block_counts.values().sum();
+            merge_counts(counts, block_counts);
+            edges.push((offset, segment));
+            words
+        },
+    )?;
+
+    let mut counts = HashMap::new();
+    let mut edges = Vec::new();
+    for (thread_counts, thread_edges) in thread_results {
+        merge_counts(&mut counts, thread_counts);
+        edges.extend(thread_edges);
+    }
+
+    debug!("All threads finished, joining blocks");
+    join_edges(&mut counts, edges, ngrams.n);
+    Ok(counts)
+}
+
+/// Adds the n-grams that cross from one piece of an input into the next to `counts`, given the
+/// edges of every piece of the input with its offset, in any order.
+pub(crate) fn join_edges(counts: &mut HashMap<String, usize>, mut edges: Edges, n: usize) {
+    edges.sort_by_key(|&(offset, _)| offset);
+    merge_counts(
+        counts,
+        join_segments(edges.into_iter().map(|(_, segment)| segment), n),
+    );
+}
+
+fn join_segments(segments: impl IntoIterator<Item = Segment>, n: usize) -> HashMap<String, usize> {
+    let mut joined = Segment::default();
+    for segment in segments {
+        joined.append(segment, n);
+    }
+    joined.counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counts;
+    use crate::tokenizer::WhitespaceTokenizer;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+
+    fn ngram_config(n: usize, boundary: Boundary) -> NgramConfig {
+        NgramConfig { n, boundary }
+    }
+
+    fn segment(text: &str, ngrams: &NgramConfig) -> Segment {
+        Segment::from_text(text, ngrams, &WhitespaceTokenizer, &Normalizer::default())
+    }
+
+    const TEXT: &str = "The cat sat. The cat ran!\n\
+                        Did the cat\n\
+                        sit? \"Yes.\" It did, 3.14 times\n\
+                        \n\
+                        new paragraph here\n";
+
+    mod test_segment {
+        use super::*;
+
+        #[test]
+        fn test_bigrams_stop_at_sentence_ends() {
+            // When
+            let result = segment(TEXT, &ngram_config(2, Boundary::Sentence)).counts;
+
+            // Then
+            assert_eq!(
+                result,
+                counts(&[
+                    ("the cat", 3),
+                    ("cat sat", 1),
+                    ("cat ran", 1),
+                    ("did the", 1),
+                    ("cat sit", 1),
+                    ("it did", 1),
+                    ("did 314", 1),
+                    ("314 times", 1),
+                    ("new paragraph", 1),
+                    ("paragraph here", 1),
+                ])
+            );
+        }
+
+        #[test]
+        fn test_line_and_no_boundary() {
+            // Given
+            let text = "a b\nc\n\nd e.\nf\n";
+
+            // When
+            let line = segment(text, &ngram_config(2, Boundary::Line)).counts;
+            let none = segment(text, &ngram_config(3, Boundary::None)).counts;
+
+            // Then
+            assert_eq!(line, counts(&[("a b", 1), ("d e", 1)]));
+            assert_eq!(
+                none,
+                counts(&[("a b c", 1), ("b c d", 1), ("c d e", 1), ("d e f", 1)])
+            );
+        }
+
+        #[test]
+        fn test_split_anywhere_at_line_boundaries_matches_whole_text() {
+            for boundary in [Boundary::Sentence, Boundary::Line, Boundary::None] {
+                for n in 2..=4 {
+                    let ngrams = ngram_config(n, boundary);
+                    let expected = segment(TEXT, &ngrams).counts;
+                    let lines: Vec<&str> = TEXT.split_inclusive('\n').collect();
+                    for split in 0..=lines.len() {
+                        // Given
+                        let (head, tail) = lines.split_at(split);
+
+                        // When
+                        let mut joined = segment(&head.concat(), &ngrams);
+                        joined.append(segment(&tail.concat(), &ngrams), n);
+
+                        // Then
+                        assert_eq!(
+                            joined.counts, expected,
+                            "{:?} n={} at {}",
+                            boundary, n, split
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_join_of_many_short_pieces() {
+            // Given: pieces shorter than n - 1 words.
+            let ngrams = ngram_config(4, Boundary::None);
+            let text = "one\ntwo\n\nthree\nfour five\nsix\n";
+
+            // When
+            let joined = join_segments(
+                text.split_inclusive('\n')
+                    .map(|line| segment(line, &ngrams)),
+                4,
+            );
+
+            // Then
+            assert_eq!(joined, segment(text, &ngrams).counts);
+            assert_eq!(joined.len(), 3);
+        }
+    }
+
+    mod test_count_file_ngrams {
+        use super::*;
+
+        fn long_text() -> String {
+            (0..200)
+                .map(|i| {
+                    let end = if i % 7 == 0 { "." } else { "" };
+                    format!("w{} w{} w{}{}\n", i % 5, i % 3, i % 11, end)
+                })
+                .collect()
+        }
+
+        #[test]
+        fn test_count_file_ngrams_agrees_for_any_chunking() {
+            // Given
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(long_text().as_bytes()).unwrap();
+            let ngrams = ngram_config(3, Boundary::Sentence);
+            let expected = segment(&long_text(), &ngrams).counts;
+
+            for num_threads in [1, 2, 3, 7] {
+                for block_size in [1, 10, 1 << 20] {
+                    let config = Config {
+                        num_threads,
+                        block_size,
+                        chunk_size: Some(block_size),
+                        ngrams: Some(ngrams),
+                        ..Config::default()
+                    };
+
+                    // When
+                    let chunked = crate::count_file(file.path(), &config).unwrap();
+                    let streamed =
+                        count_stream_ngrams(long_text().as_bytes(), &config, &ngrams).unwrap();
+
+                    // Then
+                    assert_eq!(
+                        chunked, expected,
+                        "{} threads, {} byte chunks",
+                        num_threads, block_size
+                    );
+                    assert_eq!(
+                        streamed, expected,
+                        "{} threads, {} bytes",
+                        num_threads, block_size
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_count_file_ngrams_reads_compressed_and_empty_files() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let gz = temp_dir.path().join("input.txt.gz");
+            let mut encoder =
+                flate2::write::GzEncoder::new(File::create(&gz).unwrap(), Default::default());
+            encoder.write_all(b"to be or\nnot to be\n").unwrap();
+            encoder.finish().unwrap();
+            let empty = temp_dir.path().join("empty.txt");
+            File::create(&empty).unwrap();
+            let ngrams = ngram_config(2, Boundary::None);
+
+            // When
+            let result = count_file_ngrams(&gz, &Config::default(), &ngrams).unwrap();
+
+            // Then
+            assert_eq!(
+                result,
+                counts(&[("to be", 2), ("be or", 1), ("or not", 1), ("not to", 1)])
+            );
+            assert!(count_file_ngrams(&empty, &Config::default(), &ngrams)
+                .unwrap()
+                .is_empty());
+        }
+    }
+}
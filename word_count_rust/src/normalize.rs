@@ -0,0 +1,268 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::tokenizer::Tokenizer;
+
+/// Unicode normalization form applied to every word, as selected with `--normalize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Form {
+    /// Count words exactly as the tokenizer produced them
+    #[default]
+    None,
+    /// Canonical composition: "é" and "e\u{301}" become the same word
+    Nfc,
+    /// Compatibility composition: also folds ligatures, fullwidth and superscript forms
+    Nfkc,
+}
+
+/// How letter case is treated, as selected with `--case`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CaseMode {
+    /// Per-character lowercasing ("Straße" and "STRASSE" stay different)
+    #[default]
+    Lower,
+    /// Full Unicode case folding ("Straße" and "STRASSE" both become "strasse")
+    Fold,
+    /// Full case folding with the Turkish and Azerbaijani rules for dotted and dotless I
+    FoldTurkic,
+    /// Keep the case of the input
+    Preserve,
+}
+
+/// Turns cleaned tokens into the words that are counted.
+///
+/// The default (no normalization form, lowercasing, diacritics kept) is what the counter has
+/// always done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Normalizer {
+    pub(crate) form: Form,
+    pub(crate) case: CaseMode,
+    /// Remove combining marks after canonical decomposition, so "café" counts as "cafe".
+    pub(crate) strip_diacritics: bool,
+}
+
+impl Normalizer {
+    /// A key for these settings that `--state` saves; unlike the `Debug` output it is kept
+    /// stable on purpose.
+    pub(crate) fn fingerprint(&self) -> String {
+        let form = match self.form {
+            Form::None => "none",
+            Form::Nfc => "nfc",
+            Form::Nfkc => "nfkc",
+        };
+        let case = match self.case {
+            CaseMode::Lower => "lower",
+            CaseMode::Fold => "fold",
+            CaseMode::FoldTurkic => "fold-turkic",
+            CaseMode::Preserve => "preserve",
+        };
+        format!("{} {} {}", form, case, self.strip_diacritics)
+    }
+
+    pub(crate) fn normalize(&self, word: &str) -> String {
+        if word.is_ascii() {
+            // Nothing below changes ASCII text except case mapping, and only the Turkic rules
+            // map an ASCII letter outside ASCII.
+            match self.case {
+                CaseMode::Preserve => return word.to_string(),
+                CaseMode::Lower | CaseMode::Fold => return word.to_ascii_lowercase(),
+                CaseMode::FoldTurkic => {}
+            }
+        }
+
+        let word = match self.form {
+            Form::None => word.to_string(),
+            Form::Nfc => word.nfc().collect(),
+            Form::Nfkc => word.nfkc().collect(),
+        };
+        let word = match self.case {
+            CaseMode::Lower => word.chars().flat_map(char::to_lowercase).collect(),
+            CaseMode::Fold => caseless::default_case_fold_str(&word),
+            CaseMode::FoldTurkic => caseless::default_case_fold_str(&turkic_fold(&word)),
+            CaseMode::Preserve => word,
+        };
+
+        // Case mapping can leave the text unnormalized (İ folds to "i" plus a combining dot),
+        // so the form is applied again, after any diacritics have been removed.
+        if self.strip_diacritics {
+            let stripped = word.nfd().filter(|&c| !is_combining_mark(c));
+            match self.form {
+                Form::Nfkc => stripped.nfkc().collect(),
+                Form::None | Form::Nfc => stripped.nfc().collect(),
+            }
+        } else {
+            match self.form {
+                Form::None => word,
+                Form::Nfc => word.nfc().collect(),
+                Form::Nfkc => word.nfkc().collect(),
+            }
+        }
+    }
+}
+
+/// The Turkic special cases of case folding: "I" folds to dotless "ı" and "İ" to plain "i".
+fn turkic_fold(word: &str) -> String {
+    word.chars()
+        .map(|c| match c {
+            'I' => 'ı',
+            '\u{130}' => 'i',
+            c => c,
+        })
+        .collect()
+}
+
+/// The word a raw token is counted as: cleaned by its tokenizer, then normalized.
+pub(crate) fn normalize_token(
+    tokenizer: &dyn Tokenizer,
+    normalizer: &Normalizer,
+    token: &str,
+) -> String {
+    normalizer.normalize(&tokenizer.clean(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(form: Form, case: CaseMode, strip_diacritics: bool) -> Normalizer {
+        Normalizer {
+            form,
+            case,
+            strip_diacritics,
+        }
+    }
+
+    const COMPOSED: &str = "caf\u{e9}";
+    const DECOMPOSED: &str = "cafe\u{301}";
+
+    mod test_default_normalizer {
+        use super::*;
+
+        #[test]
+        fn test_default_matches_per_character_lowercasing() {
+            for word in [
+                "HeLLo",
+                "STRASSE",
+                "Straße",
+                "İstanbul",
+                DECOMPOSED,
+                "ＡＢＣ",
+            ] {
+                // When
+                let result = Normalizer::default().normalize(word);
+
+                // Then
+                let expected: String = word.chars().flat_map(char::to_lowercase).collect();
+                assert_eq!(result, expected, "{:?}", word);
+            }
+        }
+    }
+
+    mod test_form {
+        use super::*;
+
+        #[test]
+        fn test_nfc_unifies_combining_accents() {
+            // Given
+            let nfc = normalizer(Form::Nfc, CaseMode::Lower, false);
+
+            // Then
+            assert_ne!(Normalizer::default().normalize(DECOMPOSED), COMPOSED);
+            assert_eq!(nfc.normalize(DECOMPOSED), COMPOSED);
+            assert_eq!(nfc.normalize(COMPOSED), COMPOSED);
+        }
+
+        #[test]
+        fn test_nfkc_folds_compatibility_forms() {
+            // Given
+            let nfc = normalizer(Form::Nfc, CaseMode::Lower, false);
+            let nfkc = normalizer(Form::Nfkc, CaseMode::Lower, false);
+
+            // Then
+            assert_eq!(nfc.normalize("ｆｕｌｌ"), "ｆｕｌｌ");
+            assert_eq!(nfkc.normalize("ｆｕｌｌ"), "full");
+            assert_eq!(nfkc.normalize("\u{fb01}le"), "file");
+        }
+    }
+
+    mod test_case_mode {
+        use super::*;
+
+        #[test]
+        fn test_fold_handles_german_sharp_s() {
+            // Given
+            let fold = normalizer(Form::None, CaseMode::Fold, false);
+
+            // Then
+            assert_ne!(
+                Normalizer::default().normalize("Straße"),
+                Normalizer::default().normalize("STRASSE")
+            );
+            assert_eq!(fold.normalize("Straße"), "strasse");
+            assert_eq!(fold.normalize("STRASSE"), "strasse");
+            assert_eq!(fold.normalize("ẞ"), "ss");
+        }
+
+        #[test]
+        fn test_fold_keeps_dot_of_turkish_capital_i() {
+            // Given
+            let fold = normalizer(Form::Nfc, CaseMode::Fold, false);
+
+            // Then
+            assert_eq!(fold.normalize("İstanbul"), "i\u{307}stanbul");
+            assert_eq!(fold.normalize("ISTANBUL"), "istanbul");
+        }
+
+        #[test]
+        fn test_fold_turkic_maps_dotted_and_dotless_i() {
+            // Given
+            let turkic = normalizer(Form::Nfc, CaseMode::FoldTurkic, false);
+
+            // Then
+            assert_eq!(turkic.normalize("İSTANBUL"), "istanbul");
+            assert_eq!(turkic.normalize("İstanbul"), "istanbul");
+            assert_eq!(turkic.normalize("ISPARTA"), "ısparta");
+            assert_eq!(turkic.normalize("ılık"), "ılık");
+        }
+
+        #[test]
+        fn test_preserve_keeps_case() {
+            // Given
+            let preserve = normalizer(Form::Nfc, CaseMode::Preserve, false);
+
+            // Then
+            assert_eq!(preserve.normalize("Hello"), "Hello");
+            assert_eq!(preserve.normalize("Straße"), "Straße");
+            assert_eq!(preserve.normalize("İstanbul"), "İstanbul");
+            assert_eq!(preserve.normalize("Cafe\u{301}"), "Caf\u{e9}");
+        }
+    }
+
+    mod test_strip_diacritics {
+        use super::*;
+
+        #[test]
+        fn test_strip_diacritics_from_composed_and_decomposed_forms() {
+            // Given
+            let strip = normalizer(Form::None, CaseMode::Lower, true);
+
+            // Then
+            assert_eq!(strip.normalize(COMPOSED), "cafe");
+            assert_eq!(strip.normalize(DECOMPOSED), "cafe");
+            assert_eq!(strip.normalize("Ångström"), "angstrom");
+        }
+
+        #[test]
+        fn test_strip_diacritics_with_turkish_and_german() {
+            // Given
+            let fold = normalizer(Form::Nfkc, CaseMode::Fold, true);
+            let preserve = normalizer(Form::Nfc, CaseMode::Preserve, true);
+
+            // Then
+            assert_eq!(fold.normalize("İstanbul"), "istanbul");
+            assert_eq!(fold.normalize("Straße"), "strasse");
+            assert_eq!(preserve.normalize("İstanbul"), "Istanbul");
+            assert_eq!(preserve.normalize("Straße"), "Straße");
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+/// How often the progress bar is redrawn.
+const BAR_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a JSON progress event is written.
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+/// Width of the progress bar, in cells.
+const BAR_WIDTH: usize = 24;
+
+/// Importance of a log message; messages above the configured level are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub(crate) enum Level {
+    Error,
+    Warn,
+    /// Summaries of the run: totals, skipped files, where results went.
+    #[default]
+    Info,
+    /// Workers starting and finishing, phases of the run.
+    Debug,
+    /// Every chunk, block and file read.
+    Trace,
+}
+
+impl Level {
+    /// `--quiet` keeps errors only; every `-v` shows one more level.
+    pub(crate) fn from_flags(quiet: bool, verbose: u8) -> Level {
+        match (quiet, verbose) {
+            (true, _) => Level::Error,
+            (false, 0) => Level::Info,
+            (false, 1) => Level::Debug,
+            (false, _) => Level::Trace,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+/// How progress is shown, as selected with `--progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum ProgressMode {
+    /// A progress bar when stderr is a terminal, log lines only otherwise
+    #[default]
+    Auto,
+    /// A progress bar on stderr, redrawn below the log lines
+    Bar,
+    /// One JSON event per line on stderr: start, progress, worker, log and finish
+    Json,
+    /// Log lines only
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct ProgressConfig {
+    pub(crate) mode: ProgressMode,
+    pub(crate) level: Level,
+}
+
+/// What a worker of the pool is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum WorkerState {
+    #[default]
+    Idle,
+    Counting,
+    Done,
+}
+
+impl WorkerState {
+    fn name(self) -> &'static str {
+        match self {
+            WorkerState::Idle => "idle",
+            WorkerState::Counting => "counting",
+            WorkerState::Done => "done",
+        }
+    }
+}
+
+/// Where events end up, resolved from the `ProgressMode` once stderr has been looked at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Render {
+    Text,
+    Bar,
+    Json,
+}
+
+/// The state of a run at one point in time, as rendered by the bar and JSON progress events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    bytes: u64,
+    total_bytes: Option<u64>,
+    words: u64,
+    elapsed: Duration,
+    workers: Vec<WorkerState>,
+}
+
+impl Snapshot {
+    /// Time left at the average rate so far; unknown until the total size and a first byte are.
+    fn eta(&self) -> Option<Duration> {
+        let total = self.total_bytes?;
+        if self.bytes == 0 {
+            return None;
+        }
+        let left = total.saturating_sub(self.bytes) as f64 / self.bytes as f64;
+        Some(self.elapsed.mul_f64(left))
+    }
+
+    fn busy(&self) -> usize {
+        self.workers
+            .iter()
+            .filter(|&&state| state == WorkerState::Counting)
+            .count()
+    }
+
+    /// `[#########...............]  37%  96.0/256.0 MiB  1234567 words  3/4 busy  ETA 0:12`
+    fn bar_line(&self) -> String {
+        let mut line = String::new();
+        match self.total_bytes {
+            Some(total) if total > 0 => {
+                let fraction = (self.bytes as f64 / total as f64).min(1.0);
+                let filled = (fraction * BAR_WIDTH as f64) as usize;
+                line.push_str(&format!(
+                    "[{}{}] {:>3}%  {:.1}/{:.1} MiB",
+                    "#".repeat(filled),
+                    ".".repeat(BAR_WIDTH - filled),
+                    (fraction * 100.0) as u32,
+                    mebibytes(self.bytes),
+                    mebibytes(total)
+                ));
+            }
+            _ => line.push_str(&format!("{:.1} MiB", mebibytes(self.bytes))),
+        }
+        line.push_str(&format!("  {} words", self.words));
+        if !self.workers.is_empty() {
+            line.push_str(&format!("  {}/{} busy", self.busy(), self.workers.len()));
+        }
+        match self.eta() {
+            Some(eta) => line.push_str(&format!("  ETA {}", clock(eta))),
+            None => line.push_str(&format!("  {} elapsed", clock(self.elapsed))),
+        }
+        line
+    }
+
+    fn json(&self) -> serde_json::Value {
+        json!({
+            "event": "progress",
+            "bytes": self.bytes,
+            "total_bytes": self.total_bytes,
+            "words": self.words,
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+            "eta_ms": self.eta().map(|eta| eta.as_millis() as u64),
+            "workers": self.workers.iter().map(|state| state.name()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn mebibytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour on.
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Collects the progress of the run and writes every event to stderr.
+struct Reporter {
+    level: Level,
+    render: Render,
+    start: Instant,
+    /// `u64::MAX` while the size of the input is unknown.
+    total_bytes: AtomicU64,
+    bytes: AtomicU64,
+    words: AtomicU64,
+    /// Lines that were not valid UTF-8 and were skipped or decoded some other way.
+    invalid_lines: AtomicU64,
+    workers: Mutex<Vec<WorkerState>>,
+    /// Serializes writes to stderr; true while the bar is on the last line.
+    screen: Mutex<bool>,
+    ticker: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+/// The installed reporter; until `install` is called, as when counting through the library,
+/// only warnings and errors are logged.
+fn reporter() -> &'static Reporter {
+    REPORTER.get_or_init(|| Reporter::new(Level::Warn, Render::Text))
+}
+
+impl Reporter {
+    fn new(level: Level, render: Render) -> Self {
+        Reporter {
+            level,
+            render,
+            start: Instant::now(),
+            total_bytes: AtomicU64::new(u64::MAX),
+            bytes: AtomicU64::new(0),
+            words: AtomicU64::new(0),
+            invalid_lines: AtomicU64::new(0),
+            workers: Mutex::new(Vec::new()),
+            screen: Mutex::new(false),
+            ticker: Mutex::new(None),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        Snapshot {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            total_bytes: (total_bytes != u64::MAX).then_some(total_bytes),
+            words: self.words.load(Ordering::Relaxed),
+            elapsed: self.start.elapsed(),
+            workers: self.workers.lock().expect("progress poisoned").clone(),
+        }
+    }
+
+    /// Writes a line of text or a JSON event, keeping the bar below it. A failed write to
+    /// stderr is ignored: progress must never fail a run.
+    fn write_line(&self, line: &str) {
+        let bar_drawn = self.screen.lock().expect("progress poisoned");
+        let mut stderr = io::stderr().lock();
+        if *bar_drawn {
+            let _ = write!(stderr, "\r\x1b[2K");
+        }
+        let _ = writeln!(stderr, "{}", line);
+        if *bar_drawn {
+            let _ = write!(stderr, "{}", self.snapshot().bar_line());
+        }
+        let _ = stderr.flush();
+    }
+
+    fn draw_bar(&self) {
+        let mut bar_drawn = self.screen.lock().expect("progress poisoned");
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", self.snapshot().bar_line());
+        let _ = stderr.flush();
+        *bar_drawn = true;
+    }
+
+    fn clear_bar(&self) {
+        let mut bar_drawn = self.screen.lock().expect("progress poisoned");
+        if *bar_drawn {
+            let mut stderr = io::stderr().lock();
+            let _ = writeln!(stderr, "\r\x1b[2K{}", self.snapshot().bar_line());
+            let _ = stderr.flush();
+            *bar_drawn = false;
+        }
+    }
+
+    fn event(&self, event: serde_json::Value) {
+        if self.render == Render::Json {
+            self.write_line(&event.to_string());
+        }
+    }
+}
+
+/// Sets up reporting for the run; the bar and JSON modes get a thread that redraws the bar or
+/// writes a progress event at a fixed interval until `finish`. Only the first call counts.
+pub(crate) fn install(config: ProgressConfig) {
+    let render = match config.mode {
+        ProgressMode::Auto if io::stderr().is_terminal() => Render::Bar,
+        ProgressMode::Auto | ProgressMode::Off => Render::Text,
+        ProgressMode::Bar => Render::Bar,
+        ProgressMode::Json => Render::Json,
+    };
+    // Quiet means quiet, whatever the progress mode.
+    let render = if config.level == Level::Error {
+        Render::Text
+    } else {
+        render
+    };
+    if REPORTER.set(Reporter::new(config.level, render)).is_err() {
+        return;
+    }
+    let interval = match render {
+        Render::Text => return,
+        Render::Bar => BAR_INTERVAL,
+        Render::Json => JSON_INTERVAL,
+    };
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+            let reporter = reporter();
+            match reporter.render {
+                Render::Bar => reporter.draw_bar(),
+                _ => reporter.event(reporter.snapshot().json()),
+            }
+        }
+    });
+    *reporter().ticker.lock().expect("progress poisoned") = Some((sender, handle));
+}
+
+/// Announces the inputs of the run; the ETA needs `total_bytes`, the bytes that `add_bytes`
+/// will eventually have reported.
+pub(crate) fn start(inputs: usize, total_bytes: Option<u64>) {
+    let reporter = reporter();
+    reporter
+        .total_bytes
+        .store(total_bytes.unwrap_or(u64::MAX), Ordering::Relaxed);
+    reporter.event(json!({"event": "start", "inputs": inputs, "total_bytes": total_bytes}));
+}
+
+/// Stops the ticker, leaves the last state of the bar on screen and writes the final totals.
+pub(crate) fn finish() {
+    let reporter = reporter();
+    let ticker = reporter.ticker.lock().expect("progress poisoned").take();
+    if let Some((sender, handle)) = ticker {
+        drop(sender);
+        let _ = handle.join();
+    }
+    reporter.clear_bar();
+    let snapshot = reporter.snapshot();
+    reporter.event(json!({
+        "event": "finish",
+        "bytes": snapshot.bytes,
+        "words": snapshot.words,
+        "invalid_lines": invalid_lines(),
+        "elapsed_ms": snapshot.elapsed.as_millis() as u64,
+    }));
+}
+
+/// Adds bytes of input that have been counted.
+pub(crate) fn add_bytes(bytes: u64) {
+    reporter().bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Adds words that have been counted.
+pub(crate) fn add_words(words: u64) {
+    reporter().words.fetch_add(words, Ordering::Relaxed);
+}
+
+/// Adds lines that were not valid UTF-8 and were skipped or decoded some other way.
+pub(crate) fn add_invalid_lines(lines: u64) {
+    reporter().invalid_lines.fetch_add(lines, Ordering::Relaxed);
+}
+
+/// Lines reported by `add_invalid_lines` so far.
+pub(crate) fn invalid_lines() -> u64 {
+    reporter().invalid_lines.load(Ordering::Relaxed)
+}
+
+/// Records what worker `id` of the pool is doing, and on which input.
+pub(crate) fn worker(id: usize, state: WorkerState, path: Option<&Path>) {
+    let reporter = reporter();
+    {
+        let mut workers = reporter.workers.lock().expect("progress poisoned");
+        if workers.len() <= id {
+            workers.resize(id + 1, WorkerState::Idle);
+        }
+        workers[id] = state;
+    }
+    reporter.event(json!({
+        "event": "worker",
+        "id": id,
+        "state": state.name(),
+        "path": path.map(|path| path.display().to_string()),
+    }));
+}
+
+/// Writes a log message at `level`, if the configured level lets it through.
+pub(crate) fn log(level: Level, message: fmt::Arguments) {
+    let reporter = reporter();
+    if level > reporter.level {
+        return;
+    }
+    match reporter.render {
+        Render::Json => reporter.event(json!({
+            "event": "log",
+            "level": level.name(),
+            "message": message.to_string(),
+        })),
+        _ => reporter.write_line(&message.to_string()),
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::progress::log($crate::progress::Level::Error, format_args!($($arg)*)) };
+}
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::progress::log($crate::progress::Level::Warn, format_args!($($arg)*)) };
+}
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::progress::log($crate::progress::Level::Info, format_args!($($arg)*)) };
+}
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::progress::log($crate::progress::Level::Debug, format_args!($($arg)*)) };
+}
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::progress::log($crate::progress::Level::Trace, format_args!($($arg)*)) };
+}
+pub(crate) use {debug, error, info, trace, warning};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(bytes: u64, total_bytes: Option<u64>, elapsed_secs: u64) -> Snapshot {
+        Snapshot {
+            bytes,
+            total_bytes,
+            words: 1234,
+            elapsed: Duration::from_secs(elapsed_secs),
+            workers: vec![
+                WorkerState::Counting,
+                WorkerState::Done,
+                WorkerState::Counting,
+            ],
+        }
+    }
+
+    mod test_level {
+        use super::*;
+
+        #[test]
+        fn test_flags_select_level() {
+            assert_eq!(Level::from_flags(false, 0), Level::Info);
+            assert_eq!(Level::from_flags(false, 1), Level::Debug);
+            assert_eq!(Level::from_flags(false, 5), Level::Trace);
+            assert_eq!(Level::from_flags(true, 0), Level::Error);
+            assert!(Level::Warn < Level::Info);
+        }
+    }
+
+    mod test_snapshot {
+        use super::*;
+
+        #[test]
+        fn test_eta_extrapolates_average_rate() {
+            // Given: a quarter done in 10 seconds.
+            let snapshot = snapshot(256, Some(1024), 10);
+
+            // Then
+            assert_eq!(snapshot.eta(), Some(Duration::from_secs(30)));
+            assert_eq!(self::snapshot(0, Some(1024), 10).eta(), None);
+            assert_eq!(self::snapshot(256, None, 10).eta(), None);
+        }
+
+        #[test]
+        fn test_bar_line_shows_fraction_workers_and_eta() {
+            // Given
+            let snapshot = snapshot(512 * 1024 * 1024, Some(2048 * 1024 * 1024), 75);
+
+            // Then
+            assert_eq!(
+                snapshot.bar_line(),
+                "[######..................]  25%  512.0/2048.0 MiB  1234 words  2/3 busy  ETA 3:45"
+            );
+        }
+
+        #[test]
+        fn test_bar_line_without_total_shows_elapsed() {
+            // Given
+            let snapshot = snapshot(3 * 1024 * 1024 / 2, None, 3725);
+
+            // Then
+            assert_eq!(
+                snapshot.bar_line(),
+                "1.5 MiB  1234 words  2/3 busy  1:02:05 elapsed"
+            );
+        }
+
+        #[test]
+        fn test_json_progress_event() {
+            // Given
+            let snapshot = snapshot(256, Some(1024), 10);
+
+            // Then
+            assert_eq!(
+                snapshot.json(),
+                json!({
+                    "event": "progress",
+                    "bytes": 256,
+                    "total_bytes": 1024,
+                    "words": 1234,
+                    "elapsed_ms": 10_000,
+                    "eta_ms": 30_000,
+                    "workers": ["counting", "done", "counting"],
+                })
+            );
+        }
+    }
+}
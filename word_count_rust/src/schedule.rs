@@ -0,0 +1,619 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use crate::decompress::{detect_file_compression, Compression};
+use crate::encoding::detect_file_utf16;
+use crate::error::{at_path, WordCountError};
+use crate::merge::merge_counts;
+use crate::ngram::{self, Segment};
+use crate::progress::{self, debug, WorkerState};
+use crate::{
+    count_file, count_words, mapped, read_file_chunk, Config, FileChunk, Pipeline, STDIN_PATH,
+};
+
+/// Smallest chunk handed out as the input runs out, so the queue is not hammered at the end.
+const MIN_CHUNK_SIZE: u64 = 64 * 1024;
+/// Largest chunk handed out at the start of a run.
+const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+/// Each chunk is this fraction of what is left per worker, so chunks shrink as the run goes on.
+const CHUNKS_PER_THREAD: u64 = 4;
+
+/// One piece of work: a line-aligned chunk of a regular file, or an input that can only be
+/// counted as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Unit {
+    Chunk { file: usize, chunk: FileChunk },
+    Whole { file: usize },
+}
+
+/// The shared queue workers take their next unit from.
+///
+/// Whole inputs come first, since nothing is known about their size; then regular files,
+/// largest first, are cut into chunks on demand. Unless `--chunk-size` fixes it, a chunk is a
+/// quarter of what is left per worker, clamped to 64 KiB..16 MiB: big chunks keep the queue
+/// quiet at the start, small ones let every worker finish at about the same time. Chunks end
+/// after a newline, so a very long line only delays the worker that takes it.
+struct WorkQueue<'a> {
+    paths: &'a [PathBuf],
+    num_threads: u64,
+    chunk_size: Option<u64>,
+    state: Mutex<QueueState>,
+}
+
+struct QueueState {
+    whole: VecDeque<usize>,
+    /// Files still to be cut, with the range of each that is counted.
+    files: VecDeque<(usize, FileChunk)>,
+    /// Start of the next chunk of the front file.
+    pos: u64,
+    /// Reader of the front file, used to find line ends.
+    reader: Option<BufReader<File>>,
+    /// Bytes of all files not handed out yet.
+    remaining: u64,
+}
+
+impl<'a> WorkQueue<'a> {
+    fn new(
+        paths: &'a [PathBuf],
+        ranges: &[Option<FileChunk>],
+        config: &Config,
+    ) -> io::Result<Self> {
+        let mut whole = VecDeque::new();
+        let mut files = Vec::new();
+        for (file, path) in paths.iter().enumerate() {
+            if let Some(range) = ranges[file] {
+                files.push((file, range));
+                continue;
+            }
+            match chunkable_size(path, config).map_err(at_path(path))? {
+                Some(size) => files.push((
+                    file,
+                    FileChunk {
+                        start: 0,
+                        end: size,
+                    },
+                )),
+                None => whole.push_back(file),
+            }
+        }
+        files.sort_by_key(|&(_, range)| std::cmp::Reverse(range.end - range.start));
+        let remaining = files
+            .iter()
+            .map(|&(_, range)| range.end - range.start)
+            .sum();
+        let pos = files.first().map_or(0, |&(_, range)| range.start);
+        Ok(WorkQueue {
+            paths,
+            num_threads: config.num_threads.max(1) as u64,
+            chunk_size: config.chunk_size.map(|size| size as u64),
+            state: Mutex::new(QueueState {
+                whole,
+                files: files.into(),
+                pos,
+                reader: None,
+                remaining,
+            }),
+        })
+    }
+
+    /// Threads each whole unit may count on: the workers no chunk will keep busy, shared among
+    /// the whole units. The worker that takes a whole unit always counts it.
+    fn whole_threads(&self) -> io::Result<usize> {
+        let state = self.lock()?;
+        if !state.files.is_empty() || state.whole.is_empty() {
+            return Ok(1);
+        }
+        Ok((self.num_threads as usize / state.whole.len()).max(1))
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, QueueState>> {
+        // Only a worker that panicked while cutting a chunk can poison the lock.
+        self.state.lock().map_err(|_| {
+            WordCountError::WorkerPanic {
+                worker: "Another thread".to_string(),
+                message: "it held the work queue".to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn next(&self) -> io::Result<Option<Unit>> {
+        let mut state = self.lock()?;
+        if let Some(file) = state.whole.pop_front() {
+            return Ok(Some(Unit::Whole { file }));
+        }
+        while let Some(&(file, range)) = state.files.front() {
+            if state.pos >= range.end {
+                state.files.pop_front();
+                state.pos = state.files.front().map_or(0, |&(_, range)| range.start);
+                state.reader = None;
+                continue;
+            }
+            let target = self.chunk_size.unwrap_or_else(|| {
+                (state.remaining / (self.num_threads * CHUNKS_PER_THREAD))
+                    .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+            });
+            let start = state.pos;
+            let mut end = start.saturating_add(target);
+            if end < range.end {
+                let path = &self.paths[file];
+                let reader = match &mut state.reader {
+                    Some(reader) => reader,
+                    reader => {
+                        reader.insert(BufReader::new(File::open(path).map_err(at_path(path))?))
+                    }
+                };
+                // Split on raw bytes: finding the line end must not depend on the text being UTF-8.
+                end = line_end(reader, end).map_err(at_path(path))?;
+            }
+            let end = end.min(range.end);
+            state.pos = end;
+            state.remaining -= end - start;
+            return Ok(Some(Unit::Chunk {
+                file,
+                chunk: FileChunk { start, end },
+            }));
+        }
+        Ok(None)
+    }
+
+    /// The next chunk when it belongs to `file`; any other unit is left in `other`.
+    fn next_of(&self, file: usize, other: &mut Option<Unit>) -> io::Result<Option<FileChunk>> {
+        match self.next()? {
+            Some(Unit::Chunk { file: f, chunk }) if f == file => Ok(Some(chunk)),
+            unit => {
+                *other = unit;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// What one worker counted: the counts of every file it worked on and, with `--ngrams`, the
+/// edges of every chunk it counted, by file and offset.
+#[derive(Default)]
+struct Counted {
+    counts: Vec<(usize, HashMap<String, usize>)>,
+    edges: Vec<(usize, u64, Segment)>,
+}
+
+/// Counts every input on one pool of `config.num_threads` workers and returns the counts of
+/// each input, in the order given.
+///
+/// Workers take units from a [`WorkQueue`] until it is empty, so a slow chunk or a big file no
+/// longer holds up the others. Stdin, pipes, compressed files, exported chunks and field
+/// extraction are counted as whole units by `count_file`; they only get more than the thread
+/// of the worker that takes them when there are no chunks to keep the other workers busy.
+pub(crate) fn count_files(
+    paths: &[PathBuf],
+    config: &Config,
+) -> io::Result<Vec<HashMap<String, usize>>> {
+    count_file_ranges(paths, &vec![None; paths.len()], config)
+}
+
+/// Like [`count_files`], counting only `ranges[i]` of input `i` when it is given. A range must
+/// be of a regular uncompressed file and end after a newline or at the end of the file.
+pub(crate) fn count_file_ranges(
+    paths: &[PathBuf],
+    ranges: &[Option<FileChunk>],
+    config: &Config,
+) -> io::Result<Vec<HashMap<String, usize>>> {
+    let queue = WorkQueue::new(paths, ranges, config)?;
+    let whole_config = Config {
+        num_threads: queue.whole_threads()?,
+        ..config.clone()
+    };
+
+    let worker_results = thread::scope(|scope| {
+        let handles: Vec<_> = (0..config.num_threads.max(1))
+            .map(|i| {
+                let (queue, whole_config) = (&queue, &whole_config);
+                scope.spawn(move || run_worker(i, queue, config, whole_config))
+            })
+            .collect();
+
+        let mut worker_results = Vec::with_capacity(handles.len());
+        for (i, handle) in handles.into_iter().enumerate() {
+            worker_results.push(
+                handle
+                    .join()
+                    .map_err(|e| WordCountError::worker_panic(format!("Thread {}", i), e))??,
+            );
+        }
+        Ok::<_, io::Error>(worker_results)
+    })?;
+
+    debug!("All threads finished, merging results");
+    let mut file_counts = vec![HashMap::new(); paths.len()];
+    let mut file_edges = vec![Vec::new(); paths.len()];
+    for counted in worker_results {
+        for (file, counts) in counted.counts {
+            merge_counts(&mut file_counts[file], counts);
+        }
+        for (file, offset, segment) in counted.edges {
+            file_edges[file].push((offset, segment));
+        }
+    }
+    if let Some(ngrams) = &config.ngrams {
+        for (counts, edges) in file_counts.iter_mut().zip(file_edges) {
+            ngram::join_edges(counts, edges, ngrams.n);
+        }
+    }
+    Ok(file_counts)
+}
+
+/// Takes units until the queue is empty and returns what it counted.
+///
+/// Chunks of the same file that follow each other are counted into one map and, with the
+/// mmap pipeline or `--ngrams`, through one mapping.
+fn run_worker(
+    thread_id: usize,
+    queue: &WorkQueue,
+    config: &Config,
+    whole_config: &Config,
+) -> io::Result<Counted> {
+    debug!("Thread {} started", thread_id);
+    let start = Instant::now();
+    let (mut num_units, mut bytes) = (0, 0);
+    let mut counted = Counted::default();
+
+    let mut unit = queue.next()?;
+    while let Some(current) = unit.take() {
+        let (file, chunk) = match current {
+            Unit::Whole { file } => {
+                num_units += 1;
+                progress::worker(thread_id, WorkerState::Counting, Some(&queue.paths[file]));
+                let path = &queue.paths[file];
+                let counts = count_file(path, whole_config).map_err(at_path(path))?;
+                counted.counts.push((file, counts));
+                unit = queue.next()?;
+                continue;
+            }
+            Unit::Chunk { file, chunk } => (file, chunk),
+        };
+        let path = &queue.paths[file];
+        progress::worker(thread_id, WorkerState::Counting, Some(path));
+        let mut first = Some(chunk);
+        let next_chunk = || -> io::Result<Option<FileChunk>> {
+            let chunk = match first.take() {
+                Some(chunk) => Some(chunk),
+                None => queue.next_of(file, &mut unit)?,
+            };
+            if let Some(chunk) = chunk {
+                num_units += 1;
+                bytes += chunk.end - chunk.start;
+            }
+            Ok(chunk)
+        };
+        let counts = match (&config.ngrams, config.pipeline) {
+            (Some(ngrams), _) => {
+                let (counts, edges) =
+                    ngram::count_chunks(path, next_chunk, config, ngrams).map_err(at_path(path))?;
+                let edges = edges
+                    .into_iter()
+                    .map(|(offset, segment)| (file, offset, segment));
+                counted.edges.extend(edges);
+                counts
+            }
+            (None, Pipeline::Mmap) => {
+                mapped::count_chunks(path, next_chunk, config).map_err(at_path(path))?
+            }
+            (None, Pipeline::Lines) => {
+                count_chunk_lines(path, next_chunk, config, thread_id).map_err(at_path(path))?
+            }
+        };
+        counted.counts.push((file, counts));
+    }
+
+    progress::worker(thread_id, WorkerState::Done, None);
+    debug!(
+        "Thread {} finished: {} units, {} bytes in {} ms",
+        thread_id,
+        num_units,
+        bytes,
+        start.elapsed().as_millis()
+    );
+    Ok(counted)
+}
+
+/// Counts the words of the chunks of `path` that `next_chunk` hands out, reading each into
+/// lines first.
+fn count_chunk_lines(
+    path: &Path,
+    mut next_chunk: impl FnMut() -> io::Result<Option<FileChunk>>,
+    config: &Config,
+    thread_id: usize,
+) -> io::Result<HashMap<String, usize>> {
+    let mut word_count = HashMap::new();
+    while let Some(chunk) = next_chunk()? {
+        let lines = read_file_chunk(
+            path,
+            &chunk,
+            config.buffer_size,
+            config.invalid_utf8,
+            &config.invalid_lines,
+        )?;
+        let chunk_count = count_words(
+            &lines,
+            config.tokenizer.as_ref(),
+            &config.normalizer,
+            thread_id,
+        );
+        merge_counts(&mut word_count, chunk_count);
+    }
+    Ok(word_count)
+}
+
+/// The position just after the first newline at or after `pos`, or the end of the file.
+fn line_end(reader: &mut BufReader<File>, pos: u64) -> io::Result<u64> {
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_until(b'\n', &mut Vec::new())?;
+    reader.stream_position()
+}
+
+/// The size of `path` when it can be cut into chunks for the queue.
+pub(crate) fn chunkable_size(path: &Path, config: &Config) -> io::Result<Option<u64>> {
+    if path == Path::new(STDIN_PATH) || config.chunk_dir.is_some() || config.fields.is_some() {
+        return Ok(None);
+    }
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file()
+        || detect_file_compression(path)? != Compression::None
+        || detect_file_utf16(path)?
+    {
+        return Ok(None);
+    }
+    Ok(Some(metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn write_files(temp_dir: &TempDir, files: &[(&str, String)]) -> Vec<PathBuf> {
+        files
+            .iter()
+            .map(|(name, content)| {
+                let path = temp_dir.path().join(name);
+                fs::write(&path, content).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn drain(queue: &WorkQueue) -> Vec<Unit> {
+        std::iter::from_fn(|| queue.next().unwrap()).collect()
+    }
+
+    mod test_work_queue {
+        use super::*;
+
+        #[test]
+        fn test_queue_cuts_line_aligned_chunks_largest_file_first() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let paths = write_files(
+                &temp_dir,
+                &[
+                    ("small.txt", "a b c\n".to_string()),
+                    ("big.txt", "0123456789\n".repeat(10)),
+                    ("empty.txt", String::new()),
+                ],
+            );
+            let config = Config {
+                chunk_size: Some(25),
+                ..Config::default()
+            };
+
+            // When
+            let units = drain(&WorkQueue::new(&paths, &vec![None; paths.len()], &config).unwrap());
+
+            // Then: every chunk runs on to the end of the line it stops in.
+            let chunk = |file, start, end| Unit::Chunk {
+                file,
+                chunk: FileChunk { start, end },
+            };
+            assert_eq!(
+                units,
+                [
+                    chunk(1, 0, 33),
+                    chunk(1, 33, 66),
+                    chunk(1, 66, 99),
+                    chunk(1, 99, 110),
+                    chunk(0, 0, 6),
+                ]
+            );
+        }
+
+        #[test]
+        fn test_queue_shrinks_chunks_as_input_runs_out() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let line = "word ".repeat(20) + "\n";
+            let paths = write_files(&temp_dir, &[("big.txt", line.repeat(40_000))]);
+            let config = Config {
+                num_threads: 2,
+                ..Config::default()
+            };
+
+            // When
+            let units = drain(&WorkQueue::new(&paths, &vec![None; paths.len()], &config).unwrap());
+
+            // Then
+            let sizes: Vec<u64> = units
+                .iter()
+                .map(|unit| match unit {
+                    Unit::Chunk { chunk, .. } => chunk.end - chunk.start,
+                    Unit::Whole { .. } => panic!("regular file counted whole"),
+                })
+                .collect();
+            assert_eq!(sizes.iter().sum::<u64>(), 40_000 * 101);
+            assert!(sizes[0] > 4 * sizes[sizes.len() - 2], "{:?}", sizes);
+            assert!(
+                sizes[..sizes.len() - 1]
+                    .iter()
+                    .all(|&size| size >= MIN_CHUNK_SIZE),
+                "{:?}",
+                sizes
+            );
+        }
+
+        #[test]
+        fn test_queue_keeps_stdin_and_compressed_files_whole() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let gz = temp_dir.path().join("input.txt.gz");
+            let mut encoder =
+                flate2::write::GzEncoder::new(File::create(&gz).unwrap(), Default::default());
+            encoder.write_all(b"a b\n").unwrap();
+            encoder.finish().unwrap();
+            let paths = [gz, PathBuf::from("-")];
+
+            // When
+            let units = drain(
+                &WorkQueue::new(&paths, &vec![None; paths.len()], &Config::default()).unwrap(),
+            );
+
+            // Then
+            assert_eq!(units, [Unit::Whole { file: 0 }, Unit::Whole { file: 1 }]);
+        }
+
+        #[test]
+        fn test_whole_units_only_get_the_threads_chunks_leave_idle() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let paths = write_files(&temp_dir, &[("a.txt", "a b\n".to_string())]);
+            let config = Config {
+                num_threads: 8,
+                ..Config::default()
+            };
+            let stdin = PathBuf::from("-");
+            let mixed = [stdin.clone(), stdin.clone(), paths[0].clone()];
+            let whole = [stdin.clone(), stdin];
+
+            // When
+            let with_chunks = WorkQueue::new(&mixed, &[None; 3], &config).unwrap();
+            let whole_only = WorkQueue::new(&whole, &[None; 2], &config).unwrap();
+
+            // Then
+            assert_eq!(with_chunks.whole_threads().unwrap(), 1);
+            assert_eq!(whole_only.whole_threads().unwrap(), 4);
+        }
+    }
+
+    mod test_count_files {
+        use super::*;
+
+        #[test]
+        fn test_count_files_matches_count_file_per_input() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let paths = write_files(
+                &temp_dir,
+                &[
+                    ("a.txt", "Word0 shared, words!\n".repeat(5000)),
+                    ("b.txt", "Word1 shared\n".repeat(3)),
+                    ("c.txt", "no trailing newline".to_string()),
+                    ("empty.txt", String::new()),
+                ],
+            );
+
+            for pipeline in [Pipeline::Mmap, Pipeline::Lines] {
+                for (num_threads, chunk_size) in [(1, None), (3, Some(100)), (8, Some(1))] {
+                    let config = Config {
+                        num_threads,
+                        pipeline,
+                        chunk_size,
+                        ..Config::default()
+                    };
+
+                    // When
+                    let result = count_files(&paths, &config).unwrap();
+
+                    // Then
+                    let expected: Vec<_> = paths
+                        .iter()
+                        .map(|path| count_file(path, &config).unwrap())
+                        .collect();
+                    assert_eq!(
+                        result, expected,
+                        "{:?} with {} threads",
+                        pipeline, num_threads
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_count_files_isolates_very_long_line() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let content = format!("short line\n{}\nshort line\n", "long ".repeat(10_000));
+            let paths = write_files(&temp_dir, &[("input.txt", content)]);
+            let config = Config {
+                num_threads: 4,
+                chunk_size: Some(16),
+                ..Config::default()
+            };
+
+            // When
+            let result = count_files(&paths, &config).unwrap();
+
+            // Then
+            assert_eq!(result[0]["long"], 10_000);
+            assert_eq!(result[0]["short"], 2);
+            assert_eq!(result[0]["line"], 2);
+        }
+
+        #[test]
+        fn test_count_file_ranges_counts_only_the_range() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let paths = write_files(
+                &temp_dir,
+                &[
+                    ("old.txt", "old line\nnew line\nnewer line\n".to_string()),
+                    ("whole.txt", "whole line\n".to_string()),
+                ],
+            );
+            let ranges = [Some(FileChunk { start: 9, end: 18 }), None];
+
+            for pipeline in [Pipeline::Mmap, Pipeline::Lines] {
+                let config = Config {
+                    num_threads: 2,
+                    chunk_size: Some(1),
+                    pipeline,
+                    ..Config::default()
+                };
+
+                // When
+                let result = count_file_ranges(&paths, &ranges, &config).unwrap();
+
+                // Then
+                assert_eq!(result[0].len(), 2, "{:?}", pipeline);
+                assert_eq!(result[0]["new"], 1);
+                assert_eq!(result[0]["line"], 1);
+                assert_eq!(result[1]["whole"], 1);
+            }
+        }
+
+        #[test]
+        fn test_count_files_propagates_missing_file() {
+            // When
+            let result = count_files(
+                &[PathBuf::from("/nonexistent/file.txt")],
+                &Config::default(),
+            );
+
+            // Then
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        }
+    }
+}
@@ -0,0 +1,663 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::WordCountError;
+
+/// Largest Count-Min Sketch built, in bytes. Every worker builds its own, so the bound keeps a
+/// tiny epsilon from asking for more memory than any machine has.
+const MAX_CMS_BYTES: usize = 1 << 30;
+
+/// Hashes a word the same way on every platform and in every run, so sketches saved by one run
+/// can be merged into another: FNV-1a followed by the splitmix64 finalizer, which spreads the
+/// FNV output over all 64 bits.
+pub(crate) fn hash_word(word: &str) -> u64 {
+    hash_bytes(word.as_bytes())
+}
+
+/// `hash_word` for any bytes.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    mix(hash)
+}
+
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn corrupt(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt sketch: {}", what),
+    )
+}
+
+fn incompatible(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("cannot merge sketches: {} differ", what),
+    )
+}
+
+/// Estimates the number of distinct words in a fixed number of registers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub(crate) const MIN_PRECISION: u8 = 4;
+    pub(crate) const MAX_PRECISION: u8 = 18;
+
+    /// The smallest sketch whose standard error, 1.04 / sqrt(registers), is at most
+    /// `relative_error`.
+    pub(crate) fn with_error(relative_error: f64) -> Self {
+        let registers = (1.04 / relative_error).powi(2);
+        let precision = registers.log2().ceil() as u8;
+        Self::with_precision(precision.clamp(Self::MIN_PRECISION, Self::MAX_PRECISION))
+    }
+
+    pub(crate) fn with_precision(precision: u8) -> Self {
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Standard error of the estimate.
+    pub(crate) fn relative_error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+
+    pub(crate) fn add(&mut self, hash: u64) {
+        let index = (hash >> (64 - self.precision)) as usize;
+        // The guard bit caps the rank when the remaining bits are all zero.
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub(crate) fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate while many registers are still empty.
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// Fails with `InvalidData` unless a loaded sketch has a register for every index.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if !(Self::MIN_PRECISION..=Self::MAX_PRECISION).contains(&self.precision) {
+            return Err(corrupt("HyperLogLog precision out of range"));
+        }
+        if self.registers.len() != 1 << self.precision {
+            return Err(corrupt("HyperLogLog registers do not match its precision"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn merge(&mut self, other: &HyperLogLog) -> io::Result<()> {
+        if self.precision != other.precision {
+            return Err(incompatible("HyperLogLog precisions"));
+        }
+        for (register, &theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(theirs);
+        }
+        Ok(())
+    }
+}
+
+/// Estimates word frequencies in `depth` rows of `width` counters. An estimate never falls
+/// below the true count and, with probability `1 - delta`, exceeds it by at most
+/// `epsilon * total`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+}
+
+impl CountMinSketch {
+    /// Fails with a configuration error when the sketch would take more than 1 GiB.
+    pub(crate) fn with_error(epsilon: f64, delta: f64) -> io::Result<Self> {
+        // Float to int casts saturate, so an absurd bound ends up as usize::MAX and fails below.
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil().max(1.0) as usize;
+        let size = width
+            .checked_mul(depth)
+            .filter(|&cells| cells.saturating_mul(8) <= MAX_CMS_BYTES)
+            .ok_or_else(|| {
+                WordCountError::Config(format!(
+                    "a Count-Min Sketch with epsilon {:e} and delta {:e} would take more than 1 GiB \
+                     per worker; raise --cms-epsilon or --cms-delta",
+                    epsilon, delta
+                ))
+            })?;
+        Ok(CountMinSketch {
+            width,
+            depth,
+            counters: vec![0; size],
+        })
+    }
+
+    /// Counter index in each row, by double hashing.
+    fn cells(&self, hash: u64) -> impl Iterator<Item = usize> + '_ {
+        let h1 = hash;
+        let h2 = mix(hash ^ 0x9e37_79b9_7f4a_7c15) | 1;
+        (0..self.depth).map(move |row| {
+            let column = h1.wrapping_add((row as u64).wrapping_mul(h2)) % self.width as u64;
+            row * self.width + column as usize
+        })
+    }
+
+    pub(crate) fn add(&mut self, hash: u64, count: u64) {
+        for cell in self.cells(hash).collect::<Vec<_>>() {
+            self.counters[cell] += count;
+        }
+    }
+
+    pub(crate) fn estimate(&self, hash: u64) -> u64 {
+        self.cells(hash)
+            .map(|cell| self.counters[cell])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Fails with `InvalidData` unless a loaded sketch has `width * depth` counters.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        let cells = self
+            .width
+            .checked_mul(self.depth)
+            .filter(|&cells| cells > 0);
+        if cells != Some(self.counters.len()) {
+            return Err(corrupt(
+                "Count-Min Sketch counters do not match its dimensions",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn merge(&mut self, other: &CountMinSketch) -> io::Result<()> {
+        if (self.width, self.depth) != (other.width, other.depth) {
+            return Err(incompatible("Count-Min Sketch dimensions"));
+        }
+        for (counter, &theirs) in self.counters.iter_mut().zip(&other.counters) {
+            *counter += theirs;
+        }
+        Ok(())
+    }
+}
+
+/// A word tracked by `SpaceSaving`: its count is an overestimate by at most `error`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Counter {
+    pub(crate) word: String,
+    pub(crate) count: u64,
+    pub(crate) error: u64,
+}
+
+/// Tracks the heavy hitters of a stream in `capacity` counters.
+///
+/// Every word occurring more than `total / capacity` times is guaranteed to be tracked. When
+/// all counters are taken, a new word evicts the one with the smallest count and inherits that
+/// count as its error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "SavedSpaceSaving", from = "SavedSpaceSaving")]
+pub(crate) struct SpaceSaving {
+    capacity: usize,
+    counters: Vec<Counter>,
+    index: HashMap<String, usize>,
+    /// `(count, counter index)`, so the first element is the counter to evict.
+    by_count: BTreeSet<(u64, usize)>,
+}
+
+impl SpaceSaving {
+    pub(crate) fn new(capacity: usize) -> Self {
+        SpaceSaving {
+            capacity,
+            counters: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, word: &str, count: u64) {
+        if let Some(&i) = self.index.get(word) {
+            self.set_count(i, self.counters[i].count + count);
+        } else if self.counters.len() < self.capacity {
+            self.insert(word.to_string(), count, 0);
+        } else if let Some(&(min, i)) = self.by_count.first() {
+            let evicted = std::mem::replace(&mut self.counters[i].word, word.to_string());
+            self.index.remove(&evicted);
+            self.index.insert(word.to_string(), i);
+            self.counters[i].error = min;
+            self.set_count(i, min + count);
+        }
+    }
+
+    fn insert(&mut self, word: String, count: u64, error: u64) {
+        let i = self.counters.len();
+        self.index.insert(word.clone(), i);
+        self.counters.push(Counter { word, count, error });
+        self.by_count.insert((count, i));
+    }
+
+    fn set_count(&mut self, i: usize, count: u64) {
+        self.by_count.remove(&(self.counters[i].count, i));
+        self.counters[i].count = count;
+        self.by_count.insert((count, i));
+    }
+
+    /// The count a word not in the summary may have had: zero until every counter is taken.
+    fn floor(&self) -> u64 {
+        if self.counters.len() < self.capacity {
+            0
+        } else {
+            self.by_count.first().map_or(0, |&(min, _)| min)
+        }
+    }
+
+    /// Fails with `InvalidData` unless a loaded summary has at most `capacity` counters, each
+    /// for a different word.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.counters.len() > self.capacity {
+            return Err(corrupt("more Space-Saving counters than its capacity"));
+        }
+        if self.index.len() != self.counters.len() {
+            return Err(corrupt("Space-Saving counters repeat a word"));
+        }
+        Ok(())
+    }
+
+    /// Combines two summaries so that the result keeps the Space-Saving guarantees for the
+    /// concatenation of both streams. A word missing from one side is charged that side's
+    /// floor, as both count and error, before the `capacity` largest counters are kept.
+    pub(crate) fn merge(&mut self, other: &SpaceSaving) -> io::Result<()> {
+        if self.capacity != other.capacity {
+            return Err(incompatible("Space-Saving capacities"));
+        }
+        let (own_floor, other_floor) = (self.floor(), other.floor());
+        let mut combined: HashMap<&str, (u64, u64)> = HashMap::new();
+        for counter in &self.counters {
+            let (count, error) = other
+                .get(&counter.word)
+                .map_or((other_floor, other_floor), |c| (c.count, c.error));
+            combined.insert(
+                &counter.word,
+                (counter.count + count, counter.error + error),
+            );
+        }
+        for counter in &other.counters {
+            combined
+                .entry(&counter.word)
+                .or_insert((counter.count + own_floor, counter.error + own_floor));
+        }
+
+        let mut merged: Vec<Counter> = combined
+            .into_iter()
+            .map(|(word, (count, error))| Counter {
+                word: word.to_string(),
+                count,
+                error,
+            })
+            .collect();
+        merged.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.word.cmp(&b.word)));
+        merged.truncate(self.capacity);
+
+        let mut result = SpaceSaving::new(self.capacity);
+        for counter in merged {
+            result.insert(counter.word, counter.count, counter.error);
+        }
+        *self = result;
+        Ok(())
+    }
+
+    fn get(&self, word: &str) -> Option<&Counter> {
+        self.index.get(word).map(|&i| &self.counters[i])
+    }
+
+    /// The tracked words, in no particular order.
+    pub(crate) fn counters(&self) -> &[Counter] {
+        &self.counters
+    }
+}
+
+/// How `SpaceSaving` is saved: only the counters, the indexes are rebuilt on load.
+#[derive(Serialize, Deserialize)]
+struct SavedSpaceSaving {
+    capacity: usize,
+    counters: Vec<Counter>,
+}
+
+impl From<SpaceSaving> for SavedSpaceSaving {
+    fn from(summary: SpaceSaving) -> Self {
+        SavedSpaceSaving {
+            capacity: summary.capacity,
+            counters: summary.counters,
+        }
+    }
+}
+
+/// Every saved counter is kept, and nothing is allocated for the capacity, so that `check` can
+/// reject a file with too many counters or an absurd capacity.
+impl From<SavedSpaceSaving> for SpaceSaving {
+    fn from(saved: SavedSpaceSaving) -> Self {
+        let mut summary = SpaceSaving {
+            capacity: saved.capacity,
+            counters: Vec::with_capacity(saved.counters.len()),
+            index: HashMap::with_capacity(saved.counters.len()),
+            by_count: BTreeSet::new(),
+        };
+        for counter in saved.counters {
+            summary.insert(counter.word, counter.count, counter.error);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A skewed stream: word `i` occurs `1000 / (i + 1)` times.
+    fn zipf_stream(distinct: usize) -> Vec<String> {
+        (0..distinct)
+            .flat_map(|i| std::iter::repeat_n(format!("w{}", i), 1000 / (i + 1)))
+            .collect()
+    }
+
+    fn exact_counts(words: &[String]) -> HashMap<&str, u64> {
+        let mut counts = HashMap::new();
+        for word in words {
+            *counts.entry(word.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    mod test_hash_word {
+        use super::*;
+
+        #[test]
+        fn test_hash_word_is_stable() {
+            // The value must never change, or saved sketches stop merging with new ones.
+            assert_eq!(hash_word(""), mix(0xcbf2_9ce4_8422_2325));
+            assert_eq!(hash_word("word"), hash_word("word"));
+            assert_ne!(hash_word("word"), hash_word("Word"));
+        }
+    }
+
+    mod test_hyperloglog {
+        use super::*;
+
+        #[test]
+        fn test_hyperloglog_precision_follows_error() {
+            assert_eq!(HyperLogLog::with_error(0.01).precision, 14);
+            assert_eq!(HyperLogLog::with_error(0.5).precision, 4);
+            assert_eq!(HyperLogLog::with_error(0.0001).precision, 18);
+        }
+
+        #[test]
+        fn test_hyperloglog_estimate_is_within_error() {
+            for distinct in [10, 1_000, 100_000] {
+                // Given
+                let mut hll = HyperLogLog::with_error(0.01);
+
+                // When
+                for i in 0..distinct {
+                    let hash = hash_word(&format!("word{}", i));
+                    hll.add(hash);
+                    hll.add(hash);
+                }
+
+                // Then
+                let error = (hll.estimate() - distinct as f64).abs() / distinct as f64;
+                assert!(
+                    error < 3.0 * hll.relative_error(),
+                    "{} for {}",
+                    error,
+                    distinct
+                );
+            }
+        }
+
+        #[test]
+        fn test_hyperloglog_merge_equals_union() {
+            // Given
+            let (mut left, mut right, mut union) = (
+                HyperLogLog::with_precision(10),
+                HyperLogLog::with_precision(10),
+                HyperLogLog::with_precision(10),
+            );
+            for i in 0..5_000 {
+                let hash = hash_word(&i.to_string());
+                if i % 3 == 0 {
+                    left.add(hash);
+                } else {
+                    right.add(hash);
+                }
+                union.add(hash);
+            }
+
+            // When
+            left.merge(&right).unwrap();
+
+            // Then
+            assert_eq!(left, union);
+        }
+
+        #[test]
+        fn test_hyperloglog_rejects_other_precision() {
+            // When
+            let err = HyperLogLog::with_precision(10)
+                .merge(&HyperLogLog::with_precision(11))
+                .unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    mod test_count_min_sketch {
+        use super::*;
+
+        #[test]
+        fn test_count_min_sketch_dimensions_follow_error() {
+            // When
+            let cms = CountMinSketch::with_error(0.001, 0.01).unwrap();
+
+            // Then
+            assert_eq!((cms.width, cms.depth), (2719, 5));
+        }
+
+        #[test]
+        fn test_count_min_sketch_rejects_oversized_bounds() {
+            for (epsilon, delta) in [(1e-300, 0.01), (1e-9, 0.01), (1e-6, 1e-300)] {
+                // When
+                let err = CountMinSketch::with_error(epsilon, delta).unwrap_err();
+
+                // Then
+                assert_eq!(WordCountError::from(err).exit_code(), 2);
+            }
+        }
+
+        #[test]
+        fn test_count_min_sketch_never_underestimates_and_stays_within_bound() {
+            // Given
+            let words = zipf_stream(2_000);
+            let epsilon = 0.001;
+            let mut cms = CountMinSketch::with_error(epsilon, 0.01).unwrap();
+
+            // When
+            for word in &words {
+                cms.add(hash_word(word), 1);
+            }
+
+            // Then
+            let bound = (epsilon * words.len() as f64).ceil() as u64;
+            for (word, count) in exact_counts(&words) {
+                let estimate = cms.estimate(hash_word(word));
+                assert!(estimate >= count, "{} underestimated", word);
+                assert!(
+                    estimate - count <= bound,
+                    "{} off by {}",
+                    word,
+                    estimate - count
+                );
+            }
+        }
+
+        #[test]
+        fn test_count_min_sketch_merge_equals_single_sketch() {
+            // Given
+            let words = zipf_stream(300);
+            let (mut left, mut right, mut single) = (
+                CountMinSketch::with_error(0.01, 0.05).unwrap(),
+                CountMinSketch::with_error(0.01, 0.05).unwrap(),
+                CountMinSketch::with_error(0.01, 0.05).unwrap(),
+            );
+            for (i, word) in words.iter().enumerate() {
+                let half = if i % 2 == 0 { &mut left } else { &mut right };
+                half.add(hash_word(word), 1);
+                single.add(hash_word(word), 1);
+            }
+
+            // When
+            left.merge(&right).unwrap();
+
+            // Then
+            assert_eq!(left, single);
+            assert!(left
+                .merge(&CountMinSketch::with_error(0.1, 0.05).unwrap())
+                .is_err());
+        }
+    }
+
+    mod test_space_saving {
+        use super::*;
+
+        fn tracked(summary: &SpaceSaving) -> HashMap<String, u64> {
+            summary
+                .counters()
+                .iter()
+                .map(|c| (c.word.clone(), c.count))
+                .collect()
+        }
+
+        fn check_guarantees(summary: &SpaceSaving, words: &[String]) {
+            let total = words.len() as u64;
+            let capacity = summary.capacity as u64;
+            let tracked = tracked(summary);
+            for (word, count) in exact_counts(words) {
+                match tracked.get(word) {
+                    Some(&estimate) => {
+                        let counter = summary.get(word).unwrap();
+                        assert!(estimate >= count, "{} underestimated", word);
+                        assert!(
+                            estimate - counter.error <= count,
+                            "{} error too small",
+                            word
+                        );
+                    }
+                    None => assert!(count <= total / capacity, "heavy hitter {} missing", word),
+                }
+            }
+        }
+
+        #[test]
+        fn test_space_saving_keeps_heavy_hitters() {
+            // Given
+            let words = zipf_stream(500);
+            let mut summary = SpaceSaving::new(50);
+
+            // When
+            for word in &words {
+                summary.add(word, 1);
+            }
+
+            // Then
+            assert_eq!(summary.counters().len(), 50);
+            check_guarantees(&summary, &words);
+            assert_eq!(tracked(&summary).get("w0"), Some(&1000));
+        }
+
+        #[test]
+        fn test_space_saving_is_exact_below_capacity() {
+            // Given
+            let mut summary = SpaceSaving::new(10);
+
+            // When
+            summary.add("a", 3);
+            summary.add("b", 1);
+            summary.add("a", 2);
+
+            // Then
+            assert_eq!(
+                tracked(&summary),
+                HashMap::from([("a".to_string(), 5), ("b".to_string(), 1)])
+            );
+            assert!(summary.counters().iter().all(|c| c.error == 0));
+        }
+
+        #[test]
+        fn test_space_saving_merge_keeps_guarantees() {
+            // Given
+            let words = zipf_stream(500);
+            let mut parts: Vec<SpaceSaving> = (0..4).map(|_| SpaceSaving::new(50)).collect();
+            for (i, word) in words.iter().enumerate() {
+                parts[(i * 7) % 4].add(word, 1);
+            }
+
+            // When
+            let mut merged = parts.remove(0);
+            for part in &parts {
+                merged.merge(part).unwrap();
+            }
+
+            // Then
+            check_guarantees(&merged, &words);
+            assert_eq!(tracked(&merged).get("w0"), Some(&1000));
+        }
+
+        #[test]
+        fn test_space_saving_survives_serialization() {
+            // Given
+            let mut summary = SpaceSaving::new(3);
+            for word in ["a", "b", "a", "c", "d", "a"] {
+                summary.add(word, 1);
+            }
+
+            // When
+            let json = serde_json::to_string(&summary).unwrap();
+            let mut restored: SpaceSaving = serde_json::from_str(&json).unwrap();
+
+            // Then
+            assert_eq!(tracked(&restored), tracked(&summary));
+            restored.add("e", 1);
+            summary.add("e", 1);
+            assert_eq!(tracked(&restored), tracked(&summary));
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rust_stemmers::{Algorithm, Stemmer as Snowball};
+
+use crate::normalize::{normalize_token, Normalizer};
+use crate::tokenizer::Tokenizer;
+
+const BUNDLED_LEMMAS: &str = include_str!("../data/lemmas_en.txt");
+
+/// Language of the Snowball stemmer, as selected with `--stem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StemLanguage {
+    /// The Snowball English stemmer (Porter2): "running" and "runs" become "run"
+    English,
+}
+
+impl StemLanguage {
+    fn algorithm(self) -> Algorithm {
+        match self {
+            StemLanguage::English => Algorithm::English,
+        }
+    }
+}
+
+/// Maps inflected forms to their lemma by table lookup, without looking at the context.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Lemmatizer {
+    lemmas: HashMap<String, String>,
+}
+
+impl Lemmatizer {
+    /// The bundled table of common English irregular forms.
+    pub(crate) fn bundled(tokenizer: &dyn Tokenizer, normalizer: &Normalizer) -> Self {
+        let mut lemmatizer = Lemmatizer::default();
+        lemmatizer.add_entries(BUNDLED_LEMMAS, tokenizer, normalizer);
+        lemmatizer
+    }
+
+    /// Adds a table with one lemma per line followed by its forms, so two-column
+    /// `lemma<TAB>form` lists work as they are; blank lines and lines starting with `#` are
+    /// skipped. Entries are normalized like the counted words, and when a form is listed under
+    /// two lemmas the first one wins.
+    pub(crate) fn add_entries(
+        &mut self,
+        text: &str,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line
+                .split_whitespace()
+                .map(|field| normalize_token(tokenizer, normalizer, field));
+            let Some(lemma) = fields.next() else {
+                continue;
+            };
+            for form in fields {
+                if !form.is_empty() && form != lemma {
+                    self.lemmas.entry(form).or_insert_with(|| lemma.clone());
+                }
+            }
+        }
+    }
+
+    /// Adds the table file at `path`.
+    pub(crate) fn load(
+        &mut self,
+        path: &Path,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.add_entries(&text, tokenizer, normalizer);
+        Ok(())
+    }
+
+    pub(crate) fn lemma<'a>(&'a self, word: &'a str) -> &'a str {
+        self.lemmas.get(word).map_or(word, String::as_str)
+    }
+}
+
+/// Folds the counts of related words together: each word is looked up in the lemma table,
+/// then reduced to its Snowball stem.
+///
+/// With `surface_forms`, a group is written under its most common word instead of its stem,
+/// ties going to the word that sorts first, so "running: 7, runs: 3" is reported as
+/// "running: 10" rather than "run: 10".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Stemmer {
+    pub(crate) language: Option<StemLanguage>,
+    pub(crate) lemmatizer: Option<Lemmatizer>,
+    pub(crate) surface_forms: bool,
+}
+
+impl Stemmer {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.language.is_some() || self.lemmatizer.is_some()
+    }
+
+    fn snowball(&self) -> Option<Snowball> {
+        self.language
+            .map(|language| Snowball::create(language.algorithm()))
+    }
+
+    fn stem_with(&self, snowball: Option<&Snowball>, word: &str) -> String {
+        let lemma = match &self.lemmatizer {
+            Some(lemmatizer) => lemmatizer.lemma(word),
+            None => word,
+        };
+        match snowball {
+            Some(snowball) => snowball.stem(lemma).into_owned(),
+            None => lemma.to_string(),
+        }
+    }
+
+    /// The stem of a single word.
+    pub(crate) fn stem(&self, word: &str) -> String {
+        self.stem_with(self.snowball().as_ref(), word)
+    }
+
+    /// Groups merged counts by stem. With `ngrams`, every space-separated word of an entry is
+    /// stemmed.
+    pub(crate) fn apply(
+        &self,
+        counts: HashMap<String, usize>,
+        ngrams: bool,
+    ) -> HashMap<String, usize> {
+        if !self.is_enabled() {
+            return counts;
+        }
+        let snowball = self.snowball();
+        let stem_of = |entry: &str| {
+            if ngrams {
+                entry
+                    .split(' ')
+                    .map(|word| self.stem_with(snowball.as_ref(), word))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            } else {
+                self.stem_with(snowball.as_ref(), entry)
+            }
+        };
+
+        let mut stem_counts: HashMap<String, usize> = HashMap::new();
+        // The most common word of every stem and its own count.
+        let mut surface: HashMap<String, (String, usize)> = HashMap::new();
+        for (word, count) in counts {
+            let stem = stem_of(&word);
+            *stem_counts.entry(stem.clone()).or_insert(0) += count;
+            if !self.surface_forms {
+                continue;
+            }
+            match surface.entry(stem) {
+                Entry::Vacant(entry) => {
+                    entry.insert((word, count));
+                }
+                Entry::Occupied(mut entry) => {
+                    let (best, best_count) = entry.get();
+                    if count > *best_count || (count == *best_count && word < *best) {
+                        entry.insert((word, count));
+                    }
+                }
+            }
+        }
+
+        if !self.surface_forms {
+            return stem_counts;
+        }
+        // A word has exactly one stem, so no two groups share a surface form.
+        stem_counts
+            .into_iter()
+            .map(|(stem, count)| (surface.remove(&stem).map_or(stem, |(word, _)| word), count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counts;
+    use crate::tokenizer::WhitespaceTokenizer;
+
+    fn lemmatizer(table: &str) -> Lemmatizer {
+        let mut lemmatizer = Lemmatizer::default();
+        lemmatizer.add_entries(table, &WhitespaceTokenizer, &Normalizer::default());
+        lemmatizer
+    }
+
+    fn english() -> Stemmer {
+        Stemmer {
+            language: Some(StemLanguage::English),
+            ..Stemmer::default()
+        }
+    }
+
+    mod test_lemmatizer {
+        use super::*;
+
+        #[test]
+        fn test_bundled_table_maps_irregular_forms() {
+            // Given
+            let lemmatizer = Lemmatizer::bundled(&WhitespaceTokenizer, &Normalizer::default());
+
+            // Then
+            assert_eq!(lemmatizer.lemma("went"), "go");
+            assert_eq!(lemmatizer.lemma("children"), "child");
+            assert_eq!(lemmatizer.lemma("was"), "be");
+            assert_eq!(lemmatizer.lemma("cat"), "cat");
+        }
+
+        #[test]
+        fn test_table_entries_are_normalized_and_first_lemma_wins() {
+            // When
+            let lemmatizer = lemmatizer("# comment\nMouse\tMice\n\nfoo mice bar\n");
+
+            // Then
+            assert_eq!(lemmatizer.lemma("mice"), "mouse");
+            assert_eq!(lemmatizer.lemma("bar"), "foo");
+            assert_eq!(lemmatizer.lemma("mouse"), "mouse");
+        }
+    }
+
+    mod test_stemmer {
+        use super::*;
+
+        #[test]
+        fn test_snowball_groups_inflections() {
+            // Given
+            let word_count = counts(&[("run", 2), ("runs", 3), ("running", 4), ("cat", 1)]);
+
+            // When
+            let result = english().apply(word_count, false);
+
+            // Then
+            assert_eq!(result, counts(&[("run", 9), ("cat", 1)]));
+        }
+
+        #[test]
+        fn test_lemmas_are_looked_up_before_stemming() {
+            // Given
+            let stemmer = Stemmer {
+                lemmatizer: Some(lemmatizer("run ran\ngo went goes")),
+                ..english()
+            };
+            let word_count = counts(&[("ran", 1), ("running", 1), ("went", 2), ("going", 1)]);
+
+            // When
+            let result = stemmer.apply(word_count, false);
+
+            // Then
+            assert_eq!(result, counts(&[("run", 2), ("go", 3)]));
+            assert_eq!(stemmer.stem("went"), "go");
+        }
+
+        #[test]
+        fn test_surface_forms_use_most_common_word() {
+            // Given
+            let stemmer = Stemmer {
+                surface_forms: true,
+                ..english()
+            };
+            let word_count = counts(&[
+                ("running", 4),
+                ("runs", 3),
+                ("run", 2),
+                ("connection", 1),
+                ("connected", 1),
+            ]);
+
+            // When
+            let result = stemmer.apply(word_count, false);
+
+            // Then: "connected" and "connection" tie, the first in order wins.
+            assert_eq!(result, counts(&[("running", 9), ("connected", 2)]));
+        }
+
+        #[test]
+        fn test_ngrams_are_stemmed_word_by_word() {
+            // Given
+            let stemmer = Stemmer {
+                surface_forms: true,
+                ..english()
+            };
+            let ngram_count = counts(&[("cats running", 1), ("cat runs", 2), ("dog runs", 1)]);
+
+            // When
+            let result = stemmer.apply(ngram_count, true);
+
+            // Then
+            assert_eq!(result, counts(&[("cat runs", 3), ("dog runs", 1)]));
+        }
+
+        #[test]
+        fn test_disabled_stemmer_keeps_counts() {
+            // Given
+            let word_count = counts(&[("runs", 1), ("running", 1)]);
+
+            // Then
+            assert_eq!(
+                Stemmer::default().apply(word_count.clone(), false),
+                word_count
+            );
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::normalize::{normalize_token, Normalizer};
+use crate::tokenizer::Tokenizer;
+
+const ENGLISH: &str = include_str!("../data/stopwords_en.txt");
+const CHINESE: &str = include_str!("../data/stopwords_zh.txt");
+
+/// A bundled stop-word list, as selected with `--stop-words`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StopList {
+    /// Common English function words ("the", "of", "and", ...)
+    English,
+    /// Common Chinese function words ("的", "了", "和", ...); needs --cjk to match
+    Chinese,
+}
+
+impl StopList {
+    fn entries(self) -> &'static str {
+        match self {
+            StopList::English => ENGLISH,
+            StopList::Chinese => CHINESE,
+        }
+    }
+}
+
+/// Words that are counted but left out of the results.
+///
+/// List entries are cleaned and normalized like the tokens they are compared with, so a list
+/// written in lower case with apostrophes still matches under any `--tokenizer` and `--case`.
+/// Patterns are matched against the counted words as given; anchor them to match whole words.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StopWords {
+    words: HashSet<String>,
+    patterns: Vec<Regex>,
+}
+
+impl StopWords {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Adds the entries of a list, one per line; blank lines and lines starting with `#` are
+    /// skipped.
+    pub(crate) fn add_entries(
+        &mut self,
+        text: &str,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let word = normalize_token(tokenizer, normalizer, line);
+            if !word.is_empty() {
+                self.words.insert(word);
+            }
+        }
+    }
+
+    pub(crate) fn add_list(
+        &mut self,
+        list: StopList,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) {
+        self.add_entries(list.entries(), tokenizer, normalizer);
+    }
+
+    /// Adds the entries of the list file at `path`.
+    pub(crate) fn load(
+        &mut self,
+        path: &Path,
+        tokenizer: &dyn Tokenizer,
+        normalizer: &Normalizer,
+    ) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.add_entries(&text, tokenizer, normalizer);
+        Ok(())
+    }
+
+    pub(crate) fn add_pattern(&mut self, pattern: &str) -> Result<(), String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("invalid exclusion pattern: {}", e))?;
+        self.patterns.push(regex);
+        Ok(())
+    }
+
+    pub(crate) fn contains(&self, word: &str) -> bool {
+        self.words.contains(word) || self.patterns.iter().any(|pattern| pattern.is_match(word))
+    }
+
+    /// Removes the stop words from merged counts and returns how many occurrences were removed.
+    /// With `ngrams`, an entry is removed when any of its space-separated words is a stop word.
+    pub(crate) fn remove_from(&self, counts: &mut HashMap<String, usize>, ngrams: bool) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let mut filtered = 0;
+        counts.retain(|entry, count| {
+            let stop = if ngrams {
+                entry.split(' ').any(|word| self.contains(word))
+            } else {
+                self.contains(entry)
+            };
+            if stop {
+                filtered += *count;
+            }
+            !stop
+        });
+        filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::CaseMode;
+    use crate::test_util::counts;
+    use crate::tokenizer::{UnicodeWordTokenizer, WhitespaceTokenizer};
+
+    mod test_stop_words {
+        use super::*;
+
+        #[test]
+        fn test_bundled_lists_match_normalized_words() {
+            // Given
+            let mut stop_words = StopWords::default();
+            stop_words.add_list(
+                StopList::English,
+                &WhitespaceTokenizer,
+                &Normalizer::default(),
+            );
+            stop_words.add_list(
+                StopList::Chinese,
+                &WhitespaceTokenizer,
+                &Normalizer::default(),
+            );
+
+            // Then: the whitespace tokenizer strips the apostrophe of "don't".
+            assert!(stop_words.contains("the"));
+            assert!(stop_words.contains("dont"));
+            assert!(stop_words.contains("的"));
+            assert!(!stop_words.contains("cat"));
+        }
+
+        #[test]
+        fn test_entries_follow_tokenizer_and_case() {
+            // Given
+            let preserve = Normalizer {
+                case: CaseMode::Preserve,
+                ..Normalizer::default()
+            };
+            let mut stop_words = StopWords::default();
+
+            // When
+            stop_words.add_entries(
+                "# comment\n\n  Don't \nTHE\n",
+                &UnicodeWordTokenizer,
+                &preserve,
+            );
+
+            // Then
+            assert!(stop_words.contains("Don't"));
+            assert!(stop_words.contains("THE"));
+            assert!(!stop_words.contains("the"));
+            assert!(!stop_words.contains("# comment"));
+        }
+
+        #[test]
+        fn test_remove_from_counts_filtered_occurrences() {
+            // Given
+            let mut stop_words = StopWords::default();
+            stop_words.add_entries("the\nof\n", &WhitespaceTokenizer, &Normalizer::default());
+            stop_words.add_pattern(r"^\d+$").unwrap();
+            let mut word_count =
+                counts(&[("the", 5), ("of", 2), ("cat", 3), ("42", 4), ("b52", 1)]);
+
+            // When
+            let filtered = stop_words.remove_from(&mut word_count, false);
+
+            // Then
+            assert_eq!(filtered, 11);
+            assert_eq!(word_count, counts(&[("cat", 3), ("b52", 1)]));
+        }
+
+        #[test]
+        fn test_remove_from_drops_ngrams_containing_stop_words() {
+            // Given
+            let mut stop_words = StopWords::default();
+            stop_words.add_entries("the\n", &WhitespaceTokenizer, &Normalizer::default());
+            let mut ngram_count =
+                counts(&[("the cat", 2), ("cat sat", 1), ("sat on", 1), ("on the", 1)]);
+
+            // When
+            let filtered = stop_words.remove_from(&mut ngram_count, true);
+
+            // Then
+            assert_eq!(filtered, 3);
+            assert_eq!(ngram_count, counts(&[("cat sat", 1), ("sat on", 1)]));
+        }
+
+        #[test]
+        fn test_add_pattern_rejects_invalid_regex() {
+            assert!(StopWords::default().add_pattern("(").is_err());
+        }
+    }
+}
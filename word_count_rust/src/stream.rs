@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::encoding::{self, InvalidLines, Utf8Policy};
+use crate::error::WordCountError;
+use crate::fields::FieldSelector;
+use crate::mapped::{normalize_counts, tally_raw_words};
+use crate::merge::{merge_counts, tree_reduce};
+use crate::progress::{self, debug, warning};
+use crate::Config;
+
+/// A line-aligned (or, for quoted CSV, record-aligned) piece of the stream and the offset of its
+/// first byte.
+struct Block {
+    offset: u64,
+    bytes: Vec<u8>,
+}
+
+/// Counts the words of a non-seekable reader such as stdin or a pipe.
+///
+/// A reader thread cuts the stream into line-aligned blocks of about `config.block_size`
+/// bytes and sends them over a channel bounded to two blocks per worker. When the workers
+/// fall behind the reader blocks on `send`, so memory stays flat however long the stream is.
+pub(crate) fn count_stream<R: Read + Send>(
+    reader: R,
+    config: &Config,
+) -> io::Result<HashMap<String, usize>> {
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let thread_word_counts =
+        fold_stream(reader, config, HashMap::new, |word_count, text, _offset| {
+            let mut raw_count = HashMap::new();
+            let words = tally_raw_words(text, tokenizer, &mut raw_count);
+            merge_counts(
+                word_count,
+                normalize_counts(raw_count, tokenizer, normalizer),
+            );
+            words
+        })?;
+
+    debug!("All threads finished, merging results");
+    tree_reduce(thread_word_counts)
+}
+
+/// Runs `fold` over every block of `reader` on `config.num_threads` workers, each with its own
+/// accumulator from `init`, and returns the accumulators. `fold` receives each block with the
+/// stream offset of its first byte and returns the number of words it saw, for progress
+/// reporting. A stream that starts with a UTF-16 byte order mark is transcoded to UTF-8 first,
+/// and offsets count the UTF-8 bytes.
+pub(crate) fn fold_stream<R, A, I, F>(
+    reader: R,
+    config: &Config,
+    init: I,
+    fold: F,
+) -> io::Result<Vec<A>>
+where
+    R: Read + Send,
+    A: Send,
+    I: Fn() -> A + Sync,
+    F: Fn(&mut A, &str, u64) -> usize + Sync,
+{
+    let reader = encoding::utf8_reader(reader)?;
+    let (sender, receiver) = mpsc::sync_channel::<Block>(config.num_threads * 2);
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    thread::scope(|scope| {
+        let block_size = config.block_size;
+        let csv_delimiter = config
+            .fields
+            .as_ref()
+            .and_then(FieldSelector::csv_delimiter);
+        let reader_handle = scope.spawn(move || -> io::Result<()> {
+            for block in Blocks::new(reader, block_size, csv_delimiter) {
+                if sender.send(block?).is_err() {
+                    // Every worker has stopped; the error that stopped them is reported below.
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        let worker_handles: Vec<_> = (0..config.num_threads)
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                let (init, fold) = (&init, &fold);
+                let (invalid_utf8, invalid_lines) = (config.invalid_utf8, &config.invalid_lines);
+                scope.spawn(move || {
+                    fold_blocks(&receiver, init(), fold, invalid_utf8, invalid_lines, i)
+                })
+            })
+            .collect();
+
+        let mut accumulators = Vec::with_capacity(worker_handles.len());
+        for (i, handle) in worker_handles.into_iter().enumerate() {
+            accumulators.push(
+                handle
+                    .join()
+                    .map_err(|e| WordCountError::worker_panic(format!("Thread {}", i), e))??,
+            );
+        }
+        reader_handle
+            .join()
+            .map_err(|e| WordCountError::worker_panic("Reader thread", e))??;
+
+        Ok(accumulators)
+    })
+}
+
+/// Worker loop: pulls blocks until the reader hangs up.
+fn fold_blocks<A>(
+    receiver: &Mutex<Receiver<Block>>,
+    mut accumulator: A,
+    fold: &(impl Fn(&mut A, &str, u64) -> usize + Sync),
+    invalid_utf8: Utf8Policy,
+    invalid_lines: &InvalidLines,
+    thread_id: usize,
+) -> io::Result<A> {
+    debug!("Thread {} started", thread_id);
+    let mut total_words = 0;
+    loop {
+        // The lock is only held while waiting for the next block, never while counting.
+        let block = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            // Only a worker that panicked while waiting can poison the lock.
+            Err(_) => {
+                return Err(WordCountError::WorkerPanic {
+                    worker: "Another thread".to_string(),
+                    message: "it held the block queue".to_string(),
+                }
+                .into())
+            }
+        };
+        let Ok(block) = block else {
+            break;
+        };
+
+        let text = encoding::decode_at(&block.bytes, block.offset, invalid_utf8, invalid_lines)?;
+        let words = fold(&mut accumulator, &text, block.offset);
+        total_words += words;
+        progress::add_bytes(block.bytes.len() as u64);
+        progress::add_words(words as u64);
+    }
+    debug!(
+        "Thread {} finished processing {} words",
+        thread_id, total_words
+    );
+    Ok(accumulator)
+}
+
+/// Largest CSV record kept in one block while a quoted field is open. Past it, the quote is
+/// taken to be a stray one and the block ends at a line end, so one unbalanced quote cannot
+/// turn the rest of the stream into a single block.
+const MAX_RECORD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Where a CSV reader is within a record. A quote only opens a quoted field at the start of a
+/// field, as the csv crate reads it, so the quote in `1,5" screen` is text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CsvState {
+    FieldStart,
+    Unquoted,
+    Quoted,
+    /// A quote inside a quoted field: the end of the field, or the first half of `""`.
+    QuoteInQuoted,
+}
+
+/// Finds the newlines that end CSV records, in bytes fed to it piece by piece, so newlines in
+/// quoted fields can be told from those between records.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordScanner {
+    delimiter: u8,
+    state: CsvState,
+    /// Number of bytes scanned so far.
+    scanned: usize,
+    /// Where the quoted field being read was opened.
+    quote_start: usize,
+}
+
+impl RecordScanner {
+    /// A scanner at the start of a record.
+    pub(crate) fn new(delimiter: u8) -> Self {
+        RecordScanner {
+            delimiter,
+            state: CsvState::FieldStart,
+            scanned: 0,
+            quote_start: 0,
+        }
+    }
+
+    /// Scans `bytes`, which follow the bytes scanned before, and returns the position of the
+    /// last newline among them that ends a record, counted from the first byte ever scanned.
+    pub(crate) fn scan(&mut self, bytes: &[u8]) -> Option<usize> {
+        let mut end = None;
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.state = match (self.state, byte) {
+                (CsvState::Quoted, b'"') => CsvState::QuoteInQuoted,
+                (CsvState::Quoted, _) => CsvState::Quoted,
+                (CsvState::FieldStart, b'"') => {
+                    self.quote_start = self.scanned + i;
+                    CsvState::Quoted
+                }
+                (CsvState::QuoteInQuoted, b'"') => CsvState::Quoted,
+                (_, b'\n') => {
+                    end = Some(self.scanned + i);
+                    CsvState::FieldStart
+                }
+                (_, byte) if byte == self.delimiter => CsvState::FieldStart,
+                _ => CsvState::Unquoted,
+            };
+        }
+        self.scanned += bytes.len();
+        end
+    }
+
+    /// Where the quoted field that is still open was opened, if one is.
+    pub(crate) fn open_quote(&self) -> Option<usize> {
+        (self.state == CsvState::Quoted).then_some(self.quote_start)
+    }
+}
+
+/// The line-aligned blocks of a reader, in stream order.
+///
+/// Each block is roughly `block_size` bytes. Bytes after the last newline are kept in `carry`
+/// and start the next block. A line longer than `block_size` grows the block until the line
+/// ends. With a CSV delimiter, a block only ends on a newline outside quoted fields, so a
+/// record whose quoted field spans lines stays in one block, up to `max_record` bytes. Every
+/// byte is scanned once, however often the block grows.
+struct Blocks<R> {
+    reader: R,
+    block_size: usize,
+    /// Delimiter of CSV records, whose quoted fields may hold newlines.
+    csv_delimiter: Option<u8>,
+    max_record: usize,
+    carry: Vec<u8>,
+    /// Stream offset of the next block.
+    offset: u64,
+}
+
+impl<R: Read> Blocks<R> {
+    fn new(reader: R, block_size: usize, csv_delimiter: Option<u8>) -> Self {
+        Blocks {
+            reader,
+            block_size,
+            csv_delimiter,
+            max_record: MAX_RECORD_SIZE,
+            carry: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Reads the next block; `None` at end of stream.
+    fn read_block(&mut self) -> io::Result<Option<Block>> {
+        let mut bytes = std::mem::take(&mut self.carry);
+        // Every block starts a record, so the scanner starts afresh.
+        let mut records = self.csv_delimiter.map(RecordScanner::new);
+        let (mut scanned, mut line_end, mut record_end) = (0, None, None);
+        loop {
+            let new_bytes = &bytes[scanned..];
+            if let Some(newline) = new_bytes.iter().rposition(|&b| b == b'\n') {
+                line_end = Some(scanned + newline);
+            }
+            if let Some(end) = records.as_mut().and_then(|records| records.scan(new_bytes)) {
+                record_end = Some(end);
+            }
+            scanned = bytes.len();
+
+            if bytes.len() >= self.block_size {
+                let end = match &records {
+                    None => line_end,
+                    Some(_) if record_end.is_some() => record_end,
+                    Some(records) if bytes.len() >= self.max_record && line_end.is_some() => {
+                        warning!(
+                            "The quote at byte offset {} is still open after {} bytes; the \
+                             record is cut at the line end at byte offset {}",
+                            self.offset + records.open_quote().unwrap_or(0) as u64,
+                            bytes.len(),
+                            self.offset + line_end.unwrap_or(0) as u64
+                        );
+                        line_end
+                    }
+                    Some(_) => None,
+                };
+                if let Some(end) = end {
+                    self.carry = bytes.split_off(end + 1);
+                    return Ok(Some(self.block(bytes)));
+                }
+            }
+
+            let filled = bytes.len();
+            bytes.resize(filled + self.block_size, 0);
+            let bytes_read = match self.reader.read(&mut bytes[filled..]) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    bytes.truncate(filled);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            bytes.truncate(filled + bytes_read);
+            if bytes_read == 0 {
+                // End of stream: whatever is left is the final (possibly unterminated) line.
+                return Ok((!bytes.is_empty()).then(|| self.block(bytes)));
+            }
+        }
+    }
+
+    fn block(&mut self, bytes: Vec<u8>) -> Block {
+        let offset = self.offset;
+        self.offset += bytes.len() as u64;
+        Block { offset, bytes }
+    }
+}
+
+impl<R: Read> Iterator for Blocks<R> {
+    type Item = io::Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_block().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A reader that hands out at most `max_read` bytes per call, like a pipe.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        max_read: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.max_read).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn blocks_of(data: &[u8], block_size: usize, max_read: usize) -> Vec<Vec<u8>> {
+        collect_blocks(Blocks::new(Trickle { data, max_read }, block_size, None))
+    }
+
+    fn collect_blocks(blocks: Blocks<Trickle>) -> Vec<Vec<u8>> {
+        let blocks: Vec<Block> = blocks.collect::<io::Result<_>>().unwrap();
+        let mut offset = 0;
+        for block in &blocks {
+            assert_eq!(block.offset, offset);
+            offset += block.bytes.len() as u64;
+        }
+        blocks.into_iter().map(|block| block.bytes).collect()
+    }
+
+    mod test_blocks {
+        use super::*;
+
+        #[test]
+        fn test_blocks_end_on_line_boundaries() {
+            // Given
+            let data = b"one two\nthree\nfour five six\nseven\n";
+
+            // When
+            let blocks = blocks_of(data, 10, 3);
+
+            // Then
+            assert!(blocks.iter().all(|block| block.ends_with(b"\n")));
+            assert_eq!(blocks.concat(), data);
+        }
+
+        #[test]
+        fn test_blocks_keep_unterminated_last_line() {
+            // Given
+            let data = b"first line\nlast line";
+
+            // When
+            let blocks = blocks_of(data, 4, 64);
+
+            // Then
+            assert_eq!(blocks.last().unwrap(), b"last line");
+            assert_eq!(blocks.concat(), data);
+        }
+
+        #[test]
+        fn test_blocks_grow_for_lines_longer_than_block_size() {
+            // Given
+            let long_line = "A".repeat(100) + "\n";
+            let data = format!("{}short\n", long_line);
+
+            // When
+            let blocks = blocks_of(data.as_bytes(), 8, 5);
+
+            // Then
+            assert_eq!(blocks[0], long_line.as_bytes());
+            assert_eq!(blocks.concat(), data.as_bytes());
+        }
+
+        #[test]
+        fn test_quoted_blocks_keep_multiline_fields_together() {
+            // Given
+            let data = b"id,text\n1,\"two\nlines\"\n2,\"say \"\"hi\"\"\nthere\"\n3,plain\n";
+
+            // When
+            let blocks = collect_blocks(Blocks::new(Trickle { data, max_read: 3 }, 4, Some(b',')));
+
+            // Then
+            assert_eq!(
+                blocks,
+                [
+                    &b"id,text\n"[..],
+                    b"1,\"two\nlines\"\n",
+                    b"2,\"say \"\"hi\"\"\nthere\"\n",
+                    b"3,plain\n"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_quoted_blocks_ignore_quotes_inside_unquoted_fields() {
+            // Given
+            let data = b"1,5\" screen\n2,ok\n3,\"a\"\"b\",ok\n4,ok\n";
+
+            // When
+            let blocks = collect_blocks(Blocks::new(Trickle { data, max_read: 64 }, 1, Some(b',')));
+
+            // Then
+            assert_eq!(
+                blocks,
+                [
+                    &b"1,5\" screen\n"[..],
+                    b"2,ok\n",
+                    b"3,\"a\"\"b\",ok\n",
+                    b"4,ok\n"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_quoted_blocks_cut_unclosed_quote_at_record_limit() {
+            // Given
+            let data = b"1,\"never closed\n2,ok\n3,ok\n4,ok\n5,ok\n";
+            let mut blocks = Blocks::new(Trickle { data, max_read: 4 }, 4, Some(b','));
+            blocks.max_record = 20;
+
+            // When
+            let blocks = collect_blocks(blocks);
+
+            // Then
+            assert_eq!(blocks[0], b"1,\"never closed\n");
+            assert!(blocks.iter().all(|block| block.ends_with(b"\n")));
+            assert_eq!(blocks.concat(), data);
+        }
+
+        #[test]
+        fn test_record_scanner_carries_state_across_pieces() {
+            // Given
+            let mut scanner = RecordScanner::new(b';');
+
+            // When / Then
+            assert_eq!(scanner.scan(b"a;\"x\n"), None);
+            assert_eq!(scanner.open_quote(), Some(2));
+            assert_eq!(scanner.scan(b"y\"\"\";b\nc"), Some(11));
+            assert_eq!(scanner.open_quote(), None);
+        }
+
+        #[test]
+        fn test_blocks_of_empty_stream() {
+            // When
+            let blocks = blocks_of(b"", 8, 8);
+
+            // Then
+            assert!(blocks.is_empty());
+        }
+    }
+
+    mod test_count_stream {
+        use super::*;
+        use crate::count_file;
+        use std::fs;
+        use tempfile::TempDir;
+
+        #[test]
+        fn test_count_stream_matches_count_file() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let input_path = temp_dir.path().join("input.txt");
+            let content: String = (0..500)
+                .map(|i| format!("Line {}: the Quick fox{}, the dog!\n", i, i % 13))
+                .collect();
+            fs::write(&input_path, &content).unwrap();
+            let expected = count_file(&input_path, &Config::default()).unwrap();
+
+            for (num_threads, block_size) in [(1, 1), (2, 16), (3, 100), (8, 4096)] {
+                let config = Config {
+                    num_threads,
+                    block_size,
+                    ..Config::default()
+                };
+
+                // When
+                let result = count_stream(
+                    Trickle {
+                        data: content.as_bytes(),
+                        max_read: 7,
+                    },
+                    &config,
+                )
+                .unwrap();
+
+                // Then
+                assert_eq!(
+                    result, expected,
+                    "Mismatch with {} threads and {} byte blocks",
+                    num_threads, block_size
+                );
+            }
+        }
+
+        #[test]
+        fn test_count_stream_with_empty_input() {
+            // When
+            let result = count_stream(Cursor::new(Vec::new()), &Config::default()).unwrap();
+
+            // Then
+            assert!(result.is_empty());
+        }
+
+        #[test]
+        fn test_count_stream_reports_offset_of_invalid_utf8() {
+            // Given
+            let data = b"first line\nsecond line\nbad \xff line\n".to_vec();
+            let config = Config {
+                block_size: 4,
+                ..Config::default()
+            };
+
+            // When
+            let err = count_stream(Cursor::new(data), &config).unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("byte offset 27"), "{}", err);
+        }
+
+        #[test]
+        fn test_count_stream_propagates_read_errors() {
+            // Given
+            struct Broken;
+            impl Read for Broken {
+                fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                    Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"))
+                }
+            }
+
+            // When
+            let err = count_stream(Broken, &Config::default()).unwrap_err();
+
+            // Then
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn test_count_file_reads_fifo_as_stream() {
+            use std::io::Write;
+            use std::process::Command;
+
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let fifo_path = temp_dir.path().join("input.fifo");
+            let status = Command::new("mkfifo").arg(&fifo_path).status().unwrap();
+            assert!(status.success());
+            let writer_path = fifo_path.clone();
+            let writer = std::thread::spawn(move || {
+                let mut fifo = fs::OpenOptions::new()
+                    .write(true)
+                    .open(writer_path)
+                    .unwrap();
+                fifo.write_all(b"piped words\nmore piped words\n").unwrap();
+            });
+
+            // When
+            let result = count_file(&fifo_path, &Config::default()).unwrap();
+
+            // Then
+            writer.join().unwrap();
+            assert_eq!(result.get("piped"), Some(&2));
+            assert_eq!(result.get("words"), Some(&2));
+            assert_eq!(result.get("more"), Some(&1));
+        }
+    }
+}
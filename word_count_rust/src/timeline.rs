@@ -0,0 +1,680 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::decompress::decompressing_reader;
+use crate::error::at_path;
+use crate::format::Trend;
+use crate::mapped::{normalize_counts, tally_raw_words};
+use crate::merge::merge_counts;
+use crate::progress::info;
+use crate::{create_output, report_entries, stop_word_footer, stream, Config, Output, STDIN_PATH};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Width of the time buckets, as selected with `--buckets`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum BucketSize {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl BucketSize {
+    fn seconds(self) -> i64 {
+        match self {
+            BucketSize::Minute => 60,
+            BucketSize::Hour => 60 * 60,
+            BucketSize::Day => SECONDS_PER_DAY,
+        }
+    }
+
+    /// The bucket that starts at `start`, seconds since the Unix epoch, as written in the
+    /// results: `2024-05-01T13:07`, `2024-05-01T13:00` or `2024-05-01`.
+    fn label(self, start: i64) -> String {
+        let (year, month, day) = civil_from_days(start.div_euclid(SECONDS_PER_DAY));
+        let seconds = start.rem_euclid(SECONDS_PER_DAY);
+        match self {
+            BucketSize::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+            BucketSize::Minute | BucketSize::Hour => format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}",
+                year,
+                month,
+                day,
+                seconds / 3600,
+                seconds % 3600 / 60
+            ),
+        }
+    }
+}
+
+/// Settings of `--buckets`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TimelineConfig {
+    pub(crate) format: TimestampFormat,
+    pub(crate) bucket: BucketSize,
+    /// Number of words reported as trending up in the last bucket; 0 for none.
+    pub(crate) trending: usize,
+}
+
+/// One directive or literal character of a timestamp format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Literal(char),
+    /// `%Y`: four digits.
+    Year,
+    /// `%m`, `%d`, `%H`, `%M` and `%S`: two digits each.
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    /// `%f`: the digits of a fraction of a second, which is ignored.
+    Fraction,
+    /// `%z`: `Z`, or an offset from UTC such as `+02:00` or `-0530`.
+    Offset,
+    /// `%s`: seconds since the Unix epoch.
+    Epoch,
+}
+
+/// The timestamp each line starts with, in the strftime-like notation of
+/// `--timestamp-format`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TimestampFormat {
+    pattern: String,
+    items: Vec<Item>,
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl TimestampFormat {
+    pub(crate) fn parse(pattern: &str) -> Result<Self, String> {
+        let mut items = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                items.push(Item::Literal(c));
+                continue;
+            }
+            items.push(match chars.next() {
+                Some('Y') => Item::Year,
+                Some('m') => Item::Month,
+                Some('d') => Item::Day,
+                Some('H') => Item::Hour,
+                Some('M') => Item::Minute,
+                Some('S') => Item::Second,
+                Some('f') => Item::Fraction,
+                Some('z') => Item::Offset,
+                Some('s') => Item::Epoch,
+                Some('%') => Item::Literal('%'),
+                Some(other) => {
+                    return Err(format!(
+                        "unsupported directive %{} in timestamp format",
+                        other
+                    ))
+                }
+                None => return Err("timestamp format ends in a lone %".to_string()),
+            });
+        }
+        if !items.contains(&Item::Epoch) && !items.contains(&Item::Year) {
+            return Err("timestamp format needs %Y or %s".to_string());
+        }
+        Ok(TimestampFormat {
+            pattern: pattern.to_string(),
+            items,
+        })
+    }
+
+    /// The time `line` starts with, in seconds since the Unix epoch, and the rest of the line;
+    /// `None` when the line does not start with a valid timestamp. Without `%z` or `%s`, the
+    /// timestamp is taken as UTC.
+    pub(crate) fn parse_prefix<'a>(&self, line: &'a str) -> Option<(i64, &'a str)> {
+        let (mut year, mut month, mut day) = (1970, 1, 1);
+        let (mut hour, mut minute, mut second) = (0, 0, 0);
+        let (mut offset, mut epoch) = (0, None);
+        let mut rest = line;
+        for item in &self.items {
+            match item {
+                Item::Literal(c) => rest = rest.strip_prefix(*c)?,
+                Item::Year => year = take_digits(&mut rest, 4, 4)?,
+                Item::Month => month = take_digits(&mut rest, 2, 2)?,
+                Item::Day => day = take_digits(&mut rest, 2, 2)?,
+                Item::Hour => hour = take_digits(&mut rest, 2, 2)?,
+                Item::Minute => minute = take_digits(&mut rest, 2, 2)?,
+                Item::Second => second = take_digits(&mut rest, 2, 2)?,
+                Item::Fraction => {
+                    take_digits(&mut rest, 1, usize::MAX)?;
+                }
+                Item::Offset => offset = take_offset(&mut rest)?,
+                Item::Epoch => epoch = Some(take_digits(&mut rest, 1, 18)?),
+            }
+        }
+        if let Some(epoch) = epoch {
+            return Some((epoch, rest));
+        }
+        let valid = (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second <= 60;
+        if !valid {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+        Some((
+            days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second - offset,
+            rest,
+        ))
+    }
+}
+
+/// Takes between `min` and `max` ASCII digits from the start of `text`.
+fn take_digits(text: &mut &str, min: usize, max: usize) -> Option<i64> {
+    let len = text
+        .bytes()
+        .take(max)
+        .take_while(u8::is_ascii_digit)
+        .count();
+    if len < min {
+        return None;
+    }
+    let value = text[..len.min(18)].parse().ok()?;
+    *text = &text[len..];
+    Some(value)
+}
+
+/// Takes `Z` or `+hh:mm`, `+hhmm`, `+hh` (or `-`) and returns the offset in seconds.
+fn take_offset(text: &mut &str) -> Option<i64> {
+    if let Some(rest) = text.strip_prefix('Z') {
+        *text = rest;
+        return Some(0);
+    }
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    *text = &text[1..];
+    let hours = take_digits(text, 2, 2)?;
+    if let Some(rest) = text.strip_prefix(':') {
+        *text = rest;
+    }
+    let minutes = take_digits(text, 2, 2).unwrap_or(0);
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar (Howard
+/// Hinnant's `days_from_civil`).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` after 1970-01-01; the inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Word counts per bucket, keyed by the start of the bucket in seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Timeline {
+    buckets: BTreeMap<i64, HashMap<String, usize>>,
+    /// Non-blank lines that do not start with a timestamp.
+    untimed: u64,
+}
+
+impl Timeline {
+    fn merge(&mut self, other: Timeline) {
+        for (start, counts) in other.buckets {
+            merge_counts(self.buckets.entry(start).or_default(), counts);
+        }
+        self.untimed += other.untimed;
+    }
+
+    /// The buckets lines fell into, in time order. Empty buckets are left out, so one line with
+    /// an outlying timestamp cannot blow the series up to every bucket in between.
+    fn starts(&self) -> Vec<i64> {
+        self.buckets.keys().copied().collect()
+    }
+
+    fn count(&self, start: i64, word: &str) -> usize {
+        self.buckets
+            .get(&start)
+            .and_then(|counts| counts.get(word))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Counts the words of one input per bucket. Every input is streamed, as in approximate
+/// mode; each line is bucketed on its own, so the blocks can be counted in any order.
+pub(crate) fn count_file(
+    input_path: &Path,
+    config: &Config,
+    timeline: &TimelineConfig,
+) -> io::Result<Timeline> {
+    let (tokenizer, normalizer) = (config.tokenizer.as_ref(), &config.normalizer);
+    let width = timeline.bucket.seconds();
+    let fold = |result: &mut Timeline, text: &str, _offset: u64| {
+        let mut raw_counts: BTreeMap<i64, HashMap<&str, usize>> = BTreeMap::new();
+        let mut words = 0;
+        for line in text.lines() {
+            match timeline.format.parse_prefix(line) {
+                Some((time, rest)) => {
+                    let start = time.div_euclid(width) * width;
+                    let raw_count = raw_counts.entry(start).or_default();
+                    words += tally_raw_words(rest, tokenizer, raw_count);
+                }
+                None if line.trim().is_empty() => {}
+                None => result.untimed += 1,
+            }
+        }
+        for (start, raw_count) in raw_counts {
+            merge_counts(
+                result.buckets.entry(start).or_default(),
+                normalize_counts(raw_count, tokenizer, normalizer),
+            );
+        }
+        words
+    };
+
+    let thread_timelines = if input_path == Path::new(STDIN_PATH) {
+        stream::fold_stream(
+            decompressing_reader(io::stdin())?,
+            config,
+            Timeline::default,
+            fold,
+        )?
+    } else {
+        let reader = decompressing_reader(File::open(input_path)?)?;
+        stream::fold_stream(reader, config, Timeline::default, fold)?
+    };
+
+    let mut result = Timeline::default();
+    for thread_timeline in thread_timelines {
+        result.merge(thread_timeline);
+    }
+    Ok(result)
+}
+
+/// The words whose count rose the most from the bucket just before the last one, which may be
+/// empty, to the last one, with both counts; ties are broken by word.
+fn trending(timeline: &Timeline, bucket: BucketSize, limit: usize) -> Vec<(String, usize, usize)> {
+    let Some((&last, last_counts)) = timeline.buckets.last_key_value() else {
+        return Vec::new();
+    };
+    let previous = last - bucket.seconds();
+    let mut rising: Vec<_> = last_counts
+        .iter()
+        .map(|(word, &count)| (word.clone(), timeline.count(previous, word), count))
+        .filter(|&(_, before, after)| after > before)
+        .collect();
+    rising.sort_by(|a, b| (b.2 - b.1).cmp(&(a.2 - a.1)).then_with(|| a.0.cmp(&b.0)));
+    rising.truncate(limit);
+    rising
+}
+
+/// Time-series counterpart of `process_file`: counts every input per bucket and writes a series
+/// per word through the usual report, followed by the words trending up in the last bucket,
+/// which are also logged.
+pub(crate) fn process_files(
+    input_files: &[PathBuf],
+    output: &Output,
+    config: &Config,
+    timeline_config: &TimelineConfig,
+) -> io::Result<()> {
+    let mut timeline = Timeline::default();
+    for input_file in input_files {
+        let input_timeline =
+            count_file(input_file, config, timeline_config).map_err(at_path(input_file))?;
+        timeline.merge(input_timeline);
+    }
+    if timeline.untimed > 0 {
+        info!(
+            "{} {} not start with a timestamp like {} and {} left out",
+            timeline.untimed,
+            if timeline.untimed == 1 {
+                "line did"
+            } else {
+                "lines did"
+            },
+            timeline_config.format,
+            if timeline.untimed == 1 { "was" } else { "were" }
+        );
+    }
+
+    let mut filtered = 0;
+    for counts in timeline.buckets.values_mut() {
+        filtered += config.stop_words.remove_from(counts, false);
+        *counts = config.stemmer.apply(std::mem::take(counts), false);
+    }
+    let footer = stop_word_footer(config, filtered);
+
+    let bucket = timeline_config.bucket;
+    let starts = timeline.starts();
+    let mut totals = HashMap::new();
+    for counts in timeline.buckets.values() {
+        merge_counts(&mut totals, counts.clone());
+    }
+    let entries = report_entries(&totals, &config.report)?;
+    let labels: Vec<String> = starts.iter().map(|&start| bucket.label(start)).collect();
+    let series: Vec<(&str, Vec<usize>)> = entries
+        .iter()
+        .map(|&(word, _)| {
+            let counts = starts.iter().map(|&start| timeline.count(start, word));
+            (word, counts.collect())
+        })
+        .collect();
+    let trending = trending(&timeline, bucket, timeline_config.trending);
+    let trends: Vec<Trend> = trending
+        .iter()
+        .map(|(word, before, after)| (word.as_str(), *before, *after))
+        .collect();
+
+    let write = |mut writer: &mut dyn Write| -> io::Result<()> {
+        let result_writer = config.report.format.writer();
+        result_writer.write_series(&mut writer, &labels, &series, &trends)?;
+        result_writer.write_footer(&mut writer, &footer)?;
+        writer.flush()
+    };
+    match output {
+        Output::Stdout => write(&mut io::stdout().lock())?,
+        Output::File(output_path) => {
+            create_output(output_path)
+                .and_then(|mut writer| write(&mut writer))
+                .map_err(at_path(output_path))?;
+            info!("Results written to {}", output_path.display());
+        }
+    }
+
+    for (word, before, after) in trends {
+        info!(
+            "Trending in {}: {} ({} -> {})",
+            labels.last().expect("trending needs a bucket"),
+            word,
+            before,
+            after
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counts;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn iso() -> TimestampFormat {
+        TimestampFormat::parse("%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    mod test_timestamp_format {
+        use super::*;
+
+        #[test]
+        fn test_parse_prefix_reads_iso_timestamps() {
+            // When
+            let parsed = iso().parse_prefix("2024-03-01T12:34:56 disk full");
+
+            // Then
+            assert_eq!(parsed, Some((1_709_296_496, " disk full")));
+        }
+
+        #[test]
+        fn test_parse_prefix_applies_offset_and_skips_fraction() {
+            // Given
+            let format = TimestampFormat::parse("[%Y-%m-%d %H:%M:%S.%f%z]").unwrap();
+
+            // When
+            let parsed = format.parse_prefix("[2024-03-01 14:34:56.123+02:00] ok");
+
+            // Then
+            assert_eq!(parsed, Some((1_709_296_496, " ok")));
+        }
+
+        #[test]
+        fn test_parse_prefix_reads_epoch_seconds() {
+            // Given
+            let format = TimestampFormat::parse("%s ").unwrap();
+
+            // When / Then
+            assert_eq!(
+                format.parse_prefix("1709296496 x"),
+                Some((1_709_296_496, "x"))
+            );
+            assert_eq!(format.parse_prefix("soon x"), None);
+        }
+
+        #[test]
+        fn test_parse_prefix_rejects_impossible_dates() {
+            // When / Then
+            assert_eq!(iso().parse_prefix("2023-02-29T00:00:00 x"), None);
+            assert!(iso().parse_prefix("2024-02-29T00:00:00 x").is_some());
+            assert_eq!(iso().parse_prefix("2024-13-01T00:00:00 x"), None);
+            assert_eq!(iso().parse_prefix("2024-01-01T24:00:00 x"), None);
+            assert_eq!(iso().parse_prefix("  2024-01-01T00:00:00 x"), None);
+        }
+
+        #[test]
+        fn test_parse_rejects_unknown_directives() {
+            // When / Then
+            assert!(TimestampFormat::parse("%Y %q").is_err());
+            assert!(TimestampFormat::parse("%H:%M").is_err());
+            assert!(TimestampFormat::parse("%Y%").is_err());
+        }
+
+        #[test]
+        fn test_civil_days_round_trip() {
+            for days in [-800_000, -1, 0, 59, 11_016, 19_782, 2_000_000] {
+                let (year, month, day) = civil_from_days(days);
+                assert_eq!(days_from_civil(year, month, day), days);
+            }
+            assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        }
+    }
+
+    mod test_timeline {
+        use super::*;
+
+        fn config(bucket: BucketSize) -> TimelineConfig {
+            TimelineConfig {
+                format: iso(),
+                bucket,
+                trending: 2,
+            }
+        }
+
+        #[test]
+        fn test_count_file_buckets_lines_and_leaves_out_untimed_ones() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            let log = "2024-03-01T10:15:00 error disk\n\
+                       2024-03-01T10:59:59 error\n\
+                       \tat stack frame\n\
+                       \n\
+                       2024-03-01T12:00:00 ok\n";
+            fs::write(&path, log).unwrap();
+
+            // When
+            let timeline =
+                count_file(&path, &Config::default(), &config(BucketSize::Hour)).unwrap();
+
+            // Then
+            let ten = 1_709_287_200;
+            assert_eq!(timeline.untimed, 1);
+            assert_eq!(timeline.count(ten, "error"), 2);
+            assert_eq!(timeline.count(ten, "2024"), 0);
+            assert_eq!(timeline.count(ten + 7200, "ok"), 1);
+            let starts = timeline.starts();
+            assert_eq!(starts, [ten, ten + 7200]);
+            let labels: Vec<_> = starts.iter().map(|&s| BucketSize::Hour.label(s)).collect();
+            assert_eq!(labels, ["2024-03-01T10:00", "2024-03-01T12:00"]);
+            assert_eq!(BucketSize::Day.label(ten), "2024-03-01");
+        }
+
+        #[test]
+        fn test_count_file_matches_single_worker() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            let log: String = (0..3000)
+                .map(|i| format!("2024-03-01T10:{:02}:00 word{} shared\n", i % 60, i % 7))
+                .collect();
+            fs::write(&path, log).unwrap();
+            let single = Config {
+                num_threads: 1,
+                ..Config::default()
+            };
+            let many = Config {
+                num_threads: 4,
+                block_size: 512,
+                ..Config::default()
+            };
+
+            // When
+            let expected = count_file(&path, &single, &config(BucketSize::Minute)).unwrap();
+            let result = count_file(&path, &many, &config(BucketSize::Minute)).unwrap();
+
+            // Then
+            assert_eq!(result, expected);
+            assert_eq!(result.buckets.len(), 60);
+        }
+
+        #[test]
+        fn test_trending_ranks_rises_into_last_bucket() {
+            // Given
+            let mut timeline = Timeline::default();
+            timeline
+                .buckets
+                .insert(0, counts(&[("steady", 5), ("rising", 1)]));
+            timeline.buckets.insert(
+                60,
+                counts(&[("steady", 5), ("rising", 4), ("new", 3), ("also", 3)]),
+            );
+
+            // When
+            let result = trending(&timeline, BucketSize::Minute, 2);
+
+            // Then
+            assert_eq!(
+                result,
+                [("also".to_string(), 0, 3), ("new".to_string(), 0, 3)]
+            );
+        }
+
+        #[test]
+        fn test_process_files_leaves_out_empty_buckets() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("app.log");
+            let output_path = temp_dir.path().join("series.csv");
+            let log = "2024-03-01T10:00:00 error\n2024-03-01T12:30:00 error warn\n";
+            fs::write(&path, log).unwrap();
+            let config = Config {
+                report: crate::Report {
+                    format: crate::format::Format::Csv,
+                    ..crate::Report::default()
+                },
+                ..Config::default()
+            };
+
+            // When
+            process_files(
+                &[path],
+                &Output::File(output_path.clone()),
+                &config,
+                &self::config(BucketSize::Hour),
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(
+                fs::read_to_string(&output_path).unwrap(),
+                "word,2024-03-01T10:00,2024-03-01T12:00\n\
+                 error,1,1\n\
+                 warn,0,1\n\
+                 \n\
+                 trending,before,after\n\
+                 error,0,1\n\
+                 warn,0,1\n"
+            );
+        }
+
+        #[test]
+        fn test_process_files_keeps_outlying_timestamps_sparse() {
+            // Given
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("boot.log");
+            let output_path = temp_dir.path().join("series.txt");
+            fs::write(
+                &path,
+                "0 boot
+1700000000 b
+",
+            )
+            .unwrap();
+            let timeline_config = TimelineConfig {
+                format: TimestampFormat::parse("%s ").unwrap(),
+                ..config(BucketSize::Minute)
+            };
+
+            // When
+            process_files(
+                &[path],
+                &Output::File(output_path.clone()),
+                &Config::default(),
+                &timeline_config,
+            )
+            .unwrap();
+
+            // Then
+            assert_eq!(
+                fs::read_to_string(&output_path).unwrap(),
+                "# buckets: 1970-01-01T00:00 2023-11-14T22:13\n\
+                 b: 0 1\n\
+                 boot: 1 0\n\
+                 # trending: b 0 -> 1\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Splits text into the tokens that get counted.
+///
+/// Tokens are borrowed slices of the text, so the counting pipelines can key their per-thread
+/// maps on them without copying; `clean` and normalization run later, once per distinct token.
+pub trait Tokenizer: fmt::Debug + Send + Sync {
+    /// Calls `emit` with every token of `text`, in order.
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str));
+
+    /// Strips whatever the tokenizer leaves attached to a token. Tokens are kept as they are
+    /// by default; an empty result drops the token.
+    fn clean<'a>(&self, token: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(token)
+    }
+
+    /// A key that changes whenever the tokens of some text would, so `--state` can tell whether
+    /// saved counts still apply. `None`, the default, means the saved counts are never reused.
+    fn fingerprint(&self) -> Option<String> {
+        None
+    }
+}
+
+/// The built-in tokenizers, as selected with `--tokenizer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TokenizerKind {
+    /// Split on whitespace and strip ASCII punctuation from each token
+    #[default]
+    Whitespace,
+    /// Unicode word boundaries (UAX #29): keeps "don't" and "3.14", splits "e-mail"
+    Words,
+    /// Every match of --token-pattern is a token
+    Regex,
+    /// Split code identifiers: camelCase, PascalCase, snake_case and kebab-case parts
+    Identifier,
+}
+
+impl TokenizerKind {
+    /// Builds the tokenizer; `pattern` is required by, and only allowed with, `Regex`.
+    pub(crate) fn build(self, pattern: Option<&str>) -> Result<Arc<dyn Tokenizer>, String> {
+        match (self, pattern) {
+            (TokenizerKind::Regex, Some(pattern)) => Ok(Arc::new(RegexTokenizer::new(pattern)?)),
+            (TokenizerKind::Regex, None) => {
+                Err("the regex tokenizer needs --token-pattern".to_string())
+            }
+            (_, Some(_)) => Err("--token-pattern requires --tokenizer regex".to_string()),
+            (TokenizerKind::Whitespace, None) => Ok(Arc::new(WhitespaceTokenizer)),
+            (TokenizerKind::Words, None) => Ok(Arc::new(UnicodeWordTokenizer)),
+            (TokenizerKind::Identifier, None) => Ok(Arc::new(IdentifierTokenizer)),
+        }
+    }
+}
+
+/// Whitespace-separated tokens with ASCII punctuation removed: the original behaviour, so
+/// "don't" counts as "dont" and "e-mail" as "email".
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+        text.split_whitespace().for_each(emit);
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        Some("whitespace".to_string())
+    }
+
+    fn clean<'a>(&self, token: &'a str) -> Cow<'a, str> {
+        if token.contains(|c: char| c.is_ascii_punctuation()) {
+            Cow::Owned(
+                token
+                    .chars()
+                    .filter(|c| !c.is_ascii_punctuation())
+                    .collect(),
+            )
+        } else {
+            Cow::Borrowed(token)
+        }
+    }
+}
+
+/// Words as defined by Unicode Standard Annex #29; punctuation and spaces between words are
+/// not tokens.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct UnicodeWordTokenizer;
+
+impl Tokenizer for UnicodeWordTokenizer {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+        text.unicode_words().for_each(emit);
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        Some("words".to_string())
+    }
+}
+
+/// Tokens are the non-overlapping matches of a user-supplied regular expression.
+#[derive(Debug, Clone)]
+pub(crate) struct RegexTokenizer {
+    regex: Regex,
+}
+
+impl RegexTokenizer {
+    pub(crate) fn new(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("invalid token pattern: {}", e))?;
+        Ok(RegexTokenizer { regex })
+    }
+}
+
+impl Tokenizer for RegexTokenizer {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+        for token in self.regex.find_iter(text) {
+            if !token.is_empty() {
+                emit(token.as_str());
+            }
+        }
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        Some(format!("regex {}", self.regex.as_str()))
+    }
+}
+
+/// Splits source-code identifiers into their words: `parseHTTPResponse_v2` yields "parse",
+/// "HTTP", "Response" and "v2". Anything that is not alphanumeric separates tokens.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct IdentifierTokenizer;
+
+impl Tokenizer for IdentifierTokenizer {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(&'a str)) {
+        for identifier in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|s| !s.is_empty())
+        {
+            split_identifier(identifier, emit);
+        }
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        Some("identifier".to_string())
+    }
+}
+
+/// Cuts an alphanumeric run before every uppercase letter that follows a lowercase letter or
+/// a digit, and before the last capital of an acronym that is followed by a lowercase letter.
+fn split_identifier<'a>(identifier: &'a str, emit: &mut dyn FnMut(&'a str)) {
+    let chars: Vec<(usize, char)> = identifier.char_indices().collect();
+    let mut start = 0;
+    for i in 1..chars.len() {
+        let (offset, c) = chars[i];
+        let prev = chars[i - 1].1;
+        let next_is_lower = chars
+            .get(i + 1)
+            .is_some_and(|&(_, next)| next.is_lowercase());
+        let boundary = c.is_uppercase()
+            && (prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower));
+        if boundary {
+            emit(&identifier[start..offset]);
+            start = offset;
+        }
+    }
+    emit(&identifier[start..]);
+}
+
+/// Tokenizes `text` one line at a time, with surrounding whitespace trimmed from each line, so
+/// every pipeline hands the tokenizer the same pieces whatever its chunk or block boundaries.
+pub(crate) fn tokenize_lines<'a>(
+    tokenizer: &dyn Tokenizer,
+    text: &'a str,
+    emit: &mut dyn FnMut(&'a str),
+) {
+    for line in text.lines() {
+        let line = line.trim();
+        if !line.is_empty() {
+            tokenizer.tokenize(line, emit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::{normalize_token, Normalizer};
+
+    fn tokens<'a>(tokenizer: &dyn Tokenizer, text: &'a str) -> Vec<&'a str> {
+        let mut tokens = Vec::new();
+        tokenizer.tokenize(text, &mut |token| tokens.push(token));
+        tokens
+    }
+
+    mod test_whitespace_tokenizer {
+        use super::*;
+
+        #[test]
+        fn test_whitespace_tokenizer_strips_ascii_punctuation() {
+            // Given
+            let text = "Don't e-mail me, \"Bob\"! 3.14";
+
+            // When
+            let words: Vec<String> = tokens(&WhitespaceTokenizer, text)
+                .into_iter()
+                .map(|token| normalize_token(&WhitespaceTokenizer, &Normalizer::default(), token))
+                .collect();
+
+            // Then
+            assert_eq!(words, ["dont", "email", "me", "bob", "314"]);
+        }
+    }
+
+    mod test_unicode_word_tokenizer {
+        use super::*;
+
+        #[test]
+        fn test_unicode_word_tokenizer_keeps_contractions_and_numbers() {
+            // When
+            let result = tokens(&UnicodeWordTokenizer, "Don't e-mail me, \"Bob\"! 3.14");
+
+            // Then
+            assert_eq!(result, ["Don't", "e", "mail", "me", "Bob", "3.14"]);
+        }
+
+        #[test]
+        fn test_unicode_word_tokenizer_leaves_punctuation_in_words() {
+            // When
+            let word = normalize_token(&UnicodeWordTokenizer, &Normalizer::default(), "Don't");
+
+            // Then
+            assert_eq!(word, "don't");
+        }
+
+        #[test]
+        fn test_unicode_word_tokenizer_handles_non_ascii_punctuation() {
+            // When
+            let result = tokens(&UnicodeWordTokenizer, "«Grüße» — naïve café…");
+
+            // Then
+            assert_eq!(result, ["Grüße", "naïve", "café"]);
+        }
+    }
+
+    mod test_regex_tokenizer {
+        use super::*;
+
+        #[test]
+        fn test_regex_tokenizer_emits_matches() {
+            // Given
+            let tokenizer = RegexTokenizer::new(r"[A-Za-z]+(?:-[A-Za-z]+)*").unwrap();
+
+            // When
+            let result = tokens(&tokenizer, "e-mail the co-op, 42 times");
+
+            // Then
+            assert_eq!(result, ["e-mail", "the", "co-op", "times"]);
+        }
+
+        #[test]
+        fn test_regex_tokenizer_skips_empty_matches() {
+            // Given
+            let tokenizer = RegexTokenizer::new(r"\d*").unwrap();
+
+            // When
+            let result = tokens(&tokenizer, "a1b22c");
+
+            // Then
+            assert_eq!(result, ["1", "22"]);
+        }
+
+        #[test]
+        fn test_regex_tokenizer_rejects_invalid_pattern() {
+            // When
+            let err = RegexTokenizer::new("(unclosed").unwrap_err();
+
+            // Then
+            assert!(err.starts_with("invalid token pattern"), "{}", err);
+        }
+    }
+
+    mod test_identifier_tokenizer {
+        use super::*;
+
+        #[test]
+        fn test_identifier_tokenizer_splits_cases() {
+            // When
+            let result = tokens(
+                &IdentifierTokenizer,
+                "parseHTTPResponse_v2 max_line_len XMLHttpRequest kebab-case",
+            );
+
+            // Then
+            assert_eq!(
+                result,
+                [
+                    "parse", "HTTP", "Response", "v2", "max", "line", "len", "XML", "Http",
+                    "Request", "kebab", "case"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_identifier_tokenizer_splits_after_digits() {
+            // When
+            let result = tokens(&IdentifierTokenizer, "utf8Decode ID3Tag");
+
+            // Then
+            assert_eq!(result, ["utf8", "Decode", "ID3", "Tag"]);
+        }
+
+        #[test]
+        fn test_identifier_tokenizer_with_non_ascii_letters() {
+            // When
+            let result = tokens(&IdentifierTokenizer, "größeÄnderung");
+
+            // Then
+            assert_eq!(result, ["größe", "Änderung"]);
+        }
+    }
+
+    mod test_tokenizer_kind {
+        use super::*;
+
+        #[test]
+        fn test_build_requires_pattern_for_regex() {
+            assert!(TokenizerKind::Regex.build(None).is_err());
+            assert!(TokenizerKind::Regex.build(Some(r"\w+")).is_ok());
+        }
+
+        #[test]
+        fn test_build_rejects_pattern_for_other_tokenizers() {
+            for kind in [
+                TokenizerKind::Whitespace,
+                TokenizerKind::Words,
+                TokenizerKind::Identifier,
+            ] {
+                assert!(kind.build(Some(r"\w+")).is_err(), "{:?}", kind);
+                assert!(kind.build(None).is_ok(), "{:?}", kind);
+            }
+        }
+    }
+
+    mod test_tokenize_lines {
+        use super::*;
+
+        #[test]
+        fn test_tokenize_lines_trims_each_line() {
+            // Given
+            let tokenizer = RegexTokenizer::new(r"^\w+").unwrap();
+            let mut result = Vec::new();
+
+            // When
+            tokenize_lines(
+                &tokenizer,
+                "  first word\n\tsecond word\r\n\nthird",
+                &mut |t| result.push(t),
+            );
+
+            // Then
+            assert_eq!(result, ["first", "second", "third"]);
+        }
+    }
+}
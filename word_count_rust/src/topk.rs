@@ -0,0 +1,267 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::thread;
+
+use crate::error::WordCountError;
+
+/// Order of the written results, as selected with `--sort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Order {
+    /// Alphabetical, with all-digit words in numeric order
+    #[default]
+    Word,
+    /// Most frequent first, ties broken by word
+    Count,
+}
+
+/// The order `write_results` has always used: numbers compare numerically when both words
+/// are all digits, everything else compares as strings.
+fn compare_words(a: &str, b: &str) -> Ordering {
+    if a.chars().all(|c| c.is_ascii_digit()) && b.chars().all(|c| c.is_ascii_digit()) {
+        match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a_num), Ok(b_num)) => a_num.cmp(&b_num),
+            _ => a.cmp(b), // 如果任一解析失败，退回到字符串比较
+        }
+    } else {
+        a.cmp(b)
+    }
+}
+
+/// Higher count first; on equal counts the word that sorts first by `compare_words`.
+fn compare_by_count(a: &(&str, usize), b: &(&str, usize)) -> Ordering {
+    b.1.cmp(&a.1).then_with(|| compare_words(a.0, b.0))
+}
+
+/// An entry ordered by rank, so that the greatest entry is the most frequent one.
+#[derive(Debug, PartialEq, Eq)]
+struct Ranked<'a>((&'a str, usize));
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_by_count(&other.0, &self.0)
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// All entries of `word_count` in the given order.
+pub(crate) fn sorted_entries(
+    word_count: &HashMap<String, usize>,
+    order: Order,
+) -> Vec<(&str, usize)> {
+    let mut entries: Vec<_> = word_count
+        .iter()
+        .map(|(word, &count)| (word.as_str(), count))
+        .collect();
+    sort_entries(&mut entries, order);
+    entries
+}
+
+fn sort_entries(entries: &mut [(&str, usize)], order: Order) {
+    match order {
+        Order::Word => entries.sort_by(|a, b| compare_words(a.0, b.0)),
+        Order::Count => entries.sort_by(compare_by_count),
+    }
+}
+
+/// The `k` most frequent words, ties broken by word, written in the given order; selected on
+/// `num_workers` threads.
+pub(crate) fn top_entries(
+    word_count: &HashMap<String, usize>,
+    k: usize,
+    order: Order,
+    num_workers: usize,
+) -> io::Result<Vec<(&str, usize)>> {
+    let mut entries = top_k(word_count, k, num_workers)?;
+    sort_entries(&mut entries, order);
+    Ok(entries)
+}
+
+/// Selects the `k` most frequent words of the merged counts without sorting the whole map.
+///
+/// The entries are collected once and cut into one slice per worker; each worker keeps only the
+/// best `k` of its slice in a min-heap. Because the counts are already merged, every word's
+/// count is final and the union of the worker heaps is guaranteed to contain the global top `k`;
+/// the per-chunk counts of the counting workers could not give that guarantee.
+fn top_k(
+    word_count: &HashMap<String, usize>,
+    k: usize,
+    num_workers: usize,
+) -> io::Result<Vec<(&str, usize)>> {
+    if k == 0 {
+        return Ok(Vec::new());
+    }
+    let entries: Vec<_> = word_count
+        .iter()
+        .map(|(word, &count)| (word.as_str(), count))
+        .collect();
+    let shard_size = entries.len().div_ceil(num_workers.max(1)).max(1);
+    let heaps = thread::scope(|scope| -> io::Result<Vec<BinaryHeap<Reverse<Ranked>>>> {
+        let handles: Vec<_> = entries
+            .chunks(shard_size)
+            .map(|shard| scope.spawn(move || bounded_heap(shard.iter().copied(), k)))
+            .collect();
+        let mut heaps = Vec::with_capacity(handles.len());
+        for (i, handle) in handles.into_iter().enumerate() {
+            heaps.push(
+                handle
+                    .join()
+                    .map_err(|e| WordCountError::worker_panic(format!("Top-k thread {}", i), e))?,
+            );
+        }
+        Ok(heaps)
+    })?;
+
+    let merged: Vec<_> = heaps
+        .into_iter()
+        .flat_map(BinaryHeap::into_iter)
+        .map(|Reverse(Ranked(entry))| entry)
+        .collect();
+    Ok(bounded_heap(merged.into_iter(), k)
+        .into_iter()
+        .map(|Reverse(Ranked(entry))| entry)
+        .collect())
+}
+
+/// Keeps the best `k` entries seen; the root of the min-heap is the weakest one kept. The heap
+/// is sized by the entries scanned, as `k` comes from the user and may be far larger.
+fn bounded_heap<'a>(
+    entries: impl ExactSizeIterator<Item = (&'a str, usize)>,
+    k: usize,
+) -> BinaryHeap<Reverse<Ranked<'a>>> {
+    let mut heap = BinaryHeap::with_capacity(k.min(entries.len()).saturating_add(1));
+    for entry in entries {
+        let entry = Reverse(Ranked(entry));
+        if heap.len() < k {
+            heap.push(entry);
+        } else if let Some(mut weakest) = heap.peek_mut() {
+            if entry < *weakest {
+                *weakest = entry;
+            }
+        }
+    }
+    heap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counts;
+
+    /// Full sort and truncate: what `--top` must always agree with.
+    fn reference_top(word_count: &HashMap<String, usize>, k: usize) -> Vec<(&str, usize)> {
+        let mut entries = sorted_entries(word_count, Order::Count);
+        entries.truncate(k);
+        entries
+    }
+
+    mod test_sorted_entries {
+        use super::*;
+
+        #[test]
+        fn test_word_order_sorts_numbers_numerically() {
+            // Given
+            let word_count = counts(&[("b", 1), ("10", 1), ("9", 1), ("a", 1)]);
+
+            // When
+            let result = sorted_entries(&word_count, Order::Word);
+
+            // Then
+            assert_eq!(result, [("9", 1), ("10", 1), ("a", 1), ("b", 1)]);
+        }
+
+        #[test]
+        fn test_count_order_breaks_ties_by_word() {
+            // Given
+            let word_count = counts(&[("pear", 2), ("apple", 5), ("fig", 2), ("kiwi", 7)]);
+
+            // When
+            let result = sorted_entries(&word_count, Order::Count);
+
+            // Then
+            assert_eq!(result, [("kiwi", 7), ("apple", 5), ("fig", 2), ("pear", 2)]);
+        }
+    }
+
+    mod test_top_k {
+        use super::*;
+
+        #[test]
+        fn test_top_k_matches_full_sort_for_any_worker_count() {
+            // Given: many ties, so the tie-break decides which words make the cut.
+            let word_count: HashMap<String, usize> = (0..500)
+                .map(|i| (format!("word{}", i), (i * 7919) % 23))
+                .collect();
+
+            for k in [1, 5, 23, 100, 499, 500, 1000] {
+                for num_workers in [1, 2, 3, 8] {
+                    // When
+                    let mut result = top_k(&word_count, k, num_workers).unwrap();
+                    result.sort_by(compare_by_count);
+
+                    // Then
+                    assert_eq!(
+                        result,
+                        reference_top(&word_count, k),
+                        "Mismatch for k={} with {} workers",
+                        k,
+                        num_workers
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_top_k_breaks_ties_by_word() {
+            // Given
+            let word_count = counts(&[("c", 3), ("b", 3), ("a", 3), ("d", 4)]);
+
+            // When
+            let result = top_entries(&word_count, 3, Order::Count, 4).unwrap();
+
+            // Then
+            assert_eq!(result, [("d", 4), ("a", 3), ("b", 3)]);
+        }
+
+        #[test]
+        fn test_top_entries_in_word_order() {
+            // Given
+            let word_count = counts(&[("zebra", 9), ("ant", 1), ("moth", 5), ("bee", 7)]);
+
+            // When
+            let result = top_entries(&word_count, 3, Order::Word, 4).unwrap();
+
+            // Then
+            assert_eq!(result, [("bee", 7), ("moth", 5), ("zebra", 9)]);
+        }
+
+        #[test]
+        fn test_top_k_with_k_far_beyond_the_vocabulary() {
+            // Given
+            let word_count = counts(&[("a", 1), ("b", 3), ("c", 2), ("d", 2)]);
+
+            for k in [100_000_000_000, usize::MAX] {
+                // When
+                let result = top_entries(&word_count, k, Order::Count, 4).unwrap();
+
+                // Then
+                assert_eq!(result, [("b", 3), ("c", 2), ("d", 2), ("a", 1)], "k={}", k);
+            }
+        }
+
+        #[test]
+        fn test_top_k_with_zero_or_empty_input() {
+            // Given
+            let word_count = counts(&[("a", 1)]);
+
+            // Then
+            assert!(top_k(&word_count, 0, 4).unwrap().is_empty());
+            assert!(top_k(&HashMap::new(), 10, 4).unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+
+use crate::decompress::{detect_compression, Compression};
+use crate::encoding::detect_utf16;
+use crate::error::at_path;
+use crate::progress::info;
+
+/// Bytes inspected to tell text from binary files, as git and grep do.
+const BINARY_SNIFF_SIZE: usize = 8192;
+
+/// Which files of a directory input are counted, as selected with `--include`, `--exclude-glob`
+/// and `--gitignore`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WalkConfig {
+    /// Only count files matching one of these globs; every file when empty.
+    pub(crate) include: Vec<String>,
+    /// Skip files and directories matching one of these globs.
+    pub(crate) exclude: Vec<String>,
+    /// Skip what `.gitignore` files (and `.git/info/exclude`) ignore.
+    pub(crate) gitignore: bool,
+}
+
+impl WalkConfig {
+    /// Checks the globs up front, so a typo is a usage error rather than a failure halfway
+    /// through a run.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        self.overrides(Path::new("")).map(|_| ())
+    }
+
+    /// Globs follow `.gitignore` syntax relative to the walked directory: `*.txt` matches at
+    /// any depth, `docs/*.md` only below `docs`.
+    fn overrides(&self, root: &Path) -> Result<Override, String> {
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.include {
+            builder
+                .add(glob)
+                .map_err(|e| format!("invalid --include glob: {}", e))?;
+        }
+        for glob in &self.exclude {
+            builder
+                .add(&format!("!{}", glob))
+                .map_err(|e| format!("invalid --exclude-glob glob: {}", e))?;
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+}
+
+/// Replaces every directory among `inputs` by the files below it, in path order. Files and
+/// `-` are kept as given; the globs, ignore files and binary check only apply inside
+/// directories. Hidden files and directories are skipped, like `.git`.
+pub(crate) fn expand_inputs(inputs: &[PathBuf], walk: &WalkConfig) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        if input.is_dir() {
+            walk_dir(input, walk, &mut files)?;
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn walk_dir(dir: &Path, walk: &WalkConfig, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let overrides = walk
+        .overrides(dir)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .hidden(true)
+        .git_ignore(walk.gitignore)
+        .git_exclude(walk.gitignore)
+        .require_git(false)
+        .overrides(overrides)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut skipped = 0;
+    for entry in walker {
+        let entry = entry.map_err(|e| match e.into_io_error() {
+            Some(e) => e,
+            None => io::Error::other(format!("cannot walk {}", dir.display())),
+        })?;
+        if !entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+        {
+            continue;
+        }
+        if is_binary(entry.path()).map_err(at_path(entry.path()))? {
+            skipped += 1;
+            continue;
+        }
+        files.push(entry.into_path());
+    }
+    if skipped > 0 {
+        info!("Skipped {} binary files in {}", skipped, dir.display());
+    }
+    Ok(())
+}
+
+/// A file is binary when its first bytes contain a NUL byte, unless it is compressed input
+/// the counter can decode or UTF-16 text with a byte order mark, whose ASCII characters all
+/// have a NUL half.
+fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_SIZE);
+    File::open(path)?
+        .take(BINARY_SNIFF_SIZE as u64)
+        .read_to_end(&mut head)?;
+    Ok(detect_compression(&head) == Compression::None
+        && detect_utf16(&head).is_none()
+        && head.contains(&0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    /// A small tree with nested, hidden, ignored, binary and UTF-16 files.
+    fn tree() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for (path, content) in [
+            ("a.txt", &b"alpha\n"[..]),
+            ("b.md", b"beta\n"),
+            ("sub/c.txt", b"gamma\n"),
+            ("sub/deep/d.txt", b"delta\n"),
+            ("target/e.txt", b"epsilon\n"),
+            (".hidden/f.txt", b"zeta\n"),
+            ("image.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            ("sub/h.txt", b"\xff\xfet\0h\0e\0t\0a\0\n\0"),
+            (".gitignore", b"target/\n*.md\n"),
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(root.join("sub/g.txt.gz")).unwrap(),
+            Default::default(),
+        );
+        encoder.write_all(b"eta\n").unwrap();
+        encoder.finish().unwrap();
+        temp_dir
+    }
+
+    fn relative(root: &Path, files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| {
+                file.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    mod test_expand_inputs {
+        use super::*;
+
+        #[test]
+        fn test_expand_inputs_walks_directories_in_order() {
+            // Given
+            let temp_dir = tree();
+            let root = temp_dir.path();
+
+            // When
+            let files = expand_inputs(&[root.to_path_buf()], &WalkConfig::default()).unwrap();
+
+            // Then: hidden and binary files are skipped, compressed and UTF-16 files are kept.
+            assert_eq!(
+                relative(root, &files),
+                [
+                    "a.txt",
+                    "b.md",
+                    "sub/c.txt",
+                    "sub/deep/d.txt",
+                    "sub/g.txt.gz",
+                    "sub/h.txt",
+                    "target/e.txt"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_expand_inputs_respects_gitignore_when_asked() {
+            // Given
+            let temp_dir = tree();
+            let walk = WalkConfig {
+                gitignore: true,
+                ..WalkConfig::default()
+            };
+
+            // When
+            let files = expand_inputs(&[temp_dir.path().to_path_buf()], &walk).unwrap();
+
+            // Then
+            assert_eq!(
+                relative(temp_dir.path(), &files),
+                [
+                    "a.txt",
+                    "sub/c.txt",
+                    "sub/deep/d.txt",
+                    "sub/g.txt.gz",
+                    "sub/h.txt"
+                ]
+            );
+        }
+
+        #[test]
+        fn test_expand_inputs_applies_include_and_exclude_globs() {
+            // Given
+            let temp_dir = tree();
+            let walk = WalkConfig {
+                include: vec!["*.txt".to_string()],
+                exclude: vec!["deep".to_string(), "target".to_string()],
+                gitignore: false,
+            };
+
+            // When
+            let files = expand_inputs(&[temp_dir.path().to_path_buf()], &walk).unwrap();
+
+            // Then
+            assert_eq!(
+                relative(temp_dir.path(), &files),
+                ["a.txt", "sub/c.txt", "sub/h.txt"]
+            );
+        }
+
+        #[test]
+        fn test_expand_inputs_keeps_files_and_stdin_as_given() {
+            // Given
+            let temp_dir = tree();
+            let png = temp_dir.path().join("image.png");
+            let walk = WalkConfig {
+                include: vec!["*.txt".to_string()],
+                ..WalkConfig::default()
+            };
+
+            // When
+            let files = expand_inputs(&[PathBuf::from("-"), png.clone()], &walk).unwrap();
+
+            // Then
+            assert_eq!(files, [PathBuf::from("-"), png]);
+        }
+
+        #[test]
+        fn test_validate_rejects_bad_globs() {
+            // Given
+            let walk = WalkConfig {
+                include: vec!["a[".to_string()],
+                ..WalkConfig::default()
+            };
+
+            // Then
+            assert!(walk.validate().is_err());
+            assert!(WalkConfig::default().validate().is_ok());
+        }
+    }
+}